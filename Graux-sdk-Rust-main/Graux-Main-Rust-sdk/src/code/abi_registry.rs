@@ -0,0 +1,528 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::RwLock;
+
+use ethers_core::abi::ethabi::AbiError;
+use ethers_core::abi::{Abi, Event, Function, Param, ParamType, RawLog, Token};
+use ethers_core::types::{Address, Bytes, H256, U256};
+use serde_json::Value;
+
+use crate::error::GrauxResult;
+use crate::simulation::SimulateExecutionResponse;
+use crate::trace::{CallFrame, TraceResult};
+
+/// Selector of the built-in `Error(string)` revert.
+const ERROR_SELECTOR: [u8; 4] = [0x08, 0xc3, 0x79, 0xa0];
+/// Selector of the built-in `Panic(uint256)` revert.
+const PANIC_SELECTOR: [u8; 4] = [0x4e, 0x48, 0x7b, 0x71];
+
+/// One decoded parameter or return value.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DecodedValue {
+    /// Parameter name from the ABI; empty for unnamed parameters.
+    pub name: String,
+    pub kind: ParamType,
+    pub value: Token,
+}
+
+/// A call decoded against a registered ABI.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DecodedFunction {
+    pub name: String,
+    /// Canonical signature, e.g. `transfer(address,uint256)`.
+    pub signature: String,
+    pub inputs: Vec<DecodedValue>,
+    /// Empty when there was no output to decode, e.g. because the call reverted.
+    pub outputs: Vec<DecodedValue>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DecodedEvent {
+    pub name: String,
+    pub signature: String,
+    pub params: Vec<DecodedValue>,
+}
+
+/// Decoded revert data of a failed call.
+#[derive(Debug, Clone, PartialEq)]
+pub enum DecodedRevert {
+    /// `require(cond, "reason")` / `revert("reason")`.
+    Error(String),
+    /// A compiler-inserted check failed, e.g. overflow or division by zero.
+    Panic { code: U256, description: Option<&'static str> },
+    /// A user-defined `error` found in a registered ABI.
+    Custom {
+        name: String,
+        signature: String,
+        params: Vec<DecodedValue>,
+    },
+    /// Revert data that matched nothing; empty for a bare `revert()`.
+    Unknown(Bytes),
+}
+
+impl fmt::Display for DecodedRevert {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodedRevert::Error(reason) => write!(f, "{reason}"),
+            DecodedRevert::Panic {
+                code,
+                description: Some(description),
+            } => write!(f, "panic 0x{code:02x}: {description}"),
+            DecodedRevert::Panic { code, description: None } => write!(f, "panic 0x{code:02x}"),
+            DecodedRevert::Custom { name, params, .. } => {
+                let params: Vec<String> = params.iter().map(|param| param.value.to_string()).collect();
+                write!(f, "{name}({})", params.join(", "))
+            }
+            DecodedRevert::Unknown(data) if data.is_empty() => write!(f, "reverted without data"),
+            DecodedRevert::Unknown(data) => write!(f, "unknown revert data {data}"),
+        }
+    }
+}
+
+/// An `eth_call` result together with what the registry could decode of it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CallOutput {
+    /// Return data, or the revert data if the call reverted.
+    pub output: Bytes,
+    /// The called function, if its ABI is registered. Its `outputs` are empty on a revert.
+    pub function: Option<DecodedFunction>,
+    /// Why the call reverted; `None` if it succeeded.
+    pub revert: Option<DecodedRevert>,
+}
+
+impl CallOutput {
+    pub fn reverted(&self) -> bool {
+        self.revert.is_some()
+    }
+}
+
+/// Meaning of the `Panic(uint256)` codes emitted by Solidity.
+fn panic_description(code: U256) -> Option<&'static str> {
+    Some(match code.low_u64() {
+        0x00 => "generic compiler panic",
+        0x01 => "assertion failed",
+        0x11 => "arithmetic overflow or underflow",
+        0x12 => "division or modulo by zero",
+        0x21 => "invalid enum value",
+        0x22 => "invalid storage byte array encoding",
+        0x31 => "pop on empty array",
+        0x32 => "array index out of bounds",
+        0x41 => "out of memory",
+        0x51 => "call to uninitialized function pointer",
+        _ => return None,
+    })
+}
+
+#[derive(Debug, Default)]
+struct Entries {
+    functions: HashMap<(Option<Address>, [u8; 4]), Function>,
+    events: HashMap<(Option<Address>, H256), Event>,
+    errors: HashMap<(Option<Address>, [u8; 4]), AbiError>,
+}
+
+/// ABIs used to decode calls, logs and revert data.
+///
+/// An ABI registered for an address is only used for that contract; a global one (no
+/// address) for any contract, after the address-specific ones. Shared between namespaces
+/// through an `Arc`.
+#[derive(Debug, Default)]
+pub struct AbiRegistry {
+    entries: RwLock<Entries>,
+}
+
+impl AbiRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers a JSON ABI, given either as the ABI array itself or as a compiler artifact
+    /// with an `abi` field (Hardhat, Foundry).
+    pub fn load_json(&self, json: &str, address: Option<Address>) -> GrauxResult<()> {
+        let mut value: Value = serde_json::from_str(json)?;
+        if let Some(abi) = value.get_mut("abi") {
+            value = abi.take();
+        }
+        let abi: Abi = serde_json::from_value(value)?;
+        self.add_abi(&abi, address);
+        Ok(())
+    }
+
+    pub fn add_abi(&self, abi: &Abi, address: Option<Address>) {
+        let mut entries = self.entries.write().expect("ABI registry lock poisoned");
+        for function in abi.functions() {
+            entries
+                .functions
+                .insert((address, function.short_signature()), function.clone());
+        }
+        for event in abi.events().filter(|event| !event.anonymous) {
+            entries.events.insert((address, event.signature()), event.clone());
+        }
+        for error in abi.errors() {
+            let selector = error_selector(error);
+            entries.errors.insert((address, selector), error.clone());
+        }
+    }
+
+    /// Decodes calldata sent to `to`, and `output` if given.
+    pub fn decode_call(&self, to: Option<Address>, input: &[u8], output: Option<&[u8]>) -> Option<DecodedFunction> {
+        let selector: [u8; 4] = input.get(..4)?.try_into().ok()?;
+        let entries = self.entries.read().expect("ABI registry lock poisoned");
+        let function = lookup(&entries.functions, to, selector)?;
+
+        let inputs = function.decode_input(&input[4..]).ok()?;
+        let outputs = match output {
+            Some(output) if !output.is_empty() => function.decode_output(output).unwrap_or_default(),
+            _ => Vec::new(),
+        };
+        Some(DecodedFunction {
+            name: function.name.clone(),
+            signature: signature(&function.name, &function.inputs),
+            inputs: named(&function.inputs, inputs),
+            outputs: named(&function.outputs, outputs),
+        })
+    }
+
+    /// Decodes a log emitted by `address`.
+    pub fn decode_log(&self, address: Address, topics: &[H256], data: &[u8]) -> Option<DecodedEvent> {
+        let topic0 = *topics.first()?;
+        let entries = self.entries.read().expect("ABI registry lock poisoned");
+        let event = lookup(&entries.events, Some(address), topic0)?;
+
+        let log = event
+            .parse_log(RawLog {
+                topics: topics.to_vec(),
+                data: data.to_vec(),
+            })
+            .ok()?;
+        let params = log
+            .params
+            .into_iter()
+            .zip(&event.inputs)
+            .map(|(param, input)| DecodedValue {
+                name: param.name,
+                kind: input.kind.clone(),
+                value: param.value,
+            })
+            .collect();
+        Some(DecodedEvent {
+            name: event.name.clone(),
+            signature: format!(
+                "{}({})",
+                event.name,
+                event.inputs.iter().map(|input| input.kind.to_string()).collect::<Vec<_>>().join(",")
+            ),
+            params,
+        })
+    }
+
+    /// Decodes the revert data of a call to `address`: `Error(string)`, `Panic(uint256)` or
+    /// a custom error from a registered ABI.
+    pub fn decode_revert(&self, address: Option<Address>, data: &[u8]) -> DecodedRevert {
+        let unknown = || DecodedRevert::Unknown(Bytes::from(data.to_vec()));
+        let Some(selector) = data.get(..4).and_then(|s| <[u8; 4]>::try_from(s).ok()) else {
+            return unknown();
+        };
+        let body = &data[4..];
+
+        match selector {
+            ERROR_SELECTOR => match ethers_core::abi::decode(&[ParamType::String], body) {
+                Ok(mut tokens) => match tokens.pop() {
+                    Some(Token::String(reason)) => DecodedRevert::Error(reason),
+                    _ => unknown(),
+                },
+                Err(_) => unknown(),
+            },
+            PANIC_SELECTOR => match ethers_core::abi::decode(&[ParamType::Uint(256)], body) {
+                Ok(mut tokens) => match tokens.pop().and_then(Token::into_uint) {
+                    Some(code) => DecodedRevert::Panic {
+                        code,
+                        description: panic_description(code),
+                    },
+                    None => unknown(),
+                },
+                Err(_) => unknown(),
+            },
+            _ => {
+                let entries = self.entries.read().expect("ABI registry lock poisoned");
+                let Some(error) = lookup(&entries.errors, address, selector) else {
+                    return unknown();
+                };
+                match error.decode(body) {
+                    Ok(tokens) => DecodedRevert::Custom {
+                        name: error.name.clone(),
+                        signature: signature(&error.name, &error.inputs),
+                        params: named(&error.inputs, tokens),
+                    },
+                    Err(_) => unknown(),
+                }
+            }
+        }
+    }
+
+    /// Decodes a call to `to` with `input` that returned `Ok(output)` or reverted with
+    /// `Err(revert data)`. The raw bytes are kept either way.
+    pub fn decode_call_output(&self, to: Option<Address>, input: &[u8], result: Result<Bytes, Bytes>) -> CallOutput {
+        match result {
+            Ok(output) => CallOutput {
+                function: self.decode_call(to, input, Some(&output)),
+                revert: None,
+                output,
+            },
+            Err(data) => CallOutput {
+                function: self.decode_call(to, input, None),
+                revert: Some(self.decode_revert(to, &data)),
+                output: data,
+            },
+        }
+    }
+
+    /// Fills in `decoded_call`, `decoded_revert` and log `decoded_event` throughout a
+    /// `callTracer` tree.
+    pub fn annotate_call_frame(&self, frame: &mut CallFrame) {
+        let output = frame.output.as_deref();
+        frame.decoded_call = self.decode_call(frame.to, &frame.input, output.filter(|_| frame.error.is_none()));
+        if frame.error.is_some() {
+            frame.decoded_revert = Some(self.decode_revert(frame.to, output.unwrap_or_default()));
+        }
+        for log in &mut frame.logs {
+            log.decoded_event = self.decode_log(log.address, &log.topics, &log.data);
+        }
+        for call in &mut frame.calls {
+            self.annotate_call_frame(call);
+        }
+    }
+
+    /// Annotates the call tree of a `callTracer` result; other results are left as they are.
+    pub fn annotate_trace(&self, result: &mut TraceResult) {
+        if let TraceResult::Call(frame) = result {
+            self.annotate_call_frame(frame);
+        }
+    }
+
+    /// Fills in `decoded_call`, `decoded_revert` and `decoded_event` of a simulation.
+    pub fn annotate_simulation(&self, response: &mut SimulateExecutionResponse) {
+        for call in &mut response.calls {
+            self.annotate_call_frame(call);
+        }
+        for log in &mut response.logs {
+            log.decoded_event = self.decode_log(log.address, &log.topics, &log.data);
+        }
+    }
+}
+
+/// Address-specific entry first, then the global one.
+fn lookup<K: Copy + Eq + std::hash::Hash, V>(
+    map: &HashMap<(Option<Address>, K), V>,
+    address: Option<Address>,
+    key: K,
+) -> Option<&V> {
+    address
+        .and_then(|address| map.get(&(Some(address), key)))
+        .or_else(|| map.get(&(None, key)))
+}
+
+fn error_selector(error: &AbiError) -> [u8; 4] {
+    let hash = error.signature();
+    hash.as_bytes()[..4].try_into().expect("slice of length 4")
+}
+
+fn signature(name: &str, params: &[Param]) -> String {
+    let types: Vec<String> = params.iter().map(|param| param.kind.to_string()).collect();
+    format!("{name}({})", types.join(","))
+}
+
+fn named(params: &[Param], tokens: Vec<Token>) -> Vec<DecodedValue> {
+    params
+        .iter()
+        .zip(tokens)
+        .map(|(param, value)| DecodedValue {
+            name: param.name.clone(),
+            kind: param.kind.clone(),
+            value,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use ethers_core::abi::encode;
+    use ethers_core::utils::keccak256;
+
+    use super::*;
+
+    const TOKEN: Address = Address::repeat_byte(0x70);
+    const HOLDER: Address = Address::repeat_byte(0x01);
+
+    const ERC20: &str = r#"[
+        {"type": "function", "name": "transfer", "stateMutability": "nonpayable",
+         "inputs": [{"name": "to", "type": "address"}, {"name": "amount", "type": "uint256"}],
+         "outputs": [{"name": "", "type": "bool"}]},
+        {"type": "event", "name": "Transfer", "anonymous": false,
+         "inputs": [{"name": "from", "type": "address", "indexed": true},
+                    {"name": "to", "type": "address", "indexed": true},
+                    {"name": "value", "type": "uint256", "indexed": false}]},
+        {"type": "error", "name": "InsufficientBalance",
+         "inputs": [{"name": "available", "type": "uint256"}, {"name": "required", "type": "uint256"}]}
+    ]"#;
+
+    fn registry(address: Option<Address>) -> AbiRegistry {
+        let registry = AbiRegistry::new();
+        registry.load_json(ERC20, address).unwrap();
+        registry
+    }
+
+    fn with_selector(signature: &str, tokens: &[Token]) -> Vec<u8> {
+        let mut data = keccak256(signature)[..4].to_vec();
+        data.extend(encode(tokens));
+        data
+    }
+
+    #[test]
+    fn decodes_call_inputs_and_outputs() {
+        let input = with_selector("transfer(address,uint256)", &[Token::Address(HOLDER), Token::Uint(7.into())]);
+        let output = encode(&[Token::Bool(true)]);
+        let call = registry(None).decode_call(Some(TOKEN), &input, Some(&output)).unwrap();
+
+        assert_eq!(call.signature, "transfer(address,uint256)");
+        assert_eq!(call.inputs[0].name, "to");
+        assert_eq!(call.inputs[0].value, Token::Address(HOLDER));
+        assert_eq!(call.inputs[1].value, Token::Uint(7.into()));
+        assert_eq!(call.outputs[0].value, Token::Bool(true));
+
+        assert!(registry(None).decode_call(Some(TOKEN), &input, None).unwrap().outputs.is_empty());
+        assert!(registry(None).decode_call(Some(TOKEN), &input[..3], None).is_none());
+    }
+
+    #[test]
+    fn call_outputs_keep_the_raw_bytes() {
+        let input = with_selector("transfer(address,uint256)", &[Token::Address(HOLDER), Token::Uint(7.into())]);
+        let output = Bytes::from(encode(&[Token::Bool(true)]));
+
+        let unknown = AbiRegistry::new().decode_call_output(Some(TOKEN), &input, Ok(output.clone()));
+        assert_eq!(unknown.output, output);
+        assert!(unknown.function.is_none());
+        assert!(!unknown.reverted());
+
+        let data = Bytes::from(with_selector(
+            "InsufficientBalance(uint256,uint256)",
+            &[Token::Uint(1.into()), Token::Uint(2.into())],
+        ));
+        let reverted = registry(None).decode_call_output(Some(TOKEN), &input, Err(data.clone()));
+        assert_eq!(reverted.output, data);
+        assert_eq!(reverted.function.unwrap().outputs, Vec::new());
+        assert!(matches!(
+            reverted.revert,
+            Some(DecodedRevert::Custom { ref name, .. }) if name == "InsufficientBalance"
+        ));
+    }
+
+    #[test]
+    fn address_specific_abis_only_apply_to_their_contract() {
+        let registry = registry(Some(TOKEN));
+        let input = with_selector("transfer(address,uint256)", &[Token::Address(HOLDER), Token::Uint(7.into())]);
+        assert!(registry.decode_call(Some(TOKEN), &input, None).is_some());
+        assert!(registry.decode_call(Some(HOLDER), &input, None).is_none());
+        assert!(registry.decode_call(None, &input, None).is_none());
+    }
+
+    #[test]
+    fn loads_compiler_artifacts() {
+        let registry = AbiRegistry::new();
+        registry.load_json(&format!(r#"{{"contractName": "Token", "abi": {ERC20}}}"#), None).unwrap();
+        let input = with_selector("transfer(address,uint256)", &[Token::Address(HOLDER), Token::Uint(1.into())]);
+        assert!(registry.decode_call(Some(TOKEN), &input, None).is_some());
+        assert!(registry.load_json("{\"abi\": 5}", None).is_err());
+    }
+
+    #[test]
+    fn decodes_events_with_indexed_params() {
+        let topics = [
+            H256(keccak256("Transfer(address,address,uint256)")),
+            H256::from(HOLDER),
+            H256::from(TOKEN),
+        ];
+        let data = encode(&[Token::Uint(9.into())]);
+        let event = registry(None).decode_log(TOKEN, &topics, &data).unwrap();
+
+        assert_eq!(event.signature, "Transfer(address,address,uint256)");
+        let params: Vec<(&str, &Token)> = event.params.iter().map(|p| (p.name.as_str(), &p.value)).collect();
+        assert_eq!(
+            params,
+            vec![
+                ("from", &Token::Address(HOLDER)),
+                ("to", &Token::Address(TOKEN)),
+                ("value", &Token::Uint(9.into()))
+            ]
+        );
+        assert!(registry(None).decode_log(TOKEN, &topics[..1], &data).is_none());
+    }
+
+    #[test]
+    fn decodes_builtin_reverts() {
+        let registry = AbiRegistry::new();
+        let error = with_selector("Error(string)", &[Token::String("not owner".into())]);
+        assert_eq!(registry.decode_revert(None, &error), DecodedRevert::Error("not owner".into()));
+
+        let panic = registry.decode_revert(None, &with_selector("Panic(uint256)", &[Token::Uint(0x11.into())]));
+        assert_eq!(
+            panic,
+            DecodedRevert::Panic {
+                code: 0x11.into(),
+                description: Some("arithmetic overflow or underflow")
+            }
+        );
+        assert_eq!(panic.to_string(), "panic 0x11: arithmetic overflow or underflow");
+        assert_eq!(registry.decode_revert(None, &[]).to_string(), "reverted without data");
+        assert_eq!(
+            registry.decode_revert(None, &ERROR_SELECTOR),
+            DecodedRevert::Unknown(Bytes::from(ERROR_SELECTOR.to_vec()))
+        );
+    }
+
+    #[test]
+    fn decodes_custom_errors() {
+        let data = with_selector(
+            "InsufficientBalance(uint256,uint256)",
+            &[Token::Uint(1.into()), Token::Uint(2.into())],
+        );
+        let revert = registry(Some(TOKEN)).decode_revert(Some(TOKEN), &data);
+        assert_eq!(revert.to_string(), "InsufficientBalance(1, 2)");
+        assert!(matches!(
+            revert,
+            DecodedRevert::Custom { ref signature, .. } if signature == "InsufficientBalance(uint256,uint256)"
+        ));
+        assert!(matches!(registry(Some(TOKEN)).decode_revert(Some(HOLDER), &data), DecodedRevert::Unknown(_)));
+    }
+
+    #[test]
+    fn annotates_reverted_frames_and_their_logs() {
+        let registry = registry(None);
+        let input = with_selector("transfer(address,uint256)", &[Token::Address(HOLDER), Token::Uint(7.into())]);
+        let revert = with_selector(
+            "InsufficientBalance(uint256,uint256)",
+            &[Token::Uint(1.into()), Token::Uint(7.into())],
+        );
+        let transfer = [
+            H256(keccak256("Transfer(address,address,uint256)")),
+            H256::from(HOLDER),
+            H256::from(TOKEN),
+        ];
+        let mut frame: CallFrame = serde_json::from_value(serde_json::json!({
+            "type": "CALL", "from": HOLDER, "to": TOKEN, "input": Bytes::from(input.clone()),
+            "calls": [{
+                "type": "CALL", "from": TOKEN, "to": TOKEN, "input": Bytes::from(input),
+                "output": Bytes::from(revert), "error": "execution reverted",
+                "logs": [{"address": TOKEN, "topics": transfer, "data": Bytes::from(encode(&[Token::Uint(7.into())]))}]
+            }]
+        }))
+        .unwrap();
+        registry.annotate_call_frame(&mut frame);
+
+        assert_eq!(frame.decoded_call.as_ref().unwrap().name, "transfer");
+        assert!(frame.decoded_revert.is_none());
+        let inner = &frame.calls[0];
+        assert!(inner.decoded_call.as_ref().unwrap().outputs.is_empty());
+        assert_eq!(inner.decoded_revert.as_ref().unwrap().to_string(), "InsufficientBalance(1, 7)");
+        assert_eq!(inner.logs[0].decoded_event.as_ref().unwrap().name, "Transfer");
+    }
+}
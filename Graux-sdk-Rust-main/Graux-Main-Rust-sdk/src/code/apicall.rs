@@ -1,11 +1,113 @@
 mod graux_provider;
-mod graux_websocket_provider;
+pub mod graux_websocket_provider;
+pub mod abi_registry;
+pub mod backfill;
+pub mod batch;
+pub mod call_tree;
+pub mod deployer;
+pub mod ens;
+pub mod error;
+pub mod fees;
+pub mod logs;
+#[cfg(test)]
+mod mock_http;
+#[cfg(test)]
+mod mock_ws;
+pub mod network;
+pub mod nonce;
+pub mod private_tx;
+pub mod reorg;
+pub mod replacement;
+pub mod retry;
+pub mod signatures;
+pub mod signer;
+pub mod simulation;
+pub mod state_diff;
+pub mod subscriptions;
+pub mod trace;
+
+use std::fmt;
+use std::path::Path;
+
+use serde::Deserialize;
+
+use crate::batch::BatchConfig;
+use crate::error::{GrauxError, GrauxResult};
+use crate::network::{lookup_network, GrauxNetwork};
+
+const MIN_REQUEST_TIMEOUT: u32 = 100;
+const MAX_REQUEST_TIMEOUT: u32 = 300_000;
+const MAX_MAX_RETRIES: u32 = 20;
+
+/// Optional overrides accepted by `GrauxConfig::new`.
+#[derive(Clone, Default)]
+pub struct GrauxSettings {
+    pub api_key: Option<String>,
+    pub network: Option<GrauxNetwork>,
+    pub max_retries: Option<u32>,
+    pub batch_requests: Option<bool>,
+    /// Batching limits; only used when `batch_requests` is enabled.
+    pub batch_config: Option<BatchConfig>,
+    pub url: Option<String>,
+    pub auth_token: Option<String>,
+    pub request_timeout: Option<u32>,
+}
+
+impl fmt::Debug for GrauxSettings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("GrauxSettings")
+            .field("api_key", &redact(&self.api_key))
+            .field("network", &self.network)
+            .field("max_retries", &self.max_retries)
+            .field("batch_requests", &self.batch_requests)
+            .field("batch_config", &self.batch_config)
+            .field("url", &self.url)
+            .field("auth_token", &redact(&self.auth_token))
+            .field("request_timeout", &self.request_timeout)
+            .finish()
+    }
+}
+
+/// Settings as they appear in a TOML or JSON config file.
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct FileSettings {
+    api_key: Option<String>,
+    network: Option<String>,
+    max_retries: Option<u32>,
+    batch_requests: Option<bool>,
+    url: Option<String>,
+    auth_token: Option<String>,
+    request_timeout: Option<u32>,
+}
+
+impl fmt::Debug for FileSettings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FileSettings")
+            .field("api_key", &redact(&self.api_key))
+            .field("network", &self.network)
+            .field("max_retries", &self.max_retries)
+            .field("batch_requests", &self.batch_requests)
+            .field("url", &self.url)
+            .field("auth_token", &redact(&self.auth_token))
+            .field("request_timeout", &self.request_timeout)
+            .finish()
+    }
+}
+
+/// Shows whether a secret is set without printing it.
+fn redact(secret: &Option<String>) -> Option<&'static str> {
+    secret.as_ref().map(|_| "<redacted>")
+}
 
 pub struct GrauxConfig {
-    api_key: String,
-    network: Network,
+    /// `None` only when a custom `url` is set and no key was given; the URL then carries
+    /// its own credentials.
+    api_key: Option<String>,
+    network: GrauxNetwork,
     max_retries: u32,
     batch_requests: bool,
+    batch_config: Option<BatchConfig>,
     url: Option<String>,
     auth_token: Option<String>,
     request_timeout: u32,
@@ -14,14 +116,25 @@ pub struct GrauxConfig {
 }
 
 impl GrauxConfig {
-    pub fn new(config: Option<GrauxSettings>) -> Self {
-        let api_key = config.map_or_else(|| DEFAULT_GRAUX_API_KEY.to_string(), |c| c.api_key.unwrap_or_else(|| DEFAULT_GRAUX_API_KEY.to_string()));
-        let network = config.map_or(DEFAULT_NETWORK, |c| c.network.unwrap_or(DEFAULT_NETWORK));
-        let max_retries = config.map_or(DEFAULT_MAX_RETRIES, |c| c.max_retries.unwrap_or(DEFAULT_MAX_RETRIES));
-        let batch_requests = config.map_or(false, |c| c.batch_requests.unwrap_or(false));
-        let url = config.and_then(|c| c.url);
-        let auth_token = config.and_then(|c| c.auth_token);
-        let request_timeout = config.map_or(DEFAULT_REQUEST_TIMEOUT, |c| c.request_timeout.unwrap_or(DEFAULT_REQUEST_TIMEOUT));
+    /// Validates `config` like `GrauxConfigBuilder::build`; an API key is required unless a
+    /// custom `url` is set.
+    pub fn new(config: Option<GrauxSettings>) -> GrauxResult<Self> {
+        GrauxConfigBuilder {
+            settings: config.unwrap_or_default(),
+        }
+        .build()
+    }
+
+    /// Settings already checked by `GrauxConfigBuilder::build`.
+    fn from_validated(config: GrauxSettings) -> Self {
+        let api_key = config.api_key;
+        let network = config.network.unwrap_or_default();
+        let max_retries = config.max_retries.unwrap_or(DEFAULT_MAX_RETRIES);
+        let batch_requests = config.batch_requests.unwrap_or(false);
+        let batch_config = config.batch_config;
+        let url = config.url;
+        let auth_token = config.auth_token;
+        let request_timeout = config.request_timeout.unwrap_or(DEFAULT_REQUEST_TIMEOUT);
         let base_graux_provider = None;
         let base_graux_wss_provider = None;
         
@@ -30,6 +143,7 @@ impl GrauxConfig {
             network,
             max_retries,
             batch_requests,
+            batch_config,
             url,
             auth_token,
             request_timeout,
@@ -38,40 +152,306 @@ impl GrauxConfig {
         }
     }
 
-    fn get_request_url(&self, api_type: GrauxApiType) -> String {
+    /// Starts a validating builder.
+    pub fn builder() -> GrauxConfigBuilder {
+        GrauxConfigBuilder::default()
+    }
+
+    /// Reads `GRAUX_API_KEY`, `GRAUX_NETWORK`, `GRAUX_URL` and `GRAUX_AUTH_TOKEN`.
+    pub fn from_env() -> GrauxResult<Self> {
+        let mut builder = GrauxConfig::builder();
+        if let Ok(api_key) = std::env::var("GRAUX_API_KEY") {
+            builder = builder.api_key(api_key);
+        }
+        if let Ok(network) = std::env::var("GRAUX_NETWORK") {
+            builder = builder.network(parse_network(&network)?);
+        }
+        if let Ok(url) = std::env::var("GRAUX_URL") {
+            builder = builder.url(url);
+        }
+        if let Ok(auth_token) = std::env::var("GRAUX_AUTH_TOKEN") {
+            builder = builder.auth_token(auth_token);
+        }
+        builder.build()
+    }
+
+    /// Loads settings from a `.toml` or `.json` file, picked by extension.
+    pub fn from_file(path: impl AsRef<Path>) -> GrauxResult<Self> {
+        let path = path.as_ref();
+        let contents = std::fs::read_to_string(path)
+            .map_err(|e| GrauxError::Config(format!("cannot read {}: {e}", path.display())))?;
+        let settings: FileSettings = match path.extension().and_then(|ext| ext.to_str()) {
+            Some("toml") => toml::from_str(&contents)
+                .map_err(|e| GrauxError::Config(format!("invalid TOML in {}: {e}", path.display())))?,
+            Some("json") => serde_json::from_str(&contents)
+                .map_err(|e| GrauxError::Config(format!("invalid JSON in {}: {e}", path.display())))?,
+            _ => {
+                return Err(GrauxError::Config(format!(
+                    "unsupported config file format: {}",
+                    path.display()
+                )))
+            }
+        };
+
+        let mut builder = GrauxConfig::builder();
+        if let Some(network) = settings.network {
+            builder = builder.network(parse_network(&network)?);
+        }
+        builder.settings.api_key = settings.api_key;
+        builder.settings.max_retries = settings.max_retries;
+        builder.settings.batch_requests = settings.batch_requests;
+        builder.settings.url = settings.url;
+        builder.settings.auth_token = settings.auth_token;
+        builder.settings.request_timeout = settings.request_timeout;
+        builder.build()
+    }
+
+    /// The configured API key. Fails for a config built from a custom `url` alone, which has
+    /// no key to build the network endpoints with.
+    fn api_key(&self) -> GrauxResult<&str> {
+        self.api_key.as_deref().ok_or_else(|| {
+            GrauxError::InvalidApiKey("no API key configured; only the custom url can be used".to_owned())
+        })
+    }
+
+    fn get_request_url(&self, api_type: GrauxApiType) -> GrauxResult<String> {
         if let Some(url) = &self.url {
-            url.clone()
+            Ok(url.clone())
         } else if api_type == GrauxApiType::NFT {
-            get_graux_nft_http_url(self.network, &self.api_key)
+            self.network.nft_url(self.api_key()?).ok_or_else(|| {
+                GrauxError::InvalidNetwork(format!("{} does not support the NFT API", self.network.name))
+            })
         } else if api_type == GrauxApiType::WEBHOOK {
-            get_graux_webhook_http_url()
+            Ok(get_graux_webhook_http_url())
         } else {
-            get_graux_http_url(self.network, &self.api_key)
+            Ok(self.network.http_url(self.api_key()?))
         }
     }
 
-    pub async fn get_provider(&mut self) -> Box<dyn GrauxProvider> {
+    /// The shared provider, created on first use. With `batch_requests` enabled its calls are
+    /// coalesced into JSON-RPC batches.
+    pub async fn get_provider(&mut self) -> GrauxResult<Box<dyn GrauxProvider>> {
         if let Some(provider) = &self.base_graux_provider {
-            provider.clone()
-        } else {
-            let graux_provider = graux_provider::GrauxProvider::new(self).await;
-            self.base_graux_provider = Some(Box::new(graux_provider.clone()));
-            Box::new(graux_provider)
+            return Ok(provider.clone());
         }
+
+        let graux_provider: Box<dyn GrauxProvider> = if self.batch_requests {
+            Box::new(graux_provider::GrauxProvider::new_batched(self).await?)
+        } else {
+            Box::new(graux_provider::GrauxProvider::new(self).await)
+        };
+        self.base_graux_provider = Some(graux_provider.clone());
+        Ok(graux_provider)
     }
 
-    pub async fn get_websocket_provider(&mut self) -> Box<dyn GrauxWebSocketProvider> {
+    pub async fn get_websocket_provider(&mut self) -> GrauxResult<Box<dyn GrauxWebSocketProvider>> {
         if let Some(provider) = &self.base_graux_wss_provider {
-            provider.clone()
+            Ok(provider.clone())
         } else {
-            let graux_wss_provider = graux_websocket_provider::GrauxWebSocketProvider::new(self).await;
+            let graux_wss_provider = graux_websocket_provider::GrauxWebSocketProvider::new(self).await?;
             self.base_graux_wss_provider = Some(Box::new(graux_wss_provider.clone()));
-            Box::new(graux_wss_provider)
+            Ok(Box::new(graux_wss_provider))
         }
     }
 }
 
+/// Builder returned by `GrauxConfig::builder`.
+#[derive(Debug, Default)]
+pub struct GrauxConfigBuilder {
+    settings: GrauxSettings,
+}
+
+impl GrauxConfigBuilder {
+    pub fn api_key(mut self, api_key: impl Into<String>) -> Self {
+        self.settings.api_key = Some(api_key.into());
+        self
+    }
+
+    pub fn network(mut self, network: GrauxNetwork) -> Self {
+        self.settings.network = Some(network);
+        self
+    }
+
+    pub fn max_retries(mut self, max_retries: u32) -> Self {
+        self.settings.max_retries = Some(max_retries);
+        self
+    }
+
+    pub fn batch_requests(mut self, batch_requests: bool) -> Self {
+        self.settings.batch_requests = Some(batch_requests);
+        self
+    }
+
+    /// Enables request batching with the given limits.
+    pub fn batch_config(mut self, batch_config: BatchConfig) -> Self {
+        self.settings.batch_requests = Some(true);
+        self.settings.batch_config = Some(batch_config);
+        self
+    }
+
+    pub fn url(mut self, url: impl Into<String>) -> Self {
+        self.settings.url = Some(url.into());
+        self
+    }
+
+    pub fn auth_token(mut self, auth_token: impl Into<String>) -> Self {
+        self.settings.auth_token = Some(auth_token.into());
+        self
+    }
+
+    pub fn request_timeout(mut self, request_timeout: u32) -> Self {
+        self.settings.request_timeout = Some(request_timeout);
+        self
+    }
+
+    /// Validates the collected settings and produces a `GrauxConfig`.
+    ///
+    /// An API key is required unless a custom `url` is set, since the URL then carries
+    /// its own credentials.
+    pub fn build(self) -> GrauxResult<GrauxConfig> {
+        let settings = self.settings;
+
+        match settings.api_key.as_deref().map(str::trim) {
+            Some("") => return Err(GrauxError::InvalidApiKey("API key is empty".to_owned())),
+            Some(key) if key == DEFAULT_GRAUX_API_KEY => {
+                return Err(GrauxError::InvalidApiKey(
+                    "the shared demo API key is not allowed here".to_owned(),
+                ))
+            }
+            None if settings.url.is_none() => {
+                return Err(GrauxError::InvalidApiKey("API key is missing".to_owned()))
+            }
+            _ => {}
+        }
+
+        if let Some(url) = &settings.url {
+            if !(url.starts_with("http://") || url.starts_with("https://")) {
+                return Err(GrauxError::Config(format!("url must be http(s): {url}")));
+            }
+        }
+
+        if let Some(timeout) = settings.request_timeout {
+            if !(MIN_REQUEST_TIMEOUT..=MAX_REQUEST_TIMEOUT).contains(&timeout) {
+                return Err(GrauxError::Config(format!(
+                    "request_timeout must be between {MIN_REQUEST_TIMEOUT} and {MAX_REQUEST_TIMEOUT} ms, got {timeout}"
+                )));
+            }
+        }
+
+        if let Some(batch) = &settings.batch_config {
+            if batch.max_batch_size == 0 || batch.max_payload_bytes == 0 {
+                return Err(GrauxError::Config(
+                    "batch size and payload limits must be non-zero".to_owned(),
+                ));
+            }
+        }
+
+        if let Some(retries) = settings.max_retries {
+            if retries > MAX_MAX_RETRIES {
+                return Err(GrauxError::Config(format!(
+                    "max_retries must be at most {MAX_MAX_RETRIES}, got {retries}"
+                )));
+            }
+        }
+
+        Ok(GrauxConfig::from_validated(settings))
+    }
+}
+
+/// Maps a network name from the environment or a config file to a registered network.
+fn parse_network(name: &str) -> GrauxResult<GrauxNetwork> {
+    lookup_network(name)
+}
+
 Please note that the GrauxProvider and GrauxWebSocketProvider structs and their implementations 
 should be defined in separate files (graux_provider.rs and graux_websocket_provider.rs respectively).
 The helper functions like get_graux_nft_http_url, get_graux_webhook_http_url, and 
 get_graux_http_url should be implemented separately according to requirements.
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn build_rejects_missing_empty_and_demo_keys() {
+        assert!(matches!(GrauxConfig::builder().build(), Err(GrauxError::InvalidApiKey(_))));
+        assert!(matches!(
+            GrauxConfig::builder().api_key("  ").build(),
+            Err(GrauxError::InvalidApiKey(_))
+        ));
+        assert!(matches!(
+            GrauxConfig::builder().api_key(DEFAULT_GRAUX_API_KEY).build(),
+            Err(GrauxError::InvalidApiKey(_))
+        ));
+    }
+
+    #[test]
+    fn new_validates_like_the_builder() {
+        assert!(matches!(GrauxConfig::new(None), Err(GrauxError::InvalidApiKey(_))));
+        let settings = GrauxSettings {
+            api_key: Some(DEFAULT_GRAUX_API_KEY.to_owned()),
+            ..GrauxSettings::default()
+        };
+        assert!(matches!(GrauxConfig::new(Some(settings)), Err(GrauxError::InvalidApiKey(_))));
+
+        let settings = GrauxSettings {
+            url: Some("https://node.example.com/secret".to_owned()),
+            ..GrauxSettings::default()
+        };
+        assert_eq!(GrauxConfig::new(Some(settings)).unwrap().api_key, None);
+    }
+
+    #[test]
+    fn url_only_config_never_uses_the_demo_key() {
+        let config = GrauxConfig::builder()
+            .url("https://node.example.com/secret")
+            .build()
+            .unwrap();
+        assert_eq!(config.api_key, None);
+        assert!(matches!(config.api_key(), Err(GrauxError::InvalidApiKey(_))));
+        assert_eq!(
+            config.get_request_url(GrauxApiType::NFT).unwrap(),
+            "https://node.example.com/secret"
+        );
+    }
+
+    #[test]
+    fn nft_calls_fail_on_networks_without_nft_support() {
+        let astar = lookup_network("astar").unwrap();
+        let config = GrauxConfig::builder().api_key("a-real-key").network(astar).build().unwrap();
+        assert!(matches!(
+            config.get_request_url(GrauxApiType::NFT),
+            Err(GrauxError::InvalidNetwork(_))
+        ));
+
+        let mainnet = lookup_network("mainnet").unwrap();
+        let config = GrauxConfig::builder().api_key("a-real-key").network(mainnet).build().unwrap();
+        assert_eq!(
+            config.get_request_url(GrauxApiType::NFT).unwrap(),
+            "https://eth-mainnet.g.graux.com/nft/v2/a-real-key"
+        );
+    }
+
+    #[test]
+    fn build_validates_ranges() {
+        let key = "a-real-key";
+        assert!(GrauxConfig::builder().api_key(key).request_timeout(10).build().is_err());
+        assert!(GrauxConfig::builder().api_key(key).max_retries(MAX_MAX_RETRIES + 1).build().is_err());
+        assert!(GrauxConfig::builder().api_key(key).url("ws://node").build().is_err());
+        assert!(GrauxConfig::builder().api_key(key).request_timeout(5_000).build().is_ok());
+    }
+
+    #[test]
+    fn debug_output_redacts_secrets() {
+        let settings = GrauxSettings {
+            api_key: Some("super-secret-key".to_owned()),
+            auth_token: Some("super-secret-token".to_owned()),
+            ..GrauxSettings::default()
+        };
+        let builder = GrauxConfig::builder().api_key("super-secret-key").auth_token("super-secret-token");
+        for output in [format!("{settings:?}"), format!("{builder:?}")] {
+            assert!(!output.contains("super-secret"), "{output}");
+            assert!(output.contains("<redacted>"), "{output}");
+        }
+    }
+}
@@ -0,0 +1,433 @@
+use std::collections::{BTreeMap, HashSet};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use ethers_core::types::{Block, Filter, Log, TxHash, H256, U256, U64};
+use ethers_providers::{Http, Middleware, Provider};
+use futures_util::{Stream, StreamExt};
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::{broadcast, mpsc};
+
+use crate::apicall::graux_websocket_provider::ConnectionEvent;
+use crate::error::{GrauxError, GrauxResult};
+
+/// How many recent blocks are remembered for de-duplication.
+const RECENT_BLOCKS: u64 = 256;
+
+/// Widest block range requested in a single `eth_getLogs` during backfill.
+const MAX_LOG_RANGE: u64 = 2_000;
+
+/// A subscription stream that fills gaps left by dropped connections over HTTP.
+///
+/// Items are delivered in block order without duplicates. An `Err` item means a gap could
+/// not be backfilled; the stream keeps delivering live items afterwards.
+pub struct GapFreeStream<T> {
+    receiver: mpsc::UnboundedReceiver<GrauxResult<T>>,
+}
+
+impl<T> Stream for GapFreeStream<T> {
+    type Item = GrauxResult<T>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.receiver.poll_recv(cx)
+    }
+}
+
+/// Starts relaying `live` blocks. The head at this point is the cursor the first backfill
+/// starts from, so a drop before the first live block is backfilled as well.
+pub(crate) async fn gap_free_blocks<S>(
+    live: S,
+    events: broadcast::Receiver<ConnectionEvent>,
+    http: Provider<Http>,
+) -> GrauxResult<GapFreeStream<Block<TxHash>>>
+where
+    S: Stream<Item = Block<TxHash>> + Send + Unpin + 'static,
+{
+    let head = http.get_block_number().await?.as_u64();
+    let (sender, receiver) = mpsc::unbounded_channel();
+    let cursor = BlockCursor {
+        sender,
+        start: head,
+        delivered: BTreeMap::new(),
+    };
+    tokio::spawn(run_blocks(live, events, http, cursor));
+    Ok(GapFreeStream { receiver })
+}
+
+/// Starts relaying `live` logs, backfilling from the block after the current head if the
+/// socket drops before the first live log.
+pub(crate) async fn gap_free_logs<S>(
+    live: S,
+    filter: Filter,
+    events: broadcast::Receiver<ConnectionEvent>,
+    http: Provider<Http>,
+) -> GrauxResult<GapFreeStream<Log>>
+where
+    S: Stream<Item = Log> + Send + Unpin + 'static,
+{
+    let head = http.get_block_number().await?.as_u64();
+    let (sender, receiver) = mpsc::unbounded_channel();
+    let cursor = LogCursor {
+        sender,
+        start: head + 1,
+        seen: BTreeMap::new(),
+    };
+    tokio::spawn(run_logs(live, filter, events, http, cursor));
+    Ok(GapFreeStream { receiver })
+}
+
+/// Set when the consumer dropped the stream and the task should stop.
+struct Closed;
+
+struct BlockCursor {
+    sender: mpsc::UnboundedSender<GrauxResult<Block<TxHash>>>,
+    /// Head when the subscription started, which counts as delivered.
+    start: u64,
+    /// Hashes of recently delivered blocks by number.
+    delivered: BTreeMap<u64, H256>,
+}
+
+impl BlockCursor {
+    fn last(&self) -> u64 {
+        self.delivered.keys().next_back().copied().unwrap_or(self.start)
+    }
+
+    fn report(&self, err: GrauxError) -> Result<(), Closed> {
+        self.sender.send(Err(err)).map_err(|_| Closed)
+    }
+
+    /// Delivers `block` unless it was already delivered, backfilling any gap before it.
+    async fn deliver(&mut self, http: &Provider<Http>, block: Block<TxHash>) -> Result<(), Closed> {
+        let (Some(number), Some(hash)) = (block.number.map(|n| n.as_u64()), block.hash) else {
+            return Ok(());
+        };
+        if self.delivered.get(&number) == Some(&hash) {
+            return Ok(());
+        }
+        let last = self.last();
+        if number > last + 1 {
+            self.backfill(http, last + 1, number - 1).await?;
+        }
+        self.push(number, hash, block)
+    }
+
+    fn push(&mut self, number: u64, hash: H256, block: Block<TxHash>) -> Result<(), Closed> {
+        // A block at or below the tip with a new hash replaces everything above it.
+        self.delivered.split_off(&number);
+        self.delivered.insert(number, hash);
+        while self.delivered.len() as u64 > RECENT_BLOCKS {
+            self.delivered.pop_first();
+        }
+        self.sender.send(Ok(block)).map_err(|_| Closed)
+    }
+
+    async fn backfill(&mut self, http: &Provider<Http>, from: u64, to: u64) -> Result<(), Closed> {
+        for number in from..=to {
+            match http.get_block(number).await {
+                Ok(Some(block)) => {
+                    let hash = block.hash.unwrap_or_default();
+                    if self.delivered.get(&number) != Some(&hash) {
+                        self.push(number, hash, block)?;
+                    }
+                }
+                Ok(None) => return Ok(()),
+                Err(err) => return self.report(err.into()),
+            }
+        }
+        Ok(())
+    }
+}
+
+async fn run_blocks<S>(
+    mut live: S,
+    mut events: broadcast::Receiver<ConnectionEvent>,
+    http: Provider<Http>,
+    mut cursor: BlockCursor,
+) where
+    S: Stream<Item = Block<TxHash>> + Unpin,
+{
+    let mut events_open = true;
+
+    loop {
+        let step = tokio::select! {
+            block = live.next() => match block {
+                Some(block) => cursor.deliver(&http, block).await,
+                None => return,
+            },
+            event = events.recv(), if events_open => match event {
+                Ok(ConnectionEvent::Reconnected { .. }) | Err(RecvError::Lagged(_)) => {
+                    match http.get_block_number().await {
+                        Ok(head) => cursor.backfill(&http, cursor.last() + 1, head.as_u64()).await,
+                        Err(err) => cursor.report(err.into()),
+                    }
+                }
+                Ok(ConnectionEvent::Disconnected { .. }) => Ok(()),
+                Ok(ConnectionEvent::Closed) | Err(RecvError::Closed) => {
+                    events_open = false;
+                    Ok(())
+                }
+            },
+        };
+        if step.is_err() {
+            return;
+        }
+    }
+}
+
+type LogKey = (H256, U256, bool);
+
+struct LogCursor {
+    sender: mpsc::UnboundedSender<GrauxResult<Log>>,
+    /// Block to backfill from while no log has been delivered: the one after the head when
+    /// the subscription started.
+    start: u64,
+    /// Keys of recently delivered logs, grouped by block number.
+    seen: BTreeMap<u64, HashSet<LogKey>>,
+}
+
+impl LogCursor {
+    fn last_block(&self) -> Option<u64> {
+        self.seen.keys().next_back().copied()
+    }
+
+    /// First block of the next backfill. The last block with a delivered log is included,
+    /// since it may have been only partially delivered.
+    fn backfill_from(&self) -> u64 {
+        self.last_block().unwrap_or(self.start)
+    }
+
+    fn report(&self, err: GrauxError) -> Result<(), Closed> {
+        self.sender.send(Err(err)).map_err(|_| Closed)
+    }
+
+    fn deliver(&mut self, log: Log) -> Result<(), Closed> {
+        let (Some(block), Some(block_hash), Some(index)) =
+            (log.block_number.map(|n: U64| n.as_u64()), log.block_hash, log.log_index)
+        else {
+            return self.sender.send(Ok(log)).map_err(|_| Closed);
+        };
+
+        let key = (block_hash, index, log.removed.unwrap_or(false));
+        if !self.seen.entry(block).or_default().insert(key) {
+            return Ok(());
+        }
+        if let Some(last) = self.last_block() {
+            self.seen = self.seen.split_off(&last.saturating_sub(RECENT_BLOCKS));
+        }
+        self.sender.send(Ok(log)).map_err(|_| Closed)
+    }
+
+    /// Backfills from block `from` up to the current head, then delivers the live logs
+    /// `held` back meanwhile.
+    async fn resume(
+        &mut self,
+        http: &Provider<Http>,
+        filter: &Filter,
+        from: u64,
+        held: Vec<Log>,
+    ) -> Result<(), Closed> {
+        match http.get_block_number().await {
+            Ok(head) => self.backfill(http, filter, from, head.as_u64()).await?,
+            Err(err) => self.report(err.into())?,
+        }
+        held.into_iter().try_for_each(|log| self.deliver(log))
+    }
+
+    async fn backfill(&mut self, http: &Provider<Http>, filter: &Filter, from: u64, head: u64) -> Result<(), Closed> {
+        let mut from = from;
+        while from <= head {
+            let to = (from + MAX_LOG_RANGE - 1).min(head);
+            let chunk = filter.clone().from_block(from).to_block(to);
+            let mut logs = match http.get_logs(&chunk).await {
+                Ok(logs) => logs,
+                Err(err) => return self.report(err.into()),
+            };
+            logs.sort_by_key(|log| (log.block_number, log.log_index));
+            for log in logs {
+                self.deliver(log)?;
+            }
+            from = to + 1;
+        }
+        Ok(())
+    }
+}
+
+async fn run_logs<S>(
+    mut live: S,
+    filter: Filter,
+    mut events: broadcast::Receiver<ConnectionEvent>,
+    http: Provider<Http>,
+    mut cursor: LogCursor,
+) where
+    S: Stream<Item = Log> + Unpin,
+{
+    let mut events_open = true;
+    // Set while reconnecting: the block to backfill from as of the drop, and the live logs
+    // held back until the gap has been backfilled.
+    let mut reconnecting: Option<(u64, Vec<Log>)> = None;
+
+    loop {
+        let step = tokio::select! {
+            // Connection events first, so that no live log from a new socket slips past the
+            // `Disconnected` that precedes it.
+            biased;
+            event = events.recv(), if events_open => match event {
+                Ok(ConnectionEvent::Disconnected { .. }) => {
+                    reconnecting.get_or_insert_with(|| (cursor.backfill_from(), Vec::new()));
+                    Ok(())
+                }
+                Ok(ConnectionEvent::Reconnected { .. }) | Err(RecvError::Lagged(_)) => {
+                    let (from, held) = reconnecting
+                        .take()
+                        .unwrap_or_else(|| (cursor.backfill_from(), Vec::new()));
+                    cursor.resume(&http, &filter, from, held).await
+                }
+                Ok(ConnectionEvent::Closed) | Err(RecvError::Closed) => {
+                    events_open = false;
+                    match reconnecting.take() {
+                        Some((_, held)) => held.into_iter().try_for_each(|log| cursor.deliver(log)),
+                        None => Ok(()),
+                    }
+                }
+            },
+            log = live.next() => match log {
+                Some(log) => match &mut reconnecting {
+                    Some((_, held)) => {
+                        held.push(log);
+                        Ok(())
+                    }
+                    None => cursor.deliver(log),
+                },
+                None => return,
+            },
+        };
+        if step.is_err() {
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_http::{MockResponse, MockServer};
+    use serde_json::json;
+
+    fn log(block: u64, index: u64) -> Log {
+        serde_json::from_value(json!({
+            "address": "0x0000000000000000000000000000000000000001",
+            "topics": [],
+            "data": "0x",
+            "blockNumber": format!("{block:#x}"),
+            "blockHash": format!("{:#066x}", block),
+            "logIndex": format!("{index:#x}"),
+            "removed": false,
+        }))
+        .unwrap()
+    }
+
+    fn cursor() -> (LogCursor, mpsc::UnboundedReceiver<GrauxResult<Log>>) {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let cursor = LogCursor {
+            sender,
+            start: 0,
+            seen: BTreeMap::new(),
+        };
+        (cursor, receiver)
+    }
+
+    fn received(receiver: &mut mpsc::UnboundedReceiver<GrauxResult<Log>>) -> Vec<(u64, u64)> {
+        let mut out = Vec::new();
+        while let Ok(item) = receiver.try_recv() {
+            let log = item.unwrap();
+            out.push((log.block_number.unwrap().as_u64(), log.log_index.unwrap().as_u64()));
+        }
+        out
+    }
+
+    #[test]
+    fn duplicate_logs_are_delivered_once() {
+        let (mut cursor, mut receiver) = cursor();
+        for item in [log(10, 0), log(10, 1), log(10, 0), log(11, 0)] {
+            cursor.deliver(item).ok().unwrap();
+        }
+        assert_eq!(received(&mut receiver), vec![(10, 0), (10, 1), (11, 0)]);
+    }
+
+    #[tokio::test]
+    async fn resume_backfills_from_the_snapshot_before_held_logs() {
+        let server = MockServer::start(|request| {
+            if request.body.contains("eth_blockNumber") {
+                MockResponse::json(200, r#"{"jsonrpc":"2.0","id":1,"result":"0xc"}"#)
+            } else {
+                let logs = json!([log(11, 0), log(10, 0), log(12, 0)]);
+                MockResponse::json(200, json!({"jsonrpc": "2.0", "id": 1, "result": logs}).to_string())
+            }
+        })
+        .await;
+        let http = Provider::<Http>::try_from(server.url.as_str()).unwrap();
+
+        let (mut cursor, mut receiver) = cursor();
+        cursor.deliver(log(10, 0)).ok().unwrap();
+        let from = cursor.backfill_from();
+        cursor
+            .resume(&http, &Filter::new(), from, vec![log(12, 0), log(13, 0)])
+            .await
+            .ok()
+            .unwrap();
+
+        assert_eq!(received(&mut receiver), vec![(10, 0), (11, 0), (12, 0), (13, 0)]);
+    }
+
+    fn block(number: u64) -> Block<TxHash> {
+        Block {
+            number: Some(number.into()),
+            hash: Some(H256::from_low_u64_be(number)),
+            ..Default::default()
+        }
+    }
+
+    /// Answers `eth_blockNumber` with `heads` in turn and `eth_getBlockByNumber` with the
+    /// requested block.
+    async fn block_server(heads: &'static [u64]) -> MockServer {
+        let calls = std::sync::atomic::AtomicUsize::new(0);
+        MockServer::start(move |request| {
+            let body: serde_json::Value = serde_json::from_str(&request.body).unwrap();
+            let result = match body["method"].as_str().unwrap() {
+                "eth_blockNumber" => {
+                    let call = calls.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                    json!(format!("{:#x}", heads[call.min(heads.len() - 1)]))
+                }
+                "eth_getBlockByNumber" => {
+                    let number = body["params"][0].as_str().unwrap().trim_start_matches("0x");
+                    json!(block(u64::from_str_radix(number, 16).unwrap()))
+                }
+                method => panic!("unexpected {method}"),
+            };
+            MockResponse::json(200, json!({ "jsonrpc": "2.0", "id": body["id"], "result": result }).to_string())
+        })
+        .await
+    }
+
+    #[tokio::test]
+    async fn backfills_blocks_missed_before_the_first_live_block() {
+        // The head is 10 at subscribe time and 13 once the socket is back.
+        let server = block_server(&[10, 13]).await;
+        let http = Provider::<Http>::try_from(server.url.as_str()).unwrap();
+        let (live_tx, mut live_rx) = mpsc::unbounded_channel();
+        let live = futures_util::stream::poll_fn(move |cx| live_rx.poll_recv(cx));
+        let (events_tx, events) = broadcast::channel(4);
+
+        let mut stream = gap_free_blocks(live, events, http).await.unwrap();
+        events_tx.send(ConnectionEvent::Disconnected { generation: 0 }).unwrap();
+        events_tx.send(ConnectionEvent::Reconnected { generation: 1 }).unwrap();
+        for number in 11..=13 {
+            assert_eq!(stream.next().await.unwrap().unwrap().number, Some(number.into()));
+        }
+
+        // Live blocks already backfilled are skipped; later ones come through.
+        live_tx.send(block(13)).unwrap();
+        live_tx.send(block(14)).unwrap();
+        assert_eq!(stream.next().await.unwrap().unwrap().number, Some(14.into()));
+    }
+}
@@ -0,0 +1,446 @@
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use ethers_providers::{JsonRpcClient, JsonRpcError, ProviderError, RpcError};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, AUTHORIZATION};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tokio::sync::{mpsc, oneshot};
+use tokio::time::Instant;
+
+use crate::error::{GrauxError, GrauxResult};
+
+/// Limits that decide when pending calls are flushed as one JSON-RPC batch.
+#[derive(Debug, Clone)]
+pub struct BatchConfig {
+    /// How long the first call of a batch waits for others to join it.
+    pub window: Duration,
+    pub max_batch_size: usize,
+    pub max_payload_bytes: usize,
+}
+
+impl Default for BatchConfig {
+    fn default() -> Self {
+        BatchConfig {
+            window: Duration::from_millis(10),
+            max_batch_size: 100,
+            max_payload_bytes: 1024 * 1024,
+        }
+    }
+}
+
+/// Errors produced by `BatchTransport`. Each call in a batch gets its own error.
+#[derive(Debug, thiserror::Error)]
+pub enum BatchClientError {
+    #[error("transport error: {0}")]
+    Transport(String),
+
+    #[error("HTTP {status}: {message}")]
+    Http {
+        status: u16,
+        message: String,
+        /// Raw `Retry-After` header of the response, if any.
+        retry_after: Option<String>,
+    },
+
+    #[error(transparent)]
+    JsonRpc(JsonRpcError),
+
+    #[error("malformed batch response: {0}")]
+    MalformedResponse(String),
+
+    #[error(transparent)]
+    SerdeJson(#[from] serde_json::Error),
+}
+
+impl RpcError for BatchClientError {
+    fn as_error_response(&self) -> Option<&JsonRpcError> {
+        match self {
+            BatchClientError::JsonRpc(err) => Some(err),
+            _ => None,
+        }
+    }
+
+    fn as_serde_error(&self) -> Option<&serde_json::Error> {
+        match self {
+            BatchClientError::SerdeJson(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<BatchClientError> for ProviderError {
+    fn from(err: BatchClientError) -> Self {
+        ProviderError::JsonRpcClientError(Box::new(err))
+    }
+}
+
+impl From<BatchClientError> for GrauxError {
+    fn from(err: BatchClientError) -> Self {
+        match err {
+            BatchClientError::Transport(message) => GrauxError::Transport(message),
+            BatchClientError::Http {
+                status,
+                message,
+                retry_after,
+            } => GrauxError::from_http_status(status, retry_after.as_deref(), message),
+            BatchClientError::JsonRpc(err) => GrauxError::from_json_rpc(&err),
+            BatchClientError::MalformedResponse(message) => GrauxError::Decode(message),
+            BatchClientError::SerdeJson(err) => GrauxError::from(err),
+        }
+    }
+}
+
+type CallResult = Result<Value, BatchClientError>;
+
+struct PendingCall {
+    id: u64,
+    payload: Value,
+    size: usize,
+    respond: oneshot::Sender<CallResult>,
+}
+
+#[derive(Deserialize)]
+struct ResponseItem {
+    /// The request id, which servers may echo as a number or a string; `null` when the
+    /// server could not read the request at all.
+    #[serde(default)]
+    id: Value,
+    #[serde(default)]
+    result: Option<Value>,
+    #[serde(default)]
+    error: Option<JsonRpcError>,
+}
+
+/// HTTP JSON-RPC client that coalesces concurrent calls into JSON-RPC batches.
+///
+/// Calls issued within `BatchConfig::window` of each other are sent as one array, up to
+/// `max_batch_size` calls or `max_payload_bytes`. Responses are matched back by id, so an
+/// error in one call does not fail the others. Must be created inside a Tokio runtime.
+#[derive(Debug, Clone)]
+pub struct BatchTransport {
+    next_id: std::sync::Arc<AtomicU64>,
+    sender: mpsc::UnboundedSender<PendingCall>,
+}
+
+impl BatchTransport {
+    /// Uses a default HTTP client, without timeout or extra headers. See `with_client`.
+    pub fn new(url: impl Into<String>, config: BatchConfig) -> Self {
+        BatchTransport::with_client(url, config, reqwest::Client::new())
+    }
+
+    /// Sends batches with `client`, e.g. one made by `http_client` from the SDK config.
+    pub fn with_client(url: impl Into<String>, config: BatchConfig, client: reqwest::Client) -> Self {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        tokio::spawn(run_batcher(client, url.into(), config, receiver));
+
+        BatchTransport {
+            next_id: std::sync::Arc::new(AtomicU64::new(1)),
+            sender,
+        }
+    }
+}
+
+#[async_trait]
+impl JsonRpcClient for BatchTransport {
+    type Error = BatchClientError;
+
+    async fn request<T, R>(&self, method: &str, params: T) -> Result<R, Self::Error>
+    where
+        T: Debug + Serialize + Send + Sync,
+        R: DeserializeOwned + Send,
+    {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let payload = json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "method": method,
+            "params": params,
+        });
+        let size = serde_json::to_vec(&payload)?.len();
+        let (respond, response) = oneshot::channel();
+
+        self.sender
+            .send(PendingCall {
+                id,
+                payload,
+                size,
+                respond,
+            })
+            .map_err(|_| BatchClientError::Transport("batch worker stopped".to_owned()))?;
+
+        let result = response
+            .await
+            .map_err(|_| BatchClientError::Transport("batch worker dropped the call".to_owned()))??;
+        Ok(serde_json::from_value(result)?)
+    }
+}
+
+/// HTTP client for `BatchTransport::with_client` that applies the same request timeout,
+/// auth token and SDK headers as the single-request transport.
+pub fn http_client(
+    request_timeout: Duration,
+    auth_token: Option<&str>,
+    headers: &[(&str, &str)],
+) -> GrauxResult<reqwest::Client> {
+    let invalid = |e: &dyn std::fmt::Display| GrauxError::Config(format!("invalid HTTP header: {e}"));
+
+    let mut header_map = HeaderMap::new();
+    for (name, value) in headers {
+        let name = HeaderName::from_bytes(name.as_bytes()).map_err(|e| invalid(&e))?;
+        let value = HeaderValue::from_str(value).map_err(|e| invalid(&e))?;
+        header_map.insert(name, value);
+    }
+    if let Some(token) = auth_token {
+        let mut value = HeaderValue::from_str(&format!("Bearer {token}")).map_err(|e| invalid(&e))?;
+        value.set_sensitive(true);
+        header_map.insert(AUTHORIZATION, value);
+    }
+
+    reqwest::Client::builder()
+        .timeout(request_timeout)
+        .default_headers(header_map)
+        .build()
+        .map_err(|e| GrauxError::Config(format!("cannot build HTTP client: {e}")))
+}
+
+async fn run_batcher(
+    client: reqwest::Client,
+    url: String,
+    config: BatchConfig,
+    mut receiver: mpsc::UnboundedReceiver<PendingCall>,
+) {
+    while let Some(first) = receiver.recv().await {
+        let deadline = Instant::now() + config.window;
+        let mut size = first.size;
+        let mut batch = vec![first];
+
+        while batch.len() < config.max_batch_size {
+            match tokio::time::timeout_at(deadline, receiver.recv()).await {
+                Ok(Some(call)) => {
+                    if size + call.size > config.max_payload_bytes {
+                        size = call.size;
+                        let full = std::mem::replace(&mut batch, vec![call]);
+                        tokio::spawn(dispatch(client.clone(), url.clone(), full));
+                    } else {
+                        size += call.size;
+                        batch.push(call);
+                    }
+                }
+                // Window elapsed or every sender is gone.
+                Ok(None) | Err(_) => break,
+            }
+        }
+
+        tokio::spawn(dispatch(client.clone(), url.clone(), batch));
+    }
+}
+
+/// Sends one batch and routes each response item to its caller.
+async fn dispatch(client: reqwest::Client, url: String, batch: Vec<PendingCall>) {
+    let body = if batch.len() == 1 {
+        batch[0].payload.clone()
+    } else {
+        Value::Array(batch.iter().map(|call| call.payload.clone()).collect())
+    };
+
+    let items = match send(&client, &url, &body).await {
+        Ok(items) => items,
+        Err(err) => {
+            for call in batch {
+                let _ = call.respond.send(Err(clone_shared_error(&err)));
+            }
+            return;
+        }
+    };
+
+    // A lone call owns the only response even if the server mangled its id.
+    let single = batch.len() == 1 && items.len() == 1;
+    let mut by_id: HashMap<u64, ResponseItem> = items
+        .into_iter()
+        .filter_map(|item| {
+            let id = if single { Some(batch[0].id) } else { response_id(&item.id) };
+            id.map(|id| (id, item))
+        })
+        .collect();
+    for call in batch {
+        let result = match by_id.remove(&call.id) {
+            Some(ResponseItem {
+                error: Some(err), ..
+            }) => Err(BatchClientError::JsonRpc(err)),
+            Some(item) => Ok(item.result.unwrap_or(Value::Null)),
+            None => Err(BatchClientError::MalformedResponse(format!(
+                "no response for request id {}",
+                call.id
+            ))),
+        };
+        let _ = call.respond.send(result);
+    }
+}
+
+async fn send(client: &reqwest::Client, url: &str, body: &Value) -> Result<Vec<ResponseItem>, BatchClientError> {
+    let response = client
+        .post(url)
+        .json(body)
+        .send()
+        .await
+        .map_err(|e| BatchClientError::Transport(e.to_string()))?;
+
+    let status = response.status();
+    let retry_after = response
+        .headers()
+        .get(reqwest::header::RETRY_AFTER)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_owned);
+    let text = response
+        .text()
+        .await
+        .map_err(|e| BatchClientError::Transport(e.to_string()))?;
+    if !status.is_success() {
+        return Err(BatchClientError::Http {
+            status: status.as_u16(),
+            message: text,
+            retry_after,
+        });
+    }
+
+    // A single request is answered with an object, a batch with an array. Malformed items
+    // are dropped here so that only the calls they belonged to fail, as unanswered.
+    match serde_json::from_str::<Value>(&text)? {
+        Value::Array(items) => Ok(items
+            .into_iter()
+            .filter_map(|item| serde_json::from_value(item).ok())
+            .collect()),
+        item => Ok(vec![serde_json::from_value(item)?]),
+    }
+}
+
+/// Request id of a response item, given as a number or a numeric string.
+fn response_id(id: &Value) -> Option<u64> {
+    match id {
+        Value::Number(number) => number.as_u64(),
+        Value::String(string) => string.parse().ok(),
+        _ => None,
+    }
+}
+
+/// Errors that failed the whole batch are reported to every caller in it.
+fn clone_shared_error(err: &BatchClientError) -> BatchClientError {
+    match err {
+        BatchClientError::Http {
+            status,
+            message,
+            retry_after,
+        } => BatchClientError::Http {
+            status: *status,
+            message: message.clone(),
+            retry_after: retry_after.clone(),
+        },
+        BatchClientError::Transport(message) => BatchClientError::Transport(message.clone()),
+        other => BatchClientError::MalformedResponse(other.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_http::{MockResponse, MockServer};
+
+    /// Answers each call of a batch with its id times ten, in reverse order.
+    fn echo_ids(body: &str) -> MockResponse {
+        let calls: Vec<Value> = match serde_json::from_str(body).unwrap() {
+            Value::Array(calls) => calls,
+            call => vec![call],
+        };
+        let items: Vec<Value> = calls
+            .iter()
+            .rev()
+            .map(|call| json!({ "jsonrpc": "2.0", "id": call["id"], "result": call["id"].as_u64().unwrap() * 10 }))
+            .collect();
+        MockResponse::json(200, Value::Array(items).to_string())
+    }
+
+    fn transport(url: &str) -> BatchTransport {
+        let config = BatchConfig {
+            window: Duration::from_millis(50),
+            ..BatchConfig::default()
+        };
+        BatchTransport::new(url, config)
+    }
+
+    #[tokio::test]
+    async fn coalesces_concurrent_calls_and_matches_responses_by_id() {
+        let server = MockServer::start(|request| echo_ids(&request.body)).await;
+        let transport = transport(&server.url);
+
+        let (a, b, c) = tokio::join!(
+            transport.request::<_, u64>("eth_blockNumber", ()),
+            transport.request::<_, u64>("eth_chainId", ()),
+            transport.request::<_, u64>("eth_gasPrice", ()),
+        );
+        let mut results = vec![a.unwrap(), b.unwrap(), c.unwrap()];
+        results.sort_unstable();
+        assert_eq!(results, vec![10, 20, 30]);
+        assert_eq!(server.hits(), 1);
+    }
+
+    #[tokio::test]
+    async fn isolates_errors_and_unmatched_ids_to_their_calls() {
+        let server = MockServer::start(|_| {
+            MockResponse::json(
+                200,
+                r#"[
+                    {"jsonrpc":"2.0","id":1,"result":"0x1"},
+                    {"jsonrpc":"2.0","id":"2","error":{"code":-32000,"message":"header not found"}},
+                    {"jsonrpc":"2.0","id":null,"error":{"code":-32700,"message":"parse error"}}
+                ]"#,
+            )
+        })
+        .await;
+        let transport = transport(&server.url);
+
+        let (a, b, c) = tokio::join!(
+            transport.request::<_, String>("eth_blockNumber", ()),
+            transport.request::<_, String>("eth_getBlockByNumber", ()),
+            transport.request::<_, String>("eth_chainId", ()),
+        );
+        assert_eq!(a.unwrap(), "0x1");
+        assert!(matches!(b, Err(BatchClientError::JsonRpc(ref err)) if err.code == -32000));
+        assert!(matches!(c, Err(BatchClientError::MalformedResponse(_))));
+    }
+
+    #[tokio::test]
+    async fn maps_rate_limits_with_retry_after() {
+        let server = MockServer::start(|_| MockResponse::json(429, "{}").with_header("Retry-After", "3")).await;
+
+        let err = transport(&server.url).request::<_, Value>("eth_blockNumber", ()).await.unwrap_err();
+        assert!(matches!(
+            GrauxError::from(err),
+            GrauxError::RateLimited { retry_after: Some(delay), .. } if delay == Duration::from_secs(3)
+        ));
+    }
+
+    #[tokio::test]
+    async fn configured_client_sends_auth_and_sdk_headers() {
+        let server = MockServer::start(|request| {
+            let authorized = request.header("authorization") == Some("Bearer token-1")
+                && request.header("graux-ethers-sdk-version") == Some("1.2.3");
+            let result = if authorized { "\"ok\"" } else { "\"missing headers\"" };
+            MockResponse::json(200, format!(r#"{{"jsonrpc":"2.0","id":1,"result":{result}}}"#))
+        })
+        .await;
+
+        let client = http_client(
+            Duration::from_secs(5),
+            Some("token-1"),
+            &[("Graux-Ethers-Sdk-Version", "1.2.3")],
+        )
+        .unwrap();
+        let transport = BatchTransport::with_client(&server.url, BatchConfig::default(), client);
+        assert_eq!(transport.request::<_, String>("eth_chainId", ()).await.unwrap(), "ok");
+    }
+}
@@ -0,0 +1,314 @@
+use std::fmt;
+
+use ethers_core::types::{Address, U256};
+
+use crate::simulation::CallType;
+use crate::trace::CallFrame;
+
+/// A frame of a call tree together with its position in it.
+#[derive(Debug, Clone)]
+pub struct FrameRef<'a> {
+    /// Child indices leading from the root to this frame; empty for the root.
+    pub path: Vec<usize>,
+    pub frame: &'a CallFrame,
+}
+
+impl FrameRef<'_> {
+    pub fn depth(&self) -> usize {
+        self.path.len()
+    }
+}
+
+/// Selects frames of a call tree. Unset criteria match every frame.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CallFilter {
+    /// Matches calls made from or to this address.
+    pub address: Option<Address>,
+    pub selector: Option<[u8; 4]>,
+    pub call_type: Option<CallType>,
+    /// Only frames that reverted.
+    pub reverted_only: bool,
+}
+
+impl CallFilter {
+    pub fn address(mut self, address: Address) -> Self {
+        self.address = Some(address);
+        self
+    }
+
+    pub fn selector(mut self, selector: [u8; 4]) -> Self {
+        self.selector = Some(selector);
+        self
+    }
+
+    pub fn call_type(mut self, call_type: CallType) -> Self {
+        self.call_type = Some(call_type);
+        self
+    }
+
+    pub fn reverted_only(mut self) -> Self {
+        self.reverted_only = true;
+        self
+    }
+
+    pub fn matches(&self, frame: &CallFrame) -> bool {
+        self.address.map_or(true, |address| frame.from == address || frame.to == Some(address))
+            && self.selector.map_or(true, |selector| frame.selector() == Some(selector))
+            && self.call_type.map_or(true, |call_type| frame.call_type == call_type)
+            && (!self.reverted_only || frame.error.is_some())
+    }
+}
+
+/// Gas used by one frame, split into its own execution and its subcalls.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GasAttribution {
+    pub path: Vec<usize>,
+    pub call_type: CallType,
+    pub to: Option<Address>,
+    pub selector: Option<[u8; 4]>,
+    /// Total gas used, including subcalls.
+    pub gas_used: U256,
+    /// Gas used by this frame's own code.
+    pub self_gas: U256,
+}
+
+impl CallFrame {
+    /// The 4-byte function selector of the call, if it has one.
+    pub fn selector(&self) -> Option<[u8; 4]> {
+        if matches!(self.call_type, CallType::Create | CallType::Create2) {
+            return None;
+        }
+        self.input.get(..4).map(|bytes| bytes.try_into().expect("slice of length 4"))
+    }
+
+    pub fn is_reverted(&self) -> bool {
+        self.error.is_some()
+    }
+
+    /// Gas used by this frame excluding its subcalls.
+    pub fn self_gas(&self) -> U256 {
+        let children = self
+            .calls
+            .iter()
+            .fold(U256::zero(), |total, call| total.saturating_add(call.gas_used));
+        self.gas_used.saturating_sub(children)
+    }
+
+    /// Calls `visit` with the path and frame of every frame in depth-first pre-order, i.e. in
+    /// execution order.
+    pub fn walk<'a>(&'a self, mut visit: impl FnMut(&[usize], &'a CallFrame)) {
+        fn go<'a>(frame: &'a CallFrame, path: &mut Vec<usize>, visit: &mut impl FnMut(&[usize], &'a CallFrame)) {
+            visit(path, frame);
+            for (index, call) in frame.calls.iter().enumerate() {
+                path.push(index);
+                go(call, path, visit);
+                path.pop();
+            }
+        }
+        go(self, &mut Vec::new(), &mut visit);
+    }
+
+    /// Every frame in execution order, with its path.
+    pub fn frames(&self) -> Vec<FrameRef<'_>> {
+        self.filter(&CallFilter::default())
+    }
+
+    pub fn filter(&self, filter: &CallFilter) -> Vec<FrameRef<'_>> {
+        let mut matches = Vec::new();
+        self.walk(|path, frame| {
+            if filter.matches(frame) {
+                matches.push(FrameRef {
+                    path: path.to_vec(),
+                    frame,
+                });
+            }
+        });
+        matches
+    }
+
+    /// Frame at `path`, as returned in `FrameRef::path`.
+    pub fn get(&self, path: &[usize]) -> Option<&CallFrame> {
+        path.iter().try_fold(self, |frame, index| frame.calls.get(*index))
+    }
+
+    /// Gas attribution of every frame in execution order.
+    pub fn gas_attribution(&self) -> Vec<GasAttribution> {
+        let mut attribution = Vec::new();
+        self.walk(|path, frame| {
+            attribution.push(GasAttribution {
+                path: path.to_vec(),
+                call_type: frame.call_type,
+                to: frame.to,
+                selector: frame.selector(),
+                gas_used: frame.gas_used,
+                self_gas: frame.self_gas(),
+            });
+        });
+        attribution
+    }
+
+    /// The deepest call of the first reverted branch, which is where the revert originated.
+    pub fn first_revert(&self) -> Option<FrameRef<'_>> {
+        fn find<'a>(frame: &'a CallFrame, path: &mut Vec<usize>) -> Option<FrameRef<'a>> {
+            for (index, call) in frame.calls.iter().enumerate() {
+                path.push(index);
+                if let Some(found) = find(call, path) {
+                    return Some(found);
+                }
+                path.pop();
+            }
+            frame.is_reverted().then(|| FrameRef {
+                path: path.clone(),
+                frame,
+            })
+        }
+        find(self, &mut Vec::new())
+    }
+
+    /// Renders the call tree as indented text, one line per frame.
+    pub fn render_tree(&self) -> String {
+        self.to_string()
+    }
+}
+
+/// One line of the rendered tree, without indentation.
+fn describe(frame: &CallFrame) -> String {
+    // Opcode spelling, as block explorers show it.
+    let call_type = format!("{:?}", frame.call_type).to_uppercase();
+    let mut line = format!("{call_type} {:?}", frame.from);
+    match frame.to {
+        Some(to) => line.push_str(&format!(" -> {to:?}")),
+        None => line.push_str(" -> (new contract)"),
+    }
+    if let Some(selector) = frame.selector() {
+        line.push_str(&format!(" 0x{}", hex(&selector)));
+    }
+    if let Some(value) = frame.value.filter(|value| !value.is_zero()) {
+        line.push_str(&format!(" value={value}"));
+    }
+    line.push_str(&format!(" gas={}", frame.gas_used));
+    if let Some(error) = &frame.error {
+        let reason = frame.revert_reason.as_deref().unwrap_or(error);
+        line.push_str(&format!(" [reverted: {reason}]"));
+    }
+    line
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+impl fmt::Display for CallFrame {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fn render(frame: &CallFrame, prefix: &str, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            let count = frame.calls.len();
+            for (index, call) in frame.calls.iter().enumerate() {
+                let last = index + 1 == count;
+                let (branch, indent) = if last { ("└─ ", "   ") } else { ("├─ ", "│  ") };
+                writeln!(f, "{prefix}{branch}{}", describe(call))?;
+                render(call, &format!("{prefix}{indent}"), f)?;
+            }
+            Ok(())
+        }
+
+        writeln!(f, "{}", describe(self))?;
+        render(self, "", f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn address(byte: u8) -> Address {
+        Address::repeat_byte(byte)
+    }
+
+    /// A transfer that reads a balance, then makes a call whose contract creation runs out of gas.
+    fn tree() -> CallFrame {
+        serde_json::from_value(serde_json::json!({
+            "type": "CALL", "from": address(0xa), "to": address(0xb), "value": "0x5",
+            "gasUsed": "0x64", "input": "0xa9059cbb00",
+            "error": "execution reverted",
+            "calls": [
+                {
+                    "type": "STATICCALL", "from": address(0xb), "to": address(0xc), "gasUsed": "0x14",
+                    "input": "0x70a08231"
+                },
+                {
+                    "type": "CALL", "from": address(0xb), "to": address(0xd), "gasUsed": "0x1e", "input": "0x12345678",
+                    "error": "execution reverted", "revertReason": "nope",
+                    "calls": [
+                        {
+                            "type": "CREATE", "from": address(0xd), "gasUsed": "0xa", "input": "0x60806040",
+                            "error": "out of gas"
+                        }
+                    ]
+                }
+            ]
+        }))
+        .unwrap()
+    }
+
+    fn paths(frames: Vec<FrameRef<'_>>) -> Vec<Vec<usize>> {
+        frames.into_iter().map(|frame| frame.path).collect()
+    }
+
+    #[test]
+    fn walks_frames_in_execution_order() {
+        let tree = tree();
+        assert_eq!(paths(tree.frames()), vec![vec![], vec![0], vec![1], vec![1, 0]]);
+        assert_eq!(tree.get(&[1, 0]).unwrap().call_type, CallType::Create);
+        assert!(tree.get(&[2]).is_none());
+    }
+
+    #[test]
+    fn filters_combine_their_criteria() {
+        let tree = tree();
+        assert_eq!(paths(tree.filter(&CallFilter::default().address(address(0xd)))), vec![vec![1], vec![1, 0]]);
+        assert_eq!(paths(tree.filter(&CallFilter::default().selector([0x70, 0xa0, 0x82, 0x31]))), vec![vec![0]]);
+        assert_eq!(paths(tree.filter(&CallFilter::default().call_type(CallType::Call))), vec![vec![], vec![1]]);
+        assert_eq!(
+            paths(tree.filter(&CallFilter::default().call_type(CallType::Call).reverted_only())),
+            vec![vec![], vec![1]]
+        );
+        assert!(tree.filter(&CallFilter::default().address(address(0xc)).reverted_only()).is_empty());
+    }
+
+    #[test]
+    fn creations_have_no_selector() {
+        let tree = tree();
+        assert_eq!(tree.selector(), Some([0xa9, 0x05, 0x9c, 0xbb]));
+        assert_eq!(tree.get(&[1, 0]).unwrap().selector(), None);
+    }
+
+    #[test]
+    fn attributes_gas_to_each_frame() {
+        let self_gas: Vec<u64> = tree().gas_attribution().iter().map(|gas| gas.self_gas.as_u64()).collect();
+        assert_eq!(self_gas, vec![50, 20, 20, 10]);
+    }
+
+    #[test]
+    fn first_revert_is_the_deepest_reverted_frame() {
+        let tree = tree();
+        let revert = tree.first_revert().unwrap();
+        assert_eq!(revert.path, vec![1, 0]);
+        assert_eq!(revert.depth(), 2);
+        assert_eq!(revert.frame.error.as_deref(), Some("out of gas"));
+    }
+
+    #[test]
+    fn renders_the_tree() {
+        let expected = format!(
+            "CALL {a:?} -> {b:?} 0xa9059cbb value=5 gas=100 [reverted: execution reverted]\n\
+             ├─ STATICCALL {b:?} -> {c:?} 0x70a08231 gas=20\n\
+             └─ CALL {b:?} -> {d:?} 0x12345678 gas=30 [reverted: nope]\n\
+             \x20  └─ CREATE {d:?} -> (new contract) gas=10 [reverted: out of gas]\n",
+            a = address(0xa),
+            b = address(0xb),
+            c = address(0xc),
+            d = address(0xd),
+        );
+        assert_eq!(tree().render_tree(), expected);
+    }
+}
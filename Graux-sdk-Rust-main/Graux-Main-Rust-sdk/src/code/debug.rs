@@ -1,84 +1,158 @@
-use crate::types::{BlockIdentifier, DebugCallTrace, DebugCallTracer, DebugPrestateTrace, DebugPrestateTracer, DebugTransaction};
+use std::sync::Arc;
+
+use ethers_core::types::TxHash;
+use ethers_providers::Provider;
+use futures_util::{Stream, StreamExt};
+use serde_json::{json, Value};
+
+use crate::types::{BlockIdentifier, DebugTransaction};
 use crate::graux_config::GrauxConfig;
 use crate::utils::{hex_strip_zeros, hex_value, is_hex_string};
+use crate::abi_registry::AbiRegistry;
+use crate::ens::EnsResolver;
+use crate::error::{GrauxError, GrauxResult};
+use crate::signatures::SignatureDb;
+use crate::state_diff::StateDiff;
+use crate::trace::{
+    block_trace_hashes, pair_block_traces, BlockTraceEntry, BlockTraces, PrestateTrace, PrestateTracerConfig,
+    TraceResult, Tracer, TxTraceResult,
+};
 
 DebugNamespace contains methods to access the non-standard RPC methods for inspecting and debugging transactions.
 pub struct DebugNamespace {
     config: GrauxConfig,
+    abi_registry: Arc<AbiRegistry>,
+    signatures: Arc<SignatureDb>,
+    ens: Arc<EnsResolver<Provider>>,
 }
 
 impl DebugNamespace {
     // Constructor
-    pub fn new(config: GrauxConfig) -> Self {
-        DebugNamespace { config }
+    `ens` resolves ENS names in the `from` and `to` of traced transactions; pass the resolver of `GrauxProvider::ens`.
+    pub fn new(config: GrauxConfig, ens: Arc<EnsResolver<Provider>>) -> Self {
+        DebugNamespace {
+            config,
+            abi_registry: Arc::new(AbiRegistry::new()),
+            signatures: Arc::new(SignatureDb::bundled()),
+            ens,
+        }
+    }
+
+    Decodes callTracer results with `abi_registry`, which can be shared with the other namespaces.
+    pub fn with_abi_registry(mut self, abi_registry: Arc<AbiRegistry>) -> Self {
+        self.abi_registry = abi_registry;
+        self
+    }
+
+    pub fn abi_registry(&self) -> &Arc<AbiRegistry> {
+        &self.abi_registry
+    }
+
+    Labels call frames and logs with `signatures` instead of the bundled database.
+    pub fn with_signature_db(mut self, signatures: Arc<SignatureDb>) -> Self {
+        self.signatures = signatures;
+        self
+    }
+
+    pub fn signature_db(&self) -> &Arc<SignatureDb> {
+        &self.signatures
     }
 
     Runs an `eth_call` with the context of the provided block execution using the final state of the parent block as the base.
-    pub async fn trace_call(&self, transaction: DebugTransaction, block_identifier: BlockIdentifier, tracer: DebugCallTracer) -> Result<DebugCallTrace, Box<dyn std::error::Error>> {
+    pub async fn trace_call(&self, transaction: DebugTransaction, block_identifier: BlockIdentifier, tracer: Tracer) -> GrauxResult<TraceResult> {
+        let transaction = self.ens.resolve_transaction(serde_json::to_value(transaction)?).await?;
         let provider = self.config.get_provider().await?;
-        let params = [transaction, block_identifier, parse_tracer_params(tracer)];
+        let params = json!([transaction, block_identifier, tracer.options(None)]);
         let result = provider.send("debug_traceCall", &params).await?;
-        
+        self.parse(&tracer, result)
     }
 
-    pub async fn trace_prestate(&self, transaction: DebugTransaction, block_identifier: BlockIdentifier, tracer: DebugPrestateTracer) -> Result<DebugPrestateTrace, Box<dyn std::error::Error>> {
-        let provider = self.config.get_provider().await?;
-        let params = [transaction, block_identifier, parse_tracer_params(tracer)];
-        let result = provider.send("debug_traceCall", &params).await?;
-        // Parse and return the result
-        // ...
+    Runs `trace_call` with the prestateTracer. With `diff_mode` only the changed state is returned, before and after.
+    pub async fn trace_prestate(&self, transaction: DebugTransaction, block_identifier: BlockIdentifier, diff_mode: bool) -> GrauxResult<PrestateTrace> {
+        let tracer = Tracer::Prestate(PrestateTracerConfig { diff_mode });
+        self.trace_call(transaction, block_identifier, tracer).await?.into_prestate()
     }
 
-    pub async fn trace_transaction(&self, transaction_hash: String, tracer: DebugCallTracer, timeout: Option<String>) -> Result<DebugCallTrace, Box<dyn std::error::Error>> {
+    Runs `trace_call` with the prestateTracer in diff mode and returns the per-account changes.
+    `StateDiff::report` renders them, including ERC-20 balance changes of the holders it is given.
+    pub async fn trace_state_diff(&self, transaction: DebugTransaction, block_identifier: BlockIdentifier) -> GrauxResult<StateDiff> {
+        match self.trace_prestate(transaction, block_identifier, true).await? {
+            PrestateTrace::Diff(diff) => Ok(StateDiff::from(&diff)),
+            PrestateTrace::Prestate(_) => Err(GrauxError::Decode("expected a diff mode prestate trace".to_owned())),
+        }
+    }
+
+    Same as `trace_state_diff` for a mined transaction.
+    pub async fn trace_transaction_state_diff(&self, transaction_hash: String) -> GrauxResult<StateDiff> {
+        match self.trace_transaction(transaction_hash, Tracer::prestate_diff(), None).await?.into_prestate()? {
+            PrestateTrace::Diff(diff) => Ok(StateDiff::from(&diff)),
+            PrestateTrace::Prestate(_) => Err(GrauxError::Decode("expected a diff mode prestate trace".to_owned())),
+        }
+    }
+
+    Replays a mined transaction. `timeout` uses Go duration syntax, e.g. "10s".
+    pub async fn trace_transaction(&self, transaction_hash: String, tracer: Tracer, timeout: Option<String>) -> GrauxResult<TraceResult> {
         let provider = self.config.get_provider().await?;
-        let params = [transaction_hash, parse_tracer_params(tracer, timeout)];
+        let params = json!([transaction_hash, tracer.options(timeout)]);
         let result = provider.send("debug_traceTransaction", &params).await?;
-        Parse and return the result
+        self.parse(&tracer, result)
     }
 
-    Replays a block that has already been mined.
-    pub async fn trace_block(&self, block_identifier: BlockIdentifier, tracer: DebugCallTracer) -> Result<DebugCallTrace, Box<dyn std::error::Error>> {
+    Replays a block that has already been mined and returns one result per transaction, in block order.
+    A transaction that fails to trace only sets the `result` of its own entry.
+    pub async fn trace_block(&self, block_identifier: BlockIdentifier, tracer: Tracer) -> GrauxResult<Vec<TxTraceResult>> {
         let provider = self.config.get_provider().await?;
-        let (method, params) = if is_hex_string(&block_identifier, 32) {
-            (
-                "debug_traceBlockByHash",
-                [block_identifier, parse_tracer_params(tracer)],
-            )
+        let (method, block_method, block) = if is_hex_string(&block_identifier, 32) {
+            ("debug_traceBlockByHash", "eth_getBlockByHash", json!(block_identifier))
         } else {
             (
                 "debug_traceBlockByNumber",
-                [
-                    hex_strip_zeros(hex_value(block_identifier)),
-                    parse_tracer_params(tracer),
-                ],
+                "eth_getBlockByNumber",
+                json!(hex_strip_zeros(hex_value(block_identifier))),
             )
         };
-        let result = provider.send(method, &params).await?;
-        Parse and return the result
-    }
-}
+        let result = provider.send(method, &json!([block, tracer.options(None)])).await?;
+        let entries: Vec<BlockTraceEntry> = serde_json::from_value(result)?;
 
-RawTracer represents the raw parameters for the tracer.
-struct RawTracer {
-    tracer: String,
-    tracer_config: Option<TracerConfig>,
-}
+        Older nodes omit `txHash`; the block's transaction list is in the same order as the traces.
+        let hashes: Vec<TxHash> = match block_trace_hashes(&entries) {
+            Some(hashes) => hashes,
+            None => {
+                let block = provider.send(block_method, &json!([block, false])).await?;
+                serde_json::from_value(block["transactions"].clone())?
+            }
+        };
 
-TracerConfig represents the configuration options for the tracer.
-struct TracerConfig {
-    only_top_call: Option<bool>,
-    timeout: Option<String>,
-}
+        pair_block_traces(hashes, entries, |result| self.parse(&tracer, result))
+    }
 
-Parses the tracer parameters into a RawTracer object.
-fn parse_tracer_params(tracer: DebugCallTracer, timeout: Option<String>) -> RawTracer {
-    let tracer_config = TracerConfig {
-        only_top_call: tracer.only_top_call,
-        timeout,
-    };
+    Parses a tracer's output, decodes call trees with the ABI registry and labels them from the signature database.
+    fn parse(&self, tracer: &Tracer, result: Value) -> GrauxResult<TraceResult> {
+        let mut result = tracer.parse(result)?;
+        self.abi_registry.annotate_trace(&mut result);
+        self.signatures.label_trace(&mut result);
+        Ok(result)
+    }
 
-    RawTracer {
-        tracer: tracer.type,
-        tracer_config: Some(tracer_config),
+    Traces every block in `from_block..=to_block` with at most `concurrency` blocks in flight. Blocks are yielded in order,
+    and a block that fails to trace is reported in its `BlockTraces` instead of ending the stream.
+    pub fn trace_block_range(
+        &self,
+        from_block: u64,
+        to_block: u64,
+        tracer: Tracer,
+        concurrency: usize,
+    ) -> impl Stream<Item = BlockTraces> + '_ {
+        futures_util::stream::iter(from_block..=to_block)
+            .map(move |block_number| {
+                let tracer = tracer.clone();
+                async move {
+                    BlockTraces {
+                        block_number,
+                        traces: self.trace_block(BlockIdentifier::from(block_number), tracer).await,
+                    }
+                }
+            })
+            .buffered(concurrency.max(1))
     }
 }
@@ -0,0 +1,288 @@
+use ethers_core::types::{Address, BlockId, BlockNumber, TxHash, U64};
+use ethers_core::utils::get_contract_address;
+use ethers_providers::Middleware;
+use serde_json::Value;
+
+use crate::error::{GrauxError, GrauxResult};
+use crate::simulation::CallType;
+use crate::trace::{CallFrame, Tracer};
+
+/// Where and by whom a contract was created.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ContractDeployment {
+    pub contract: Address,
+    /// Externally owned account that sent the creating transaction.
+    pub deployer: Address,
+    pub transaction_hash: TxHash,
+    pub block_number: u64,
+    /// Contract that executed the `CREATE`/`CREATE2`, when the contract was deployed through
+    /// a factory rather than by the transaction itself.
+    pub factory: Option<Address>,
+    pub create_type: CallType,
+}
+
+/// Finds the transaction that created `contract`, searching `from_block..=to_block` (by
+/// default the whole chain).
+///
+/// The creation block is found by binary search over `eth_getCode`, so this needs an archive
+/// node and assumes the address has held code ever since it was created. Direct deployments
+/// are recognized from the sender and nonce; deployments by factories (`CREATE` and
+/// `CREATE2`) from a `callTracer` trace of the creation block.
+///
+/// Returns `None` if there is no code at `contract` at `to_block`, or if it already had code
+/// at `from_block`. With the default range the latter means the contract was allocated in the
+/// genesis block, so no transaction created it.
+pub async fn find_contract_deployer<M>(
+    inner: &M,
+    contract: Address,
+    from_block: Option<BlockNumber>,
+    to_block: Option<BlockNumber>,
+) -> GrauxResult<Option<ContractDeployment>>
+where
+    M: Middleware,
+    GrauxError: From<M::Error>,
+{
+    let from = resolve(inner, from_block.unwrap_or(BlockNumber::Earliest)).await?;
+    let to = resolve(inner, to_block.unwrap_or(BlockNumber::Latest)).await?;
+    if from > to {
+        return Err(GrauxError::InvalidArgument(format!(
+            "fromBlock {from} is after toBlock {to}"
+        )));
+    }
+    if !has_code(inner, contract, to).await? || has_code(inner, contract, from).await? {
+        return Ok(None);
+    }
+
+    // Code is absent at `from` and present at `to`, so the creation block is in `from + 1..=to`.
+    let (mut low, mut high) = (from + 1, to);
+    while low < high {
+        let mid = low + (high - low) / 2;
+        if has_code(inner, contract, mid).await? {
+            high = mid;
+        } else {
+            low = mid + 1;
+        }
+    }
+    let block_number = low;
+
+    let block = inner
+        .get_block_with_txs(block_number)
+        .await?
+        .ok_or_else(|| GrauxError::Decode(format!("block {block_number} not found")))?;
+
+    for tx in &block.transactions {
+        if tx.to.is_none() && get_contract_address(tx.from, tx.nonce) == contract {
+            return Ok(Some(ContractDeployment {
+                contract,
+                deployer: tx.from,
+                transaction_hash: tx.hash,
+                block_number,
+                factory: None,
+                create_type: CallType::Create,
+            }));
+        }
+    }
+
+    let tracer = Tracer::call();
+    let traces: Vec<Value> = inner
+        .provider()
+        .request(
+            "debug_traceBlockByNumber",
+            (U64::from(block_number), tracer.options(None)),
+        )
+        .await?;
+    if traces.len() != block.transactions.len() {
+        return Err(GrauxError::Decode(format!(
+            "{} traces for a block with {} transactions",
+            traces.len(),
+            block.transactions.len()
+        )));
+    }
+
+    for (tx, mut trace) in block.transactions.iter().zip(traces) {
+        let Some(result) = trace.get_mut("result").map(Value::take) else {
+            continue;
+        };
+        let frame = tracer.parse(result)?.into_call()?;
+        if let Some((creator, create_type)) = find_create(&frame, contract) {
+            return Ok(Some(ContractDeployment {
+                contract,
+                deployer: tx.from,
+                transaction_hash: tx.hash,
+                block_number,
+                factory: (creator != tx.from).then_some(creator),
+                create_type,
+            }));
+        }
+    }
+
+    Err(GrauxError::Decode(format!(
+        "code for {contract:?} appears in block {block_number} but no transaction there creates it"
+    )))
+}
+
+async fn resolve<M>(inner: &M, block: BlockNumber) -> GrauxResult<u64>
+where
+    M: Middleware,
+    GrauxError: From<M::Error>,
+{
+    match block {
+        BlockNumber::Number(number) => Ok(number.as_u64()),
+        BlockNumber::Earliest => Ok(0),
+        BlockNumber::Latest => Ok(inner.get_block_number().await?.as_u64()),
+        tag => inner
+            .get_block(tag)
+            .await?
+            .and_then(|block| block.number)
+            .map(|number| number.as_u64())
+            .ok_or_else(|| GrauxError::InvalidArgument(format!("the node has no {tag} block"))),
+    }
+}
+
+async fn has_code<M>(inner: &M, contract: Address, block: u64) -> GrauxResult<bool>
+where
+    M: Middleware,
+    GrauxError: From<M::Error>,
+{
+    let block = BlockId::Number(BlockNumber::Number(block.into()));
+    Ok(!inner.get_code(contract, Some(block)).await?.is_empty())
+}
+
+/// The successful `CREATE`/`CREATE2` frame that produced `contract`, as (creator, type).
+fn find_create(frame: &CallFrame, contract: Address) -> Option<(Address, CallType)> {
+    let mut found = None;
+    frame.walk(|_, frame| {
+        let is_create = matches!(frame.call_type, CallType::Create | CallType::Create2);
+        if found.is_none() && is_create && frame.to == Some(contract) && !frame.is_reverted() {
+            found = Some((frame.from, frame.call_type));
+        }
+    });
+    found
+}
+
+#[cfg(test)]
+mod tests {
+    use ethers_core::types::{Block, Bytes, Transaction};
+    use ethers_providers::{MockProvider, Provider};
+    use serde_json::json;
+
+    use super::*;
+
+    const CONTRACT: Address = Address::repeat_byte(0xc0);
+
+    fn code(present: bool) -> Bytes {
+        if present {
+            Bytes::from(vec![0x60, 0x80])
+        } else {
+            Bytes::new()
+        }
+    }
+
+    /// Pushes `getCode` answers for the given blocks in request order.
+    fn push_code(mock: &MockProvider, answers: &[(u64, bool)]) {
+        for (_, present) in answers.iter().rev() {
+            mock.push::<Bytes, _>(code(*present)).unwrap();
+        }
+    }
+
+    fn assert_code_requests(mock: &MockProvider, contract: Address, answers: &[(u64, bool)]) {
+        for (block, _) in answers {
+            let block = BlockId::Number(BlockNumber::Number((*block).into()));
+            mock.assert_request("eth_getCode", (contract, block)).unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn finds_a_direct_deployment() {
+        let (provider, mock) = Provider::mocked();
+        let deployer = Address::repeat_byte(0xd0);
+        let contract = get_contract_address(deployer, 5);
+        let creation = Transaction {
+            hash: TxHash::repeat_byte(3),
+            from: deployer,
+            nonce: 5.into(),
+            to: None,
+            ..Default::default()
+        };
+        let other = Transaction {
+            from: deployer,
+            nonce: 4.into(),
+            to: Some(contract),
+            ..Default::default()
+        };
+
+        mock.push::<Block<Transaction>, _>(Block {
+            number: Some(3.into()),
+            transactions: vec![other, creation],
+            ..Default::default()
+        })
+        .unwrap();
+        let answers = [(8, true), (0, false), (4, true), (2, false), (3, true)];
+        push_code(&mock, &answers);
+        mock.push::<U64, _>(U64::from(8)).unwrap();
+
+        let deployment = find_contract_deployer(&provider, contract, None, None).await.unwrap().unwrap();
+        mock.assert_request("eth_blockNumber", ()).unwrap();
+        assert_code_requests(&mock, contract, &answers);
+        assert_eq!(deployment.block_number, 3);
+        assert_eq!(deployment.deployer, deployer);
+        assert_eq!(deployment.transaction_hash, TxHash::repeat_byte(3));
+        assert_eq!(deployment.factory, None);
+        assert_eq!(deployment.create_type, CallType::Create);
+    }
+
+    #[tokio::test]
+    async fn finds_a_factory_deployment_within_bounds() {
+        let (provider, mock) = Provider::mocked();
+        let (sender, factory) = (Address::repeat_byte(0xd0), Address::repeat_byte(0xfa));
+        let tx = Transaction {
+            hash: TxHash::repeat_byte(7),
+            from: sender,
+            to: Some(factory),
+            ..Default::default()
+        };
+
+        mock.push::<Vec<serde_json::Value>, _>(vec![json!({"result": {
+            "type": "CALL", "from": sender, "to": factory,
+            "calls": [
+                {"type": "CREATE2", "from": factory, "to": CONTRACT, "error": "execution reverted"},
+                {"type": "CREATE2", "from": factory, "to": CONTRACT}
+            ]
+        }})])
+        .unwrap();
+        mock.push::<Block<Transaction>, _>(Block {
+            number: Some(11.into()),
+            transactions: vec![tx],
+            ..Default::default()
+        })
+        .unwrap();
+        let answers = [(12, true), (10, false), (11, true)];
+        push_code(&mock, &answers);
+
+        let range = (Some(BlockNumber::from(10)), Some(BlockNumber::from(12)));
+        let deployment = find_contract_deployer(&provider, CONTRACT, range.0, range.1).await.unwrap().unwrap();
+        assert_eq!(deployment.block_number, 11);
+        assert_eq!(deployment.deployer, sender);
+        assert_eq!(deployment.factory, Some(factory));
+        assert_eq!(deployment.create_type, CallType::Create2);
+        assert_code_requests(&mock, CONTRACT, &answers);
+    }
+
+    #[tokio::test]
+    async fn genesis_and_missing_contracts_have_no_deployment() {
+        let (provider, mock) = Provider::mocked();
+        let answers = [(20, true), (0, true)];
+        push_code(&mock, &answers);
+        mock.push::<U64, _>(U64::from(20)).unwrap();
+        assert_eq!(find_contract_deployer(&provider, CONTRACT, None, None).await.unwrap(), None);
+        mock.assert_request("eth_blockNumber", ()).unwrap();
+        assert_code_requests(&mock, CONTRACT, &answers);
+
+        push_code(&mock, &[(5, false)]);
+        let none = find_contract_deployer(&provider, CONTRACT, None, Some(5.into())).await.unwrap();
+        assert_eq!(none, None);
+
+        let inverted = find_contract_deployer(&provider, CONTRACT, Some(6.into()), Some(5.into())).await;
+        assert!(matches!(inverted, Err(GrauxError::InvalidArgument(_))));
+    }
+}
@@ -0,0 +1,404 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use ethers_core::abi::{self, ParamType, Token};
+use ethers_core::types::transaction::eip2718::TypedTransaction;
+use ethers_core::types::{Address, Bytes, TransactionRequest, H160, H256};
+use ethers_core::utils::keccak256;
+use ethers_providers::Middleware;
+use serde_json::Value;
+
+use crate::error::{GrauxError, GrauxResult};
+
+/// ENS registry, deployed at the same address on mainnet and the public testnets
+/// (`0x00000000000C2E074eC69A0dFb2997BA6C7d2e1e`).
+pub const ENS_REGISTRY: Address = H160([
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x0c, 0x2e, 0x07, 0x4e, 0xc6, 0x9a, 0x0d, 0xfb, 0x29, 0x97, 0xba, 0x6c, 0x7d, 0x2e,
+    0x1e,
+]);
+
+/// `resolver(bytes32)` on the registry.
+const RESOLVER_SELECTOR: [u8; 4] = [0x01, 0x78, 0xb8, 0xbf];
+/// `addr(bytes32)` on a resolver.
+const ADDR_SELECTOR: [u8; 4] = [0x3b, 0x3b, 0x57, 0xde];
+/// `name(bytes32)` on a reverse resolver.
+const NAME_SELECTOR: [u8; 4] = [0x69, 0x1f, 0x34, 0x31];
+/// `text(bytes32,string)` on a resolver.
+const TEXT_SELECTOR: [u8; 4] = [0x59, 0xd1, 0xd4, 0x3c];
+
+/// EIP-137 namehash of a normalized ENS name. Labels are hashed as given; see `EnsResolver`
+/// for what normalization is done.
+pub fn namehash(name: &str) -> H256 {
+    let mut node = [0u8; 32];
+    if name.is_empty() {
+        return H256(node);
+    }
+    for label in name.rsplit('.') {
+        let mut preimage = [0u8; 64];
+        preimage[..32].copy_from_slice(&node);
+        preimage[32..].copy_from_slice(&keccak256(label.as_bytes()));
+        node = keccak256(preimage);
+    }
+    H256(node)
+}
+
+/// Whether `value` looks like an ENS name rather than a hex address.
+pub fn is_ens_name(value: &str) -> bool {
+    !value.starts_with("0x") && value.contains('.') && !value.starts_with('.') && !value.ends_with('.')
+}
+
+struct Cached<T> {
+    value: T,
+    at: Instant,
+}
+
+#[derive(Default)]
+struct Cache {
+    addresses: HashMap<String, Cached<Option<Address>>>,
+    names: HashMap<Address, Cached<Option<String>>>,
+    texts: HashMap<(String, String), Cached<Option<String>>>,
+}
+
+impl Cache {
+    fn evict(&mut self, ttl: Duration, capacity: usize) {
+        evict(&mut self.addresses, ttl, capacity);
+        evict(&mut self.names, ttl, capacity);
+        evict(&mut self.texts, ttl, capacity);
+    }
+}
+
+/// Drops expired entries once `map` is over `capacity`, then the oldest ones until it fits.
+fn evict<K: Clone + Eq + Hash, V>(map: &mut HashMap<K, Cached<V>>, ttl: Duration, capacity: usize) {
+    if map.len() <= capacity {
+        return;
+    }
+    map.retain(|_, cached| cached.at.elapsed() < ttl);
+    while map.len() > capacity {
+        let Some(oldest) = map.iter().min_by_key(|(_, cached)| cached.at).map(|(key, _)| key.clone()) else {
+            break;
+        };
+        map.remove(&oldest);
+    }
+}
+
+/// Resolves ENS names through the registry and resolver contracts, caching results for
+/// `ttl`. One resolver is built by `GrauxProvider` and shared with the namespaces through
+/// an `Arc`, so they all use the same cache.
+///
+/// Names are only lowercased, not normalized according to ENSIP-15, so names with other
+/// non-ASCII characters or emoji must be normalized by the caller. Wildcard resolution
+/// (ENSIP-10) and offchain resolvers (CCIP-read, EIP-3668) are not supported: names that
+/// rely on them resolve to `None` or fail with the resolver's revert.
+pub struct EnsResolver<M> {
+    inner: M,
+    registry: Address,
+    ttl: Duration,
+    capacity: usize,
+    cache: Mutex<Cache>,
+}
+
+impl<M> EnsResolver<M>
+where
+    M: Middleware,
+    GrauxError: From<M::Error>,
+{
+    pub fn new(inner: M) -> Self {
+        EnsResolver {
+            inner,
+            registry: ENS_REGISTRY,
+            ttl: Duration::from_secs(300),
+            capacity: 1_024,
+            cache: Mutex::new(Cache::default()),
+        }
+    }
+
+    /// How long lookups, including negative ones, are cached. Defaults to 5 minutes.
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    /// Entries kept per kind of lookup (addresses, names, text records). Defaults to 1024.
+    pub fn with_cache_capacity(mut self, capacity: usize) -> Self {
+        self.capacity = capacity;
+        self
+    }
+
+    /// Uses a registry other than the canonical one, e.g. on a private deployment or a
+    /// network with its own ENS fork.
+    pub fn with_registry(mut self, registry: Address) -> Self {
+        self.registry = registry;
+        self
+    }
+
+    pub fn clear_cache(&self) {
+        *self.cache.lock().expect("ENS cache lock poisoned") = Cache::default();
+    }
+
+    /// Accepts either a hex address or an ENS name, as taken by the `address_or_name`
+    /// parameters of the namespaces.
+    pub async fn resolve(&self, address_or_name: &str) -> GrauxResult<Address> {
+        if let Ok(address) = address_or_name.parse::<Address>() {
+            return Ok(address);
+        }
+        if !is_ens_name(address_or_name) {
+            return Err(GrauxError::InvalidArgument(format!(
+                "`{address_or_name}` is neither an address nor an ENS name"
+            )));
+        }
+        self.resolve_name(address_or_name)
+            .await?
+            .ok_or_else(|| GrauxError::InvalidArgument(format!("ENS name `{address_or_name}` does not resolve")))
+    }
+
+    /// Replaces ENS names in the `from` and `to` fields of a JSON transaction object with the
+    /// addresses they resolve to, for RPC methods that only accept addresses.
+    pub async fn resolve_transaction(&self, mut transaction: Value) -> GrauxResult<Value> {
+        for field in ["from", "to"] {
+            let name = transaction.get(field).and_then(Value::as_str).filter(|value| is_ens_name(value));
+            let Some(name) = name.map(str::to_owned) else {
+                continue;
+            };
+            let address = self.resolve(&name).await?;
+            transaction[field] = Value::String(format!("{address:?}"));
+        }
+        Ok(transaction)
+    }
+
+    /// Address an ENS name points to, or `None` if it has no resolver or address record.
+    pub async fn resolve_name(&self, name: &str) -> GrauxResult<Option<Address>> {
+        let name = name.to_lowercase();
+        if let Some(address) = self.cached(|cache| cache.addresses.get(&name).map(|c| (c.value, c.at))) {
+            return Ok(address);
+        }
+
+        let node = namehash(&name);
+        let address = match self.resolver(node).await? {
+            Some(resolver) => {
+                let output = self.call(resolver, ADDR_SELECTOR, &[Token::FixedBytes(node.0.to_vec())]).await?;
+                decode_address(&output).filter(|address| !address.is_zero())
+            }
+            None => None,
+        };
+
+        self.store(|cache, at| cache.addresses.insert(name, Cached { value: address, at }));
+        Ok(address)
+    }
+
+    /// Primary ENS name of `address`. The name is only returned if it resolves back to
+    /// `address`, since anyone can set any reverse record.
+    pub async fn lookup_address(&self, address: Address) -> GrauxResult<Option<String>> {
+        if let Some(name) = self.cached(|cache| cache.names.get(&address).map(|c| (c.value.clone(), c.at))) {
+            return Ok(name);
+        }
+
+        let reverse = format!("{}.addr.reverse", hex_lower(address.as_bytes()));
+        let node = namehash(&reverse);
+        let name = match self.resolver(node).await? {
+            Some(resolver) => {
+                let output = self.call(resolver, NAME_SELECTOR, &[Token::FixedBytes(node.0.to_vec())]).await?;
+                decode_string(&output).filter(|name| !name.is_empty())
+            }
+            None => None,
+        };
+        let name = match name {
+            Some(name) if self.resolve_name(&name).await? == Some(address) => Some(name),
+            _ => None,
+        };
+
+        let value = name.clone();
+        self.store(|cache, at| cache.names.insert(address, Cached { value, at }));
+        Ok(name)
+    }
+
+    /// A text record of `name`, e.g. `"url"`, `"com.twitter"` or `"avatar"`.
+    pub async fn text(&self, name: &str, key: &str) -> GrauxResult<Option<String>> {
+        let cache_key = (name.to_lowercase(), key.to_owned());
+        if let Some(text) = self.cached(|cache| cache.texts.get(&cache_key).map(|c| (c.value.clone(), c.at))) {
+            return Ok(text);
+        }
+
+        let node = namehash(&cache_key.0);
+        let text = match self.resolver(node).await? {
+            Some(resolver) => {
+                let params = [Token::FixedBytes(node.0.to_vec()), Token::String(key.to_owned())];
+                let output = self.call(resolver, TEXT_SELECTOR, &params).await?;
+                decode_string(&output).filter(|text| !text.is_empty())
+            }
+            None => None,
+        };
+
+        let value = text.clone();
+        self.store(|cache, at| cache.texts.insert(cache_key, Cached { value, at }));
+        Ok(text)
+    }
+
+    /// The `avatar` text record: an `https://`, `ipfs://` or `data:` URI, or an NFT reference
+    /// such as `eip155:1/erc721:<contract>/<token id>`, returned as stored.
+    pub async fn avatar(&self, name: &str) -> GrauxResult<Option<String>> {
+        self.text(name, "avatar").await
+    }
+
+    async fn resolver(&self, node: H256) -> GrauxResult<Option<Address>> {
+        let output = self
+            .call(self.registry, RESOLVER_SELECTOR, &[Token::FixedBytes(node.0.to_vec())])
+            .await?;
+        // The registry always returns a word; no output means there is no contract there.
+        if output.is_empty() {
+            return Err(GrauxError::Config(format!(
+                "no ENS registry at {:?} on this network; set one with `with_registry`",
+                self.registry
+            )));
+        }
+        Ok(decode_address(&output).filter(|resolver| !resolver.is_zero()))
+    }
+
+    async fn call(&self, to: Address, selector: [u8; 4], params: &[Token]) -> GrauxResult<Bytes> {
+        let mut data = selector.to_vec();
+        data.extend(abi::encode(params));
+        let tx: TypedTransaction = TransactionRequest::new().to(to).data(data).into();
+        Ok(self.inner.call(&tx, None).await?)
+    }
+
+    fn cached<T>(&self, get: impl FnOnce(&Cache) -> Option<(T, Instant)>) -> Option<T> {
+        let cache = self.cache.lock().expect("ENS cache lock poisoned");
+        get(&cache).and_then(|(value, at)| (at.elapsed() < self.ttl).then_some(value))
+    }
+
+    fn store<R>(&self, put: impl FnOnce(&mut Cache, Instant) -> R) {
+        let mut cache = self.cache.lock().expect("ENS cache lock poisoned");
+        put(&mut cache, Instant::now());
+        cache.evict(self.ttl, self.capacity);
+    }
+}
+
+fn decode_address(output: &[u8]) -> Option<Address> {
+    abi::decode(&[ParamType::Address], output).ok()?.pop()?.into_address()
+}
+
+fn decode_string(output: &[u8]) -> Option<String> {
+    abi::decode(&[ParamType::String], output).ok()?.pop()?.into_string()
+}
+
+fn hex_lower(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use ethers_providers::{MockProvider, Provider};
+    use serde_json::json;
+
+    use super::*;
+
+    const RESOLVER: Address = Address::repeat_byte(0x5e);
+    const OWNER: Address = Address::repeat_byte(0x0a);
+
+    fn resolver() -> (EnsResolver<Provider<MockProvider>>, MockProvider) {
+        let (provider, mock) = Provider::mocked();
+        (EnsResolver::new(provider), mock)
+    }
+
+    fn returns(mock: &MockProvider, tokens: &[Token]) {
+        mock.push::<Bytes, _>(Bytes::from(abi::encode(tokens))).unwrap();
+    }
+
+    /// Answers for a forward resolution, pushed in reverse since the mock is last-in first-out.
+    fn resolves_to(mock: &MockProvider, address: Address) {
+        returns(mock, &[Token::Address(address)]);
+        returns(mock, &[Token::Address(RESOLVER)]);
+    }
+
+    #[test]
+    fn computes_namehashes() {
+        assert_eq!(namehash(""), H256::zero());
+        assert_eq!(
+            namehash("eth"),
+            "0x93cdeb708b7545dc668eb9280176169d1c33cfd8ed6f04690a0bcc88a93fc4ae".parse().unwrap()
+        );
+        assert_eq!(
+            namehash("foo.eth"),
+            "0xde9b09fd7c5f901e23a3f19fecc54828e9c848539801e86591bd9801b019f84f".parse().unwrap()
+        );
+    }
+
+    #[test]
+    fn tells_names_from_addresses() {
+        assert!(is_ens_name("vitalik.eth"));
+        assert!(is_ens_name("sub.domain.eth"));
+        assert!(!is_ens_name("0x5e5e5e5e5e5e5e5e5e5e5e5e5e5e5e5e5e5e5e5e"));
+        assert!(!is_ens_name("eth"));
+        assert!(!is_ens_name(".eth"));
+        assert!(!is_ens_name("vitalik."));
+    }
+
+    #[tokio::test]
+    async fn resolves_and_caches_names() {
+        let (ens, mock) = resolver();
+        resolves_to(&mock, OWNER);
+
+        assert_eq!(ens.resolve("Vitalik.ETH").await.unwrap(), OWNER);
+        // Served from the cache: the mock has no answers left.
+        assert_eq!(ens.resolve_name("vitalik.eth").await.unwrap(), Some(OWNER));
+        assert_eq!(ens.resolve(&format!("{OWNER:?}")).await.unwrap(), OWNER);
+        assert!(matches!(ens.resolve("not-a-name").await, Err(GrauxError::InvalidArgument(_))));
+    }
+
+    #[tokio::test]
+    async fn names_without_a_resolver_do_not_resolve() {
+        let (ens, mock) = resolver();
+        returns(&mock, &[Token::Address(Address::zero())]);
+        assert!(matches!(ens.resolve("nobody.eth").await, Err(GrauxError::InvalidArgument(_))));
+    }
+
+    #[tokio::test]
+    async fn a_missing_registry_is_a_config_error() {
+        let (ens, mock) = resolver();
+        mock.push::<Bytes, _>(Bytes::new()).unwrap();
+        assert!(matches!(ens.resolve_name("vitalik.eth").await, Err(GrauxError::Config(_))));
+    }
+
+    #[tokio::test]
+    async fn reverse_lookups_must_resolve_back() {
+        let (ens, mock) = resolver();
+        resolves_to(&mock, Address::repeat_byte(0xee));
+        returns(&mock, &[Token::String("spoof.eth".to_owned())]);
+        returns(&mock, &[Token::Address(RESOLVER)]);
+        assert_eq!(ens.lookup_address(OWNER).await.unwrap(), None);
+
+        let (ens, mock) = resolver();
+        resolves_to(&mock, OWNER);
+        returns(&mock, &[Token::String("owner.eth".to_owned())]);
+        returns(&mock, &[Token::Address(RESOLVER)]);
+        assert_eq!(ens.lookup_address(OWNER).await.unwrap().as_deref(), Some("owner.eth"));
+    }
+
+    #[tokio::test]
+    async fn resolves_names_in_transactions() {
+        let (ens, mock) = resolver();
+        resolves_to(&mock, OWNER);
+        let tx = ens
+            .resolve_transaction(json!({"from": format!("{RESOLVER:?}"), "to": "owner.eth", "data": "0x"}))
+            .await
+            .unwrap();
+        assert_eq!(tx, json!({"from": format!("{RESOLVER:?}"), "to": format!("{OWNER:?}"), "data": "0x"}));
+    }
+
+    #[tokio::test]
+    async fn evicts_the_oldest_entries_beyond_capacity() {
+        let (ens, mock) = resolver();
+        let ens = ens.with_cache_capacity(2);
+        for (index, name) in ["a.eth", "b.eth", "c.eth"].iter().enumerate() {
+            resolves_to(&mock, Address::repeat_byte(index as u8 + 1));
+            ens.resolve_name(name).await.unwrap();
+            std::thread::sleep(Duration::from_millis(2));
+        }
+
+        let cache = ens.cache.lock().unwrap();
+        let mut names: Vec<&String> = cache.addresses.keys().collect();
+        names.sort();
+        assert_eq!(names, ["b.eth", "c.eth"]);
+    }
+}
@@ -0,0 +1,270 @@
+use std::time::{Duration, SystemTime};
+
+use ethers_providers::{JsonRpcError, ProviderError};
+use ethers_signers::WalletError;
+use serde_json::Value;
+
+/// JSON-RPC error code returned by nodes when a call reverts.
+const EXECUTION_REVERTED_CODE: i64 = 3;
+
+/// JSON-RPC error code returned by Graux when the request rate limit is exceeded.
+const LIMIT_EXCEEDED_CODE: i64 = -32005;
+
+/// Result type returned by every Graux namespace.
+pub type GrauxResult<T> = Result<T, GrauxError>;
+
+/// Error type shared by the provider, `Graux`, `DebugNamespace` and `GrauxCoreNamespace`.
+#[derive(Debug, thiserror::Error)]
+pub enum GrauxError {
+    /// The request never produced a JSON-RPC response (connection, TLS, HTTP failure).
+    #[error("transport error: {0}")]
+    Transport(String),
+
+    /// The node answered with a JSON-RPC error object.
+    #[error("JSON-RPC error {code}: {message}")]
+    JsonRpc {
+        code: i64,
+        message: String,
+        data: Option<Value>,
+    },
+
+    /// The server answered with a non-success HTTP status.
+    #[error("HTTP {status}: {message}")]
+    Http { status: u16, message: String },
+
+    /// Graux rejected the request because the rate limit was hit.
+    #[error("rate limited: {message}")]
+    RateLimited {
+        message: String,
+        /// Delay requested by the server through `Retry-After`, if any.
+        retry_after: Option<Duration>,
+    },
+
+    /// The API key or auth token was rejected.
+    #[error("unauthorized: {0}")]
+    Unauthorized(String),
+
+    /// The SDK configuration is missing or invalid.
+    #[error("invalid configuration: {0}")]
+    Config(String),
+
+    #[error("no network detected")]
+    NoNetworkDetected,
+
+    #[error("invalid network: {0}")]
+    InvalidNetwork(String),
+
+    #[error("invalid API key: {0}")]
+    InvalidApiKey(String),
+
+    /// The response could not be decoded into the expected type.
+    #[error("failed to decode response: {0}")]
+    Decode(String),
+
+    /// The request did not complete within the configured `request_timeout`.
+    #[error("request timed out")]
+    Timeout,
+
+    /// A request argument was rejected before anything was sent.
+    #[error("invalid argument: {0}")]
+    InvalidArgument(String),
+
+    /// A chain reorganization went deeper than the tracked block window.
+    #[error("reorg deeper than the {0}-block tracking window")]
+    ReorgTooDeep(usize),
+
+    /// The call reverted; `data` holds the raw revert payload when the node returned one.
+    #[error("execution reverted: {message}")]
+    ExecutionReverted {
+        message: String,
+        data: Option<String>,
+    },
+
+    /// The node could not trace one transaction of a block.
+    #[error("trace failed: {0}")]
+    TraceFailed(String),
+
+    /// The local signer could not load its key or sign.
+    #[error("signer error: {0}")]
+    Signer(String),
+
+    /// The provider refused the request itself, e.g. an RPC or node client it does not support.
+    #[error("provider error: {0}")]
+    Provider(String),
+}
+
+impl GrauxError {
+    /// Builds the matching variant from a JSON-RPC error object.
+    pub fn from_json_rpc(err: &JsonRpcError) -> Self {
+        if err.code == EXECUTION_REVERTED_CODE || err.message.starts_with("execution reverted") {
+            return GrauxError::ExecutionReverted {
+                message: err.message.clone(),
+                data: err.data.as_ref().and_then(|d| d.as_str()).map(str::to_owned),
+            };
+        }
+        if err.code == LIMIT_EXCEEDED_CODE {
+            return GrauxError::RateLimited {
+                message: err.message.clone(),
+                retry_after: None,
+            };
+        }
+
+        GrauxError::JsonRpc {
+            code: err.code,
+            message: err.message.clone(),
+            data: err.data.clone(),
+        }
+    }
+
+    /// Builds the matching variant from a failed HTTP response.
+    pub fn from_http_status(status: u16, retry_after: Option<&str>, message: String) -> Self {
+        match status {
+            401 | 403 => GrauxError::Unauthorized(message),
+            429 => GrauxError::RateLimited {
+                message,
+                retry_after: retry_after.and_then(parse_retry_after),
+            },
+            _ => GrauxError::Http { status, message },
+        }
+    }
+}
+
+/// Parses a `Retry-After` header given either as delta-seconds or as an HTTP date.
+pub fn parse_retry_after(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let at = httpdate::parse_http_date(value).ok()?;
+    Some(at.duration_since(SystemTime::now()).unwrap_or(Duration::ZERO))
+}
+
+impl From<ProviderError> for GrauxError {
+    fn from(err: ProviderError) -> Self {
+        match err {
+            ProviderError::JsonRpcClientError(inner) => {
+                if let Some(rpc_err) = inner.as_error_response() {
+                    GrauxError::from_json_rpc(rpc_err)
+                } else if let Some(serde_err) = inner.as_serde_error() {
+                    GrauxError::Decode(serde_err.to_string())
+                } else {
+                    GrauxError::Transport(inner.to_string())
+                }
+            }
+            ProviderError::HTTPError(http_err) => match http_err.status() {
+                Some(status) => {
+                    GrauxError::from_http_status(status.as_u16(), None, http_err.to_string())
+                }
+                None if http_err.is_timeout() => GrauxError::Timeout,
+                None => GrauxError::Transport(http_err.to_string()),
+            },
+            ProviderError::SerdeJson(serde_err) => GrauxError::Decode(serde_err.to_string()),
+            ProviderError::HexError(hex_err) => GrauxError::Decode(hex_err.to_string()),
+            ProviderError::EnsError(_) | ProviderError::EnsNotOwned(_) => GrauxError::InvalidArgument(err.to_string()),
+            ProviderError::SignerUnavailable => GrauxError::Signer(err.to_string()),
+            ProviderError::CustomError(_) | ProviderError::UnsupportedRPC | ProviderError::UnsupportedNodeClient => {
+                GrauxError::Provider(err.to_string())
+            }
+        }
+    }
+}
+
+impl From<WalletError> for GrauxError {
+    fn from(err: WalletError) -> Self {
+        GrauxError::Signer(err.to_string())
+    }
+}
+
+impl From<serde_json::Error> for GrauxError {
+    fn from(err: serde_json::Error) -> Self {
+        GrauxError::Decode(err.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rpc_error(code: i64, message: &str, data: Option<Value>) -> JsonRpcError {
+        JsonRpcError {
+            code,
+            message: message.to_owned(),
+            data,
+        }
+    }
+
+    #[test]
+    fn maps_reverts_by_code_and_message() {
+        let data = Value::String("0x08c379a0".to_owned());
+        let err = GrauxError::from_json_rpc(&rpc_error(3, "execution reverted: nope", Some(data)));
+        assert!(matches!(
+            err,
+            GrauxError::ExecutionReverted { ref message, data: Some(ref data) }
+                if message == "execution reverted: nope" && data == "0x08c379a0"
+        ));
+
+        let err = GrauxError::from_json_rpc(&rpc_error(-32000, "execution reverted", None));
+        assert!(matches!(err, GrauxError::ExecutionReverted { data: None, .. }));
+    }
+
+    #[test]
+    fn maps_limit_exceeded_to_rate_limited() {
+        let err = GrauxError::from_json_rpc(&rpc_error(LIMIT_EXCEEDED_CODE, "slow down", None));
+        assert!(matches!(err, GrauxError::RateLimited { retry_after: None, .. }));
+    }
+
+    #[test]
+    fn keeps_other_json_rpc_errors() {
+        let err = GrauxError::from_json_rpc(&rpc_error(-32601, "method not found", None));
+        assert!(matches!(err, GrauxError::JsonRpc { code: -32601, .. }));
+    }
+
+    #[test]
+    fn maps_http_statuses() {
+        assert!(matches!(
+            GrauxError::from_http_status(401, None, String::new()),
+            GrauxError::Unauthorized(_)
+        ));
+        assert!(matches!(
+            GrauxError::from_http_status(429, Some("7"), String::new()),
+            GrauxError::RateLimited { retry_after: Some(delay), .. } if delay == Duration::from_secs(7)
+        ));
+        assert!(matches!(
+            GrauxError::from_http_status(502, None, String::new()),
+            GrauxError::Http { status: 502, .. }
+        ));
+    }
+
+    #[test]
+    fn keeps_local_provider_errors_out_of_transport() {
+        let cases = [
+            ProviderError::EnsError("nobody.eth".to_owned()),
+            ProviderError::EnsNotOwned("nobody.eth".to_owned()),
+            ProviderError::CustomError("boom".to_owned()),
+            ProviderError::UnsupportedRPC,
+            ProviderError::UnsupportedNodeClient,
+            ProviderError::SignerUnavailable,
+        ];
+        for case in cases {
+            let err = GrauxError::from(case);
+            assert!(!matches!(err, GrauxError::Transport(_)), "{err:?}");
+            assert!(!crate::retry::is_retryable(&err), "{err:?}");
+        }
+    }
+
+    #[tokio::test]
+    async fn maps_connection_failures_to_transport() {
+        // Nothing listens on port 9 of the loopback interface, so the connection is refused.
+        let provider = ethers_providers::Provider::<ethers_providers::Http>::try_from("http://127.0.0.1:9").unwrap();
+        let err = GrauxError::from(provider.request::<_, u64>("eth_chainId", ()).await.unwrap_err());
+        assert!(matches!(err, GrauxError::Transport(_)), "{err:?}");
+        assert!(crate::retry::is_retryable(&err));
+    }
+
+    #[test]
+    fn parses_retry_after() {
+        assert_eq!(parse_retry_after(" 120 "), Some(Duration::from_secs(120)));
+        assert_eq!(parse_retry_after("Wed, 21 Oct 2015 07:28:00 GMT"), Some(Duration::ZERO));
+        assert_eq!(parse_retry_after("soon"), None);
+    }
+}
@@ -0,0 +1,344 @@
+use ethers_core::types::transaction::eip2718::TypedTransaction;
+use ethers_core::types::{BlockNumber, Eip1559TransactionRequest, FeeHistory, U256};
+use ethers_providers::Middleware;
+
+use crate::error::{GrauxError, GrauxResult};
+
+/// Which of the oracle's suggestions to use when filling a transaction.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum FeeSpeed {
+    Slow,
+    #[default]
+    Standard,
+    Fast,
+}
+
+/// Settings for `FeeOracle`.
+#[derive(Debug, Clone)]
+pub struct FeeOracleConfig {
+    /// Number of recent blocks passed to `eth_feeHistory`.
+    pub block_count: u64,
+    /// Reward percentiles used for the slow, standard and fast priority fees.
+    pub reward_percentiles: [f64; 3],
+    /// `maxFeePerGas` is `base_fee * base_fee_multiplier + priority_fee`, leaving room for
+    /// the base fee to rise over the next few blocks.
+    pub base_fee_multiplier: u64,
+    /// Lower bound for suggested priority fees, in wei.
+    pub min_priority_fee: U256,
+}
+
+impl Default for FeeOracleConfig {
+    fn default() -> Self {
+        FeeOracleConfig {
+            block_count: 20,
+            reward_percentiles: [10.0, 50.0, 90.0],
+            base_fee_multiplier: 2,
+            min_priority_fee: U256::from(1_000_000u64),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FeeEstimate {
+    pub max_fee_per_gas: U256,
+    pub max_priority_fee_per_gas: U256,
+}
+
+/// Fee suggestions for the next block.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FeeSuggestions {
+    Eip1559 {
+        base_fee_per_gas: U256,
+        slow: FeeEstimate,
+        standard: FeeEstimate,
+        fast: FeeEstimate,
+    },
+    /// The network does not support EIP-1559.
+    Legacy { gas_price: U256 },
+}
+
+impl FeeSuggestions {
+    pub fn estimate(&self, speed: FeeSpeed) -> Option<FeeEstimate> {
+        match self {
+            FeeSuggestions::Eip1559 {
+                slow, standard, fast, ..
+            } => Some(match speed {
+                FeeSpeed::Slow => *slow,
+                FeeSpeed::Standard => *standard,
+                FeeSpeed::Fast => *fast,
+            }),
+            FeeSuggestions::Legacy { .. } => None,
+        }
+    }
+}
+
+/// Suggests EIP-1559 fees from `eth_feeHistory`, falling back to `eth_gasPrice` on networks
+/// without a base fee.
+#[derive(Debug, Clone)]
+pub struct FeeOracle<M> {
+    inner: M,
+    config: FeeOracleConfig,
+}
+
+impl<M> FeeOracle<M>
+where
+    M: Middleware,
+    GrauxError: From<M::Error>,
+{
+    pub fn new(inner: M, config: FeeOracleConfig) -> Self {
+        FeeOracle { inner, config }
+    }
+
+    pub async fn suggest(&self) -> GrauxResult<FeeSuggestions> {
+        let history = match self
+            .inner
+            .fee_history(self.config.block_count, BlockNumber::Latest, &self.config.reward_percentiles)
+            .await
+        {
+            Ok(history) => history,
+            Err(err) => {
+                let err = GrauxError::from(err);
+                // Nodes without EIP-1559 commonly reject `eth_feeHistory` outright.
+                if is_unsupported_method(&err) {
+                    return self.legacy().await;
+                }
+                return Err(err);
+            }
+        };
+
+        let Some(base_fee_per_gas) = history.base_fee_per_gas.last().copied().filter(|fee| !fee.is_zero())
+        else {
+            return self.legacy().await;
+        };
+
+        let estimate = |column: usize| {
+            let priority = median_reward(&history, column).max(self.config.min_priority_fee);
+            FeeEstimate {
+                max_fee_per_gas: base_fee_per_gas * self.config.base_fee_multiplier + priority,
+                max_priority_fee_per_gas: priority,
+            }
+        };
+
+        Ok(FeeSuggestions::Eip1559 {
+            base_fee_per_gas,
+            slow: estimate(0),
+            standard: estimate(1),
+            fast: estimate(2),
+        })
+    }
+
+    /// Fills in whichever fee fields `tx` is missing. A legacy transaction without a gas
+    /// price is upgraded to an EIP-1559 transaction when the network supports it.
+    ///
+    /// Fees set by the caller are kept. A `max_fee_per_gas` filled in next to a caller's
+    /// priority fee covers that priority fee, and a filled-in priority fee never exceeds the
+    /// caller's `max_fee_per_gas`. EIP-2930 transactions get the legacy gas price.
+    pub async fn fill(&self, tx: &mut TypedTransaction, speed: FeeSpeed) -> GrauxResult<()> {
+        let needs_fees = match tx {
+            TypedTransaction::Eip1559(inner) => {
+                inner.max_fee_per_gas.is_none() || inner.max_priority_fee_per_gas.is_none()
+            }
+            TypedTransaction::Legacy(inner) => inner.gas_price.is_none(),
+            TypedTransaction::Eip2930(inner) => inner.tx.gas_price.is_none(),
+        };
+        if !needs_fees {
+            return Ok(());
+        }
+
+        if let TypedTransaction::Eip2930(inner) = tx {
+            inner.tx.gas_price = Some(self.inner.get_gas_price().await?);
+            return Ok(());
+        }
+
+        match (self.suggest().await?, tx) {
+            (FeeSuggestions::Legacy { gas_price }, tx) => {
+                tx.set_gas_price(gas_price);
+            }
+            (suggestions @ FeeSuggestions::Eip1559 { base_fee_per_gas, .. }, tx) => {
+                let estimate = suggestions.estimate(speed).expect("EIP-1559 suggestions have estimates");
+                match tx {
+                    TypedTransaction::Eip1559(inner) => {
+                        let (max_fee, priority_fee) = complete_fees(
+                            inner.max_fee_per_gas,
+                            inner.max_priority_fee_per_gas,
+                            base_fee_per_gas * self.config.base_fee_multiplier,
+                            estimate,
+                        );
+                        inner.max_fee_per_gas = Some(max_fee);
+                        inner.max_priority_fee_per_gas = Some(priority_fee);
+                    }
+                    TypedTransaction::Legacy(inner) => {
+                        *tx = TypedTransaction::Eip1559(Eip1559TransactionRequest {
+                            from: inner.from,
+                            to: inner.to.clone(),
+                            gas: inner.gas,
+                            value: inner.value,
+                            data: inner.data.clone(),
+                            nonce: inner.nonce,
+                            access_list: Default::default(),
+                            max_priority_fee_per_gas: Some(estimate.max_priority_fee_per_gas),
+                            max_fee_per_gas: Some(estimate.max_fee_per_gas),
+                            chain_id: inner.chain_id,
+                        });
+                    }
+                    TypedTransaction::Eip2930(_) => unreachable!("EIP-2930 transactions are filled above"),
+                }
+            }
+        }
+        Ok(())
+    }
+
+    async fn legacy(&self) -> GrauxResult<FeeSuggestions> {
+        let gas_price = self.inner.get_gas_price().await?;
+        Ok(FeeSuggestions::Legacy { gas_price })
+    }
+}
+
+/// Completes a partially set `(max_fee, priority_fee)` pair. `base_fee_headroom` is the part
+/// of `maxFeePerGas` reserved for the base fee.
+fn complete_fees(
+    max_fee: Option<U256>,
+    priority_fee: Option<U256>,
+    base_fee_headroom: U256,
+    estimate: FeeEstimate,
+) -> (U256, U256) {
+    match (max_fee, priority_fee) {
+        (Some(max_fee), Some(priority_fee)) => (max_fee, priority_fee),
+        (None, Some(priority_fee)) => (base_fee_headroom + priority_fee, priority_fee),
+        (Some(max_fee), None) => (max_fee, estimate.max_priority_fee_per_gas.min(max_fee)),
+        (None, None) => (estimate.max_fee_per_gas, estimate.max_priority_fee_per_gas),
+    }
+}
+
+/// Whether the node rejected a method because it does not implement it.
+fn is_unsupported_method(error: &GrauxError) -> bool {
+    let GrauxError::JsonRpc { code, message, .. } = error else {
+        return false;
+    };
+    let message = message.to_ascii_lowercase();
+    *code == -32601
+        || ["method not found", "not supported", "unsupported", "does not exist", "not available"]
+            .iter()
+            .any(|pattern| message.contains(pattern))
+}
+
+/// Median of one reward percentile column, ignoring empty blocks whose reward is always 0.
+fn median_reward(history: &FeeHistory, column: usize) -> U256 {
+    let mut rewards: Vec<U256> = history
+        .reward
+        .iter()
+        .zip(&history.gas_used_ratio)
+        .filter(|(_, ratio)| **ratio > 0.0)
+        .filter_map(|(rewards, _)| rewards.get(column).copied())
+        .collect();
+    if rewards.is_empty() {
+        return U256::zero();
+    }
+    rewards.sort();
+    rewards[rewards.len() / 2]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers_core::types::transaction::eip2930::AccessList;
+    use ethers_core::types::TransactionRequest;
+    use ethers_providers::{JsonRpcError, MockProvider, MockResponse, Provider};
+
+    const GWEI: u64 = 1_000_000_000;
+
+    fn oracle() -> (FeeOracle<Provider<MockProvider>>, MockProvider) {
+        let (provider, mock) = Provider::mocked();
+        (FeeOracle::new(provider, FeeOracleConfig::default()), mock)
+    }
+
+    fn gwei(amount: u64) -> U256 {
+        U256::from(amount * GWEI)
+    }
+
+    /// Three blocks with a 10 gwei base fee; the middle one is empty.
+    fn history() -> FeeHistory {
+        FeeHistory {
+            base_fee_per_gas: vec![gwei(10); 4],
+            gas_used_ratio: vec![0.5, 0.0, 0.7],
+            oldest_block: U256::from(100),
+            reward: vec![
+                vec![gwei(1), gwei(2), gwei(3)],
+                vec![U256::zero(); 3],
+                vec![gwei(1), gwei(4), gwei(5)],
+            ],
+        }
+    }
+
+    fn rpc_error(code: i64, message: &str) -> MockResponse {
+        MockResponse::Error(JsonRpcError {
+            code,
+            message: message.to_owned(),
+            data: None,
+        })
+    }
+
+    #[tokio::test]
+    async fn suggests_fees_from_non_empty_blocks() {
+        let (oracle, mock) = oracle();
+        mock.push(history()).unwrap();
+
+        let suggestions = oracle.suggest().await.unwrap();
+        assert_eq!(
+            suggestions.estimate(FeeSpeed::Standard),
+            Some(FeeEstimate {
+                max_fee_per_gas: gwei(24),
+                max_priority_fee_per_gas: gwei(4),
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn falls_back_to_gas_price_only_when_fee_history_is_unsupported() {
+        let (oracle, mock) = oracle();
+        // ethers retries `eth_feeHistory` once with a decimal block count before failing.
+        mock.push::<U256, _>(gwei(7)).unwrap();
+        for _ in 0..2 {
+            mock.push_response(rpc_error(-32601, "the method eth_feeHistory does not exist"));
+        }
+        assert_eq!(oracle.suggest().await.unwrap(), FeeSuggestions::Legacy { gas_price: gwei(7) });
+
+        for _ in 0..2 {
+            mock.push_response(rpc_error(-32005, "daily request count exceeded"));
+        }
+        assert!(matches!(oracle.suggest().await, Err(GrauxError::RateLimited { .. })));
+    }
+
+    #[tokio::test]
+    async fn max_fee_covers_a_caller_priority_fee() {
+        let (oracle, mock) = oracle();
+        mock.push(history()).unwrap();
+
+        let mut tx: TypedTransaction = Eip1559TransactionRequest::new().max_priority_fee_per_gas(gwei(50)).into();
+        oracle.fill(&mut tx, FeeSpeed::Standard).await.unwrap();
+        let TypedTransaction::Eip1559(inner) = tx else { unreachable!() };
+        assert_eq!(inner.max_priority_fee_per_gas, Some(gwei(50)));
+        assert_eq!(inner.max_fee_per_gas, Some(gwei(70)));
+    }
+
+    #[test]
+    fn filled_priority_fee_stays_under_a_caller_max_fee() {
+        let estimate = FeeEstimate {
+            max_fee_per_gas: gwei(24),
+            max_priority_fee_per_gas: gwei(4),
+        };
+        assert_eq!(complete_fees(Some(gwei(3)), None, gwei(20), estimate), (gwei(3), gwei(3)));
+        assert_eq!(complete_fees(Some(gwei(30)), None, gwei(20), estimate), (gwei(30), gwei(4)));
+        assert_eq!(complete_fees(None, None, gwei(20), estimate), (gwei(24), gwei(4)));
+    }
+
+    #[tokio::test]
+    async fn eip2930_transactions_get_the_legacy_gas_price() {
+        let (oracle, mock) = oracle();
+        mock.push::<U256, _>(gwei(9)).unwrap();
+
+        let mut tx = TypedTransaction::Eip2930(TransactionRequest::new().with_access_list(AccessList::default()));
+        oracle.fill(&mut tx, FeeSpeed::Fast).await.unwrap();
+        assert_eq!(tx.gas_price(), Some(gwei(9)));
+    }
+}
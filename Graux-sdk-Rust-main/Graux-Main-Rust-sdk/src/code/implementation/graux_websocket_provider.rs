@@ -0,0 +1,748 @@
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::future::Future;
+use std::marker::PhantomData;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use ethers_core::types::{Block, Filter, Log, TxHash, U256};
+use ethers_providers::{Http, JsonRpcClient, Provider, ProviderError, PubsubClient, Ws};
+use futures_util::{Stream, StreamExt};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::{json, Value};
+use tokio::sync::{broadcast, mpsc, RwLock};
+
+use super::GrauxConfig;
+use crate::backfill::{gap_free_blocks, gap_free_logs, GapFreeStream};
+use crate::error::{GrauxError, GrauxResult};
+use crate::reorg::{self, ReorgAwareStream, ReorgConfig};
+use crate::retry::RetryPolicy;
+use crate::subscriptions::{
+    MinedTransaction, MinedTransactionsFilter, PendingTransactionsFilter, TransactionNotification,
+};
+
+type NotificationStream = <Ws as PubsubClient>::NotificationStream;
+
+/// What an `eth_subscribe` call subscribes to.
+#[derive(Debug, Clone)]
+pub enum SubscriptionKind {
+    NewHeads,
+    Logs(Filter),
+    NewPendingTransactions,
+    /// Graux-enhanced pending transactions, filtered by sender/recipient.
+    GrauxPendingTransactions(PendingTransactionsFilter),
+    /// Graux-enhanced mined transactions, filtered by sender/recipient pairs.
+    GrauxMinedTransactions(MinedTransactionsFilter),
+}
+
+impl SubscriptionKind {
+    /// Parameters for `eth_subscribe`.
+    fn params(&self) -> Value {
+        match self {
+            SubscriptionKind::NewHeads => json!(["newHeads"]),
+            SubscriptionKind::Logs(filter) => json!(["logs", filter]),
+            SubscriptionKind::NewPendingTransactions => json!(["newPendingTransactions"]),
+            SubscriptionKind::GrauxPendingTransactions(filter) => {
+                json!(["graux_pendingTransactions", filter])
+            }
+            SubscriptionKind::GrauxMinedTransactions(filter) => {
+                json!(["graux_minedTransactions", filter])
+            }
+        }
+    }
+}
+
+/// Connection state changes, reported through `GrauxWebSocketProvider::connection_events`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionEvent {
+    /// The socket of `generation` dropped and a reconnect has started.
+    Disconnected { generation: u64 },
+    /// The socket was re-opened and every active subscription re-established.
+    Reconnected { generation: u64 },
+    /// Reconnecting failed for good; all subscription streams have ended.
+    Closed,
+}
+
+struct ActiveSubscription {
+    kind: SubscriptionKind,
+    server_id: Option<U256>,
+    sender: mpsc::UnboundedSender<Value>,
+}
+
+struct Connection {
+    ws: Option<Ws>,
+    generation: u64,
+    /// Set while a reconnect is replacing the socket of `generation`.
+    reconnecting: bool,
+}
+
+struct Shared {
+    url: String,
+    reconnect_policy: RetryPolicy,
+    connection: RwLock<Connection>,
+    subscriptions: Mutex<HashMap<u64, ActiveSubscription>>,
+    next_id: AtomicU64,
+    events: broadcast::Sender<ConnectionEvent>,
+}
+
+/// WebSocket provider with `eth_subscribe` support.
+///
+/// When the socket drops, the provider reconnects with backoff and re-issues `eth_subscribe`
+/// for every live `Subscription`; the streams handed out to callers keep working across
+/// reconnects.
+#[derive(Clone)]
+pub struct GrauxWebSocketProvider {
+    shared: Arc<Shared>,
+    /// HTTP endpoint used to backfill blocks and logs missed while disconnected.
+    http: Option<Provider<Http>>,
+}
+
+impl GrauxWebSocketProvider {
+    /// Connects to the endpoint of `config`: its custom `url` if set, the network endpoint for
+    /// its API key otherwise. Backfills go to the HTTP side of the same endpoint.
+    pub async fn new(config: &GrauxConfig) -> GrauxResult<Self> {
+        let (url, http_url) = match &config.url {
+            Some(url) => custom_endpoints(url)?,
+            None => {
+                let api_key = config.api_key()?;
+                (config.network.ws_url(api_key), config.network.http_url(api_key))
+            }
+        };
+
+        GrauxWebSocketProvider::connect(url, RetryPolicy::new(config.max_retries))
+            .await?
+            .with_backfill_url(&http_url)
+    }
+
+    /// Connects to `url`. `reconnect_policy` controls the backoff between reconnect attempts
+    /// and how many are made before the provider gives up.
+    pub async fn connect(url: impl Into<String>, reconnect_policy: RetryPolicy) -> GrauxResult<Self> {
+        let url = url.into();
+        let ws = open(&url).await?;
+        let (events, _) = broadcast::channel(16);
+
+        Ok(GrauxWebSocketProvider {
+            shared: Arc::new(Shared {
+                url,
+                reconnect_policy,
+                connection: RwLock::new(Connection {
+                    ws: Some(ws),
+                    generation: 0,
+                    reconnecting: false,
+                }),
+                subscriptions: Mutex::new(HashMap::new()),
+                next_id: AtomicU64::new(1),
+                events,
+            }),
+            http: None,
+        })
+    }
+
+    /// Sets the HTTP endpoint used by the gap-free subscriptions.
+    pub fn with_backfill_url(mut self, url: &str) -> GrauxResult<Self> {
+        let http = Provider::<Http>::try_from(url).map_err(|e| GrauxError::Config(e.to_string()))?;
+        self.http = Some(http);
+        Ok(self)
+    }
+
+    /// Sends a JSON-RPC request over the socket.
+    pub async fn request<T, R>(&self, method: &str, params: T) -> GrauxResult<R>
+    where
+        T: Debug + Serialize + Send + Sync,
+        R: DeserializeOwned + Send,
+    {
+        let (ws, generation) = self.shared.current().await?;
+        match ws.request(method, params).await {
+            Ok(result) => Ok(result),
+            Err(err) => {
+                let err = GrauxError::from(ProviderError::from(err));
+                if matches!(err, GrauxError::Transport(_)) {
+                    self.shared.spawn_reconnect(generation);
+                }
+                Err(err)
+            }
+        }
+    }
+
+    /// Opens a subscription; items that fail to decode as `T` are skipped.
+    pub async fn subscribe<T: DeserializeOwned>(&self, kind: SubscriptionKind) -> GrauxResult<Subscription<T>> {
+        let id = self.shared.next_id.fetch_add(1, Ordering::Relaxed);
+        let (sender, receiver) = mpsc::unbounded_channel();
+        self.shared.lock_subscriptions().insert(
+            id,
+            ActiveSubscription {
+                kind,
+                server_id: None,
+                sender,
+            },
+        );
+
+        let (ws, generation) = self.shared.current().await?;
+        if let Err(err) = self.shared.attach(id, &ws, generation).await {
+            self.shared.lock_subscriptions().remove(&id);
+            return Err(err);
+        }
+
+        Ok(Subscription {
+            id,
+            shared: self.shared.clone(),
+            receiver,
+            _item: PhantomData,
+        })
+    }
+
+    pub async fn subscribe_blocks(&self) -> GrauxResult<Subscription<Block<TxHash>>> {
+        self.subscribe(SubscriptionKind::NewHeads).await
+    }
+
+    pub async fn subscribe_logs(&self, filter: &Filter) -> GrauxResult<Subscription<Log>> {
+        self.subscribe(SubscriptionKind::Logs(filter.clone())).await
+    }
+
+    pub async fn subscribe_pending_transactions(&self) -> GrauxResult<Subscription<TxHash>> {
+        self.subscribe(SubscriptionKind::NewPendingTransactions).await
+    }
+
+    /// Pending transactions matching `filter`. The filter can later be changed with
+    /// `Subscription::set_filter` without ending the stream.
+    pub async fn subscribe_graux_pending_transactions(
+        &self,
+        filter: PendingTransactionsFilter,
+    ) -> GrauxResult<Subscription<TransactionNotification>> {
+        self.subscribe(SubscriptionKind::GrauxPendingTransactions(filter)).await
+    }
+
+    /// Mined transactions matching `filter`. The filter can later be changed with
+    /// `Subscription::set_filter` without ending the stream.
+    pub async fn subscribe_graux_mined_transactions(
+        &self,
+        filter: MinedTransactionsFilter,
+    ) -> GrauxResult<Subscription<MinedTransaction>> {
+        self.subscribe(SubscriptionKind::GrauxMinedTransactions(filter)).await
+    }
+
+    /// Like `subscribe_blocks`, but blocks missed while the socket was down are fetched over
+    /// HTTP with `eth_getBlockByNumber` and delivered, in order, before live blocks resume.
+    pub async fn subscribe_blocks_gap_free(&self) -> GrauxResult<GapFreeStream<Block<TxHash>>> {
+        let http = self.backfill_provider()?;
+        let events = self.connection_events();
+        let live = self.subscribe_blocks().await?;
+        gap_free_blocks(live, events, http).await
+    }
+
+    /// Like `subscribe_logs`, but logs emitted while the socket was down are fetched with
+    /// `eth_getLogs` and delivered before live logs resume.
+    pub async fn subscribe_logs_gap_free(&self, filter: &Filter) -> GrauxResult<GapFreeStream<Log>> {
+        let http = self.backfill_provider()?;
+        let events = self.connection_events();
+        let live = self.subscribe_logs(filter).await?;
+        gap_free_logs(live, filter.clone(), events, http).await
+    }
+
+    /// `newHeads` subscription that reports reorgs as `Reorged` events. Missing ancestors
+    /// and filter logs are fetched over HTTP.
+    pub async fn subscribe_blocks_with_reorgs(&self, config: ReorgConfig) -> GrauxResult<ReorgAwareStream> {
+        let http = self.backfill_provider()?;
+        let live = self.subscribe_blocks().await?;
+        Ok(reorg::from_heads(http, live.map(Ok), config))
+    }
+
+    fn backfill_provider(&self) -> GrauxResult<Provider<Http>> {
+        self.http
+            .clone()
+            .ok_or_else(|| GrauxError::Config("no HTTP URL configured for backfill".to_owned()))
+    }
+
+    pub fn connection_events(&self) -> broadcast::Receiver<ConnectionEvent> {
+        self.shared.events.subscribe()
+    }
+}
+
+impl Shared {
+    fn lock_subscriptions(&self) -> std::sync::MutexGuard<'_, HashMap<u64, ActiveSubscription>> {
+        self.subscriptions.lock().expect("subscription table lock poisoned")
+    }
+
+    /// Switches subscription `id` to `kind` on the live connection. The new server-side
+    /// subscription is opened before the old one is cancelled, so the caller's stream stays
+    /// open throughout. If the server rejects `kind`, the subscription keeps its old filter.
+    async fn resubscribe(self: &Arc<Self>, id: u64, kind: SubscriptionKind) -> GrauxResult<()> {
+        let previous = match self.lock_subscriptions().get(&id) {
+            Some(subscription) => subscription.server_id,
+            None => return Err(GrauxError::Transport("subscription is closed".to_owned())),
+        };
+
+        let (ws, generation) = self.current().await?;
+        self.attach_as(id, kind, &ws, generation).await?;
+        if let Some(previous) = previous {
+            let _ = ws.unsubscribe(previous);
+        }
+        Ok(())
+    }
+
+    async fn current(&self) -> GrauxResult<(Ws, u64)> {
+        let connection = self.connection.read().await;
+        match &connection.ws {
+            Some(ws) => Ok((ws.clone(), connection.generation)),
+            None => Err(GrauxError::Transport("WebSocket connection closed".to_owned())),
+        }
+    }
+
+    /// Issues `eth_subscribe` for subscription `id` on `ws` and starts forwarding its
+    /// notifications.
+    async fn attach(self: &Arc<Self>, id: u64, ws: &Ws, generation: u64) -> GrauxResult<()> {
+        let kind = match self.lock_subscriptions().get(&id) {
+            Some(subscription) => subscription.kind.clone(),
+            None => return Ok(()),
+        };
+        self.attach_as(id, kind, ws, generation).await
+    }
+
+    /// Like `attach`, but subscribes to `kind`, which is stored on the subscription only once
+    /// the server has accepted it.
+    async fn attach_as(
+        self: &Arc<Self>,
+        id: u64,
+        kind: SubscriptionKind,
+        ws: &Ws,
+        generation: u64,
+    ) -> GrauxResult<()> {
+        let server_id: U256 = ws
+            .request("eth_subscribe", kind.params())
+            .await
+            .map_err(|e| GrauxError::from(ProviderError::from(e)))?;
+        let notifications = match ws.subscribe(server_id) {
+            Ok(notifications) => notifications,
+            Err(err) => {
+                let _ = ws.unsubscribe(server_id);
+                return Err(GrauxError::from(ProviderError::from(err)));
+            }
+        };
+
+        match self.lock_subscriptions().get_mut(&id) {
+            Some(subscription) => {
+                subscription.kind = kind;
+                subscription.server_id = Some(server_id);
+            }
+            None => {
+                // Dropped by the caller while we were subscribing.
+                let _ = ws.unsubscribe(server_id);
+                return Ok(());
+            }
+        }
+
+        tokio::spawn(forward(self.clone(), id, ws.clone(), server_id, generation, notifications));
+        Ok(())
+    }
+
+    /// Starts a reconnect in the background. The future is boxed because `reconnect` is
+    /// reached from the `forward` tasks that `attach` itself spawns.
+    fn spawn_reconnect(self: &Arc<Self>, failed_generation: u64) {
+        let shared = self.clone();
+        let task: Pin<Box<dyn Future<Output = ()> + Send>> =
+            Box::pin(async move { shared.reconnect(failed_generation).await });
+        tokio::spawn(task);
+    }
+
+    /// Re-opens the socket after the connection of `failed_generation` dropped and re-issues
+    /// `eth_subscribe` for every subscription. Concurrent callers for the same generation
+    /// collapse into a single reconnect.
+    async fn reconnect(self: &Arc<Self>, failed_generation: u64) {
+        let mut failed_generation = failed_generation;
+        let mut attempt = 0;
+        loop {
+            let Some((ws, generation)) = self.reopen(failed_generation, &mut attempt).await else {
+                return;
+            };
+            if self.resubscribe_all(&ws, generation).await.is_ok() {
+                let _ = self.events.send(ConnectionEvent::Reconnected { generation });
+                return;
+            }
+
+            // The new socket failed while resubscribing; back off before opening another.
+            if attempt >= self.reconnect_policy.max_retries {
+                self.close(generation).await;
+                return;
+            }
+            tokio::time::sleep(self.reconnect_policy.backoff(attempt)).await;
+            attempt += 1;
+            failed_generation = generation;
+        }
+    }
+
+    /// Opens a new socket in place of the one of `failed_generation`, backing off between
+    /// attempts. Returns `None` if another task already replaced it or is replacing it, or
+    /// once the retry budget is spent, in which case the provider is closed. The connection
+    /// lock is only taken to swap the socket, never across a connect attempt or a backoff.
+    async fn reopen(&self, failed_generation: u64, attempt: &mut u32) -> Option<(Ws, u64)> {
+        {
+            let mut connection = self.connection.write().await;
+            if connection.generation != failed_generation || connection.ws.is_none() || connection.reconnecting {
+                return None;
+            }
+            connection.reconnecting = true;
+        }
+        let _ = self.events.send(ConnectionEvent::Disconnected {
+            generation: failed_generation,
+        });
+
+        loop {
+            let opened = open(&self.url).await;
+            let mut connection = self.connection.write().await;
+            match opened {
+                Ok(ws) => {
+                    connection.ws = Some(ws.clone());
+                    connection.generation += 1;
+                    connection.reconnecting = false;
+                    return Some((ws, connection.generation));
+                }
+                Err(_) if *attempt < self.reconnect_policy.max_retries => {
+                    drop(connection);
+                    tokio::time::sleep(self.reconnect_policy.backoff(*attempt)).await;
+                    *attempt += 1;
+                }
+                Err(_) => {
+                    connection.reconnecting = false;
+                    self.close_locked(&mut connection);
+                    return None;
+                }
+            }
+        }
+    }
+
+    /// Re-attaches every subscription to `ws`. A subscription the server keeps rejecting is
+    /// dropped, which ends its stream; a transport failure aborts so the socket is re-opened.
+    async fn resubscribe_all(self: &Arc<Self>, ws: &Ws, generation: u64) -> GrauxResult<()> {
+        let ids: Vec<u64> = self.lock_subscriptions().keys().copied().collect();
+        for id in ids {
+            let mut attempt = 0;
+            while let Err(err) = self.attach(id, ws, generation).await {
+                match on_attach_failure(&self.reconnect_policy, attempt, &err) {
+                    AttachFailure::Reconnect => return Err(err),
+                    AttachFailure::RetryAfter(delay) => {
+                        tokio::time::sleep(delay).await;
+                        attempt += 1;
+                    }
+                    AttachFailure::Drop => {
+                        self.lock_subscriptions().remove(&id);
+                        break;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    async fn close(&self, generation: u64) {
+        let mut connection = self.connection.write().await;
+        if connection.generation == generation {
+            self.close_locked(&mut connection);
+        }
+    }
+
+    fn close_locked(&self, connection: &mut Connection) {
+        connection.ws = None;
+        // Dropping the senders ends every subscription stream.
+        self.lock_subscriptions().clear();
+        let _ = self.events.send(ConnectionEvent::Closed);
+    }
+}
+
+/// What to do when `eth_subscribe` fails while resubscribing after a reconnect.
+#[derive(Debug, PartialEq, Eq)]
+enum AttachFailure {
+    /// The socket itself failed; open a new one.
+    Reconnect,
+    /// The server rejected the subscription; try it again after the delay.
+    RetryAfter(Duration),
+    /// The server kept rejecting the subscription; give up on it alone.
+    Drop,
+}
+
+fn on_attach_failure(policy: &RetryPolicy, attempt: u32, error: &GrauxError) -> AttachFailure {
+    match error {
+        GrauxError::Transport(_) | GrauxError::Timeout => AttachFailure::Reconnect,
+        _ if attempt < policy.max_retries => AttachFailure::RetryAfter(policy.backoff(attempt)),
+        _ => AttachFailure::Drop,
+    }
+}
+
+/// WebSocket and HTTP URLs for a custom endpoint given with either scheme.
+fn custom_endpoints(url: &str) -> GrauxResult<(String, String)> {
+    match url.split_once("://") {
+        Some(("wss" | "https", rest)) => Ok((format!("wss://{rest}"), format!("https://{rest}"))),
+        Some(("ws" | "http", rest)) => Ok((format!("ws://{rest}"), format!("http://{rest}"))),
+        _ => Err(GrauxError::Config(
+            "custom url must use the http, https, ws or wss scheme".to_owned(),
+        )),
+    }
+}
+
+async fn open(url: &str) -> GrauxResult<Ws> {
+    // Reconnects are handled by `Shared::reconnect`, so the ethers-level ones are disabled.
+    Ws::connect_with_reconnects(url, 0)
+        .await
+        .map_err(|e| GrauxError::from(ProviderError::from(e)))
+}
+
+/// Relays notifications of one server-side subscription to its `Subscription` stream.
+async fn forward(
+    shared: Arc<Shared>,
+    id: u64,
+    ws: Ws,
+    server_id: U256,
+    generation: u64,
+    mut notifications: NotificationStream,
+) {
+    while let Some(raw) = notifications.next().await {
+        let Ok(value) = serde_json::from_str::<Value>(raw.get()) else {
+            continue;
+        };
+
+        let mut subscriptions = shared.lock_subscriptions();
+        let delivered = subscriptions
+            .get(&id)
+            .filter(|s| s.server_id == Some(server_id))
+            .map(|s| s.sender.send(value).is_ok());
+        match delivered {
+            Some(true) => {}
+            Some(false) => {
+                subscriptions.remove(&id);
+                drop(subscriptions);
+                let _ = ws.unsubscribe(server_id);
+                return;
+            }
+            None => {
+                drop(subscriptions);
+                let _ = ws.unsubscribe(server_id);
+                return;
+            }
+        }
+    }
+
+    // The notification stream only ends on its own when the socket goes away.
+    let still_active = shared
+        .lock_subscriptions()
+        .get(&id)
+        .is_some_and(|s| s.server_id == Some(server_id));
+    if still_active {
+        shared.spawn_reconnect(generation);
+    }
+}
+
+/// Stream of notifications for one subscription. Survives reconnects; unsubscribes on drop.
+pub struct Subscription<T> {
+    id: u64,
+    shared: Arc<Shared>,
+    receiver: mpsc::UnboundedReceiver<Value>,
+    _item: PhantomData<fn() -> T>,
+}
+
+impl<T> Subscription<T> {
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+}
+
+impl Subscription<TransactionNotification> {
+    /// Replaces the address filter of a `graux_pendingTransactions` subscription.
+    pub async fn set_filter(&self, filter: PendingTransactionsFilter) -> GrauxResult<()> {
+        self.shared
+            .resubscribe(self.id, SubscriptionKind::GrauxPendingTransactions(filter))
+            .await
+    }
+}
+
+impl Subscription<MinedTransaction> {
+    /// Replaces the address filter of a `graux_minedTransactions` subscription.
+    pub async fn set_filter(&self, filter: MinedTransactionsFilter) -> GrauxResult<()> {
+        self.shared
+            .resubscribe(self.id, SubscriptionKind::GrauxMinedTransactions(filter))
+            .await
+    }
+}
+
+impl<T: DeserializeOwned> Stream for Subscription<T> {
+    type Item = T;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        loop {
+            match self.receiver.poll_recv(cx) {
+                Poll::Ready(Some(value)) => {
+                    if let Ok(item) = serde_json::from_value(value) {
+                        return Poll::Ready(Some(item));
+                    }
+                }
+                Poll::Ready(None) => return Poll::Ready(None),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+
+impl<T> Drop for Subscription<T> {
+    fn drop(&mut self) {
+        let removed = self.shared.lock_subscriptions().remove(&self.id);
+        if let (Some(server_id), Ok(connection)) = (
+            removed.and_then(|s| s.server_id),
+            self.shared.connection.try_read(),
+        ) {
+            if let Some(ws) = &connection.ws {
+                let _ = ws.unsubscribe(server_id);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_ws::MockWsServer;
+    use ethers_core::types::{Address, H256};
+
+    #[test]
+    fn transport_failures_reopen_the_socket() {
+        let policy = RetryPolicy::new(3);
+        let error = GrauxError::Transport("connection reset".to_owned());
+        assert_eq!(on_attach_failure(&policy, 0, &error), AttachFailure::Reconnect);
+        assert_eq!(on_attach_failure(&policy, 0, &GrauxError::Timeout), AttachFailure::Reconnect);
+    }
+
+    #[test]
+    fn rejected_subscriptions_back_off_then_drop() {
+        let policy = RetryPolicy::new(2);
+        let error = GrauxError::InvalidArgument("unsupported filter".to_owned());
+        assert!(matches!(on_attach_failure(&policy, 0, &error), AttachFailure::RetryAfter(_)));
+        assert!(matches!(on_attach_failure(&policy, 1, &error), AttachFailure::RetryAfter(_)));
+        assert_eq!(on_attach_failure(&policy, 2, &error), AttachFailure::Drop);
+    }
+
+    #[test]
+    fn subscribe_params_name_the_subscription() {
+        assert_eq!(SubscriptionKind::NewHeads.params(), json!(["newHeads"]));
+        assert_eq!(
+            SubscriptionKind::NewPendingTransactions.params(),
+            json!(["newPendingTransactions"])
+        );
+        let params = SubscriptionKind::Logs(Filter::new().from_block(1u64)).params();
+        assert_eq!(params[0], "logs");
+        assert_eq!(params[1]["fromBlock"], "0x1");
+    }
+
+    #[test]
+    fn custom_endpoints_share_host_and_path() {
+        let expected = (
+            "wss://node.example.com/v2/key".to_owned(),
+            "https://node.example.com/v2/key".to_owned(),
+        );
+        assert_eq!(custom_endpoints("https://node.example.com/v2/key").unwrap(), expected);
+        assert_eq!(custom_endpoints("wss://node.example.com/v2/key").unwrap(), expected);
+        assert_eq!(
+            custom_endpoints("ws://127.0.0.1:8546").unwrap(),
+            ("ws://127.0.0.1:8546".to_owned(), "http://127.0.0.1:8546".to_owned())
+        );
+        assert!(matches!(custom_endpoints("node.example.com"), Err(GrauxError::Config(_))));
+    }
+
+    fn fast_reconnects() -> RetryPolicy {
+        RetryPolicy {
+            initial_backoff: Duration::from_millis(1),
+            max_backoff: Duration::from_millis(5),
+            ..RetryPolicy::new(3)
+        }
+    }
+
+    #[tokio::test]
+    async fn resubscribes_the_same_stream_after_the_socket_drops() {
+        // Each connection numbers its subscription after itself and sends one notification
+        // carrying the connection number.
+        let server = MockWsServer::start(|connection, request| {
+            if request["method"] != "eth_subscribe" {
+                return Vec::new();
+            }
+            let server_id = format!("0x{:x}", connection + 1);
+            vec![
+                json!({ "jsonrpc": "2.0", "id": request["id"], "result": server_id }),
+                json!({
+                    "jsonrpc": "2.0",
+                    "method": "eth_subscription",
+                    "params": { "subscription": server_id, "result": connection },
+                }),
+            ]
+        })
+        .await;
+
+        let test = async {
+            let provider = GrauxWebSocketProvider::connect(server.url.clone(), fast_reconnects()).await.unwrap();
+            let mut events = provider.connection_events();
+            let mut subscription = provider.subscribe::<u64>(SubscriptionKind::NewHeads).await.unwrap();
+            assert_eq!(subscription.next().await, Some(0));
+
+            server.drop_connections();
+            assert_eq!(events.recv().await.unwrap(), ConnectionEvent::Disconnected { generation: 0 });
+            assert_eq!(events.recv().await.unwrap(), ConnectionEvent::Reconnected { generation: 1 });
+            assert_eq!(subscription.next().await, Some(1));
+            assert_eq!(server.connections(), 2);
+        };
+        tokio::time::timeout(Duration::from_secs(10), test).await.expect("reconnect timed out");
+    }
+
+    #[tokio::test]
+    async fn set_filter_switches_a_live_stream_in_place() {
+        // The n-th `eth_subscribe` gets server id n and one notification with hash n.
+        let requests = Arc::new(Mutex::new(Vec::<Value>::new()));
+        let log = requests.clone();
+        let server = MockWsServer::start(move |_, request| {
+            let mut requests = log.lock().unwrap();
+            requests.push(request.clone());
+            if request["method"] != "eth_subscribe" {
+                return Vec::new();
+            }
+            let n = requests.iter().filter(|r| r["method"] == "eth_subscribe").count() as u64;
+            vec![
+                json!({ "jsonrpc": "2.0", "id": request["id"], "result": format!("{n:#x}") }),
+                json!({
+                    "jsonrpc": "2.0",
+                    "method": "eth_subscription",
+                    "params": { "subscription": format!("{n:#x}"), "result": H256::from_low_u64_be(n) },
+                }),
+            ]
+        })
+        .await;
+        let first = PendingTransactionsFilter {
+            from_address: vec![Address::repeat_byte(1)],
+            ..Default::default()
+        };
+        let second = PendingTransactionsFilter {
+            to_address: vec![Address::repeat_byte(2)],
+            hashes_only: true,
+            ..Default::default()
+        };
+
+        let test = async {
+            let provider = GrauxWebSocketProvider::connect(server.url.clone(), fast_reconnects()).await.unwrap();
+            let mut subscription = provider.subscribe_graux_pending_transactions(first.clone()).await.unwrap();
+            assert_eq!(subscription.next().await.map(|n| n.hash()), Some(H256::from_low_u64_be(1)));
+
+            subscription.set_filter(second.clone()).await.unwrap();
+            assert_eq!(subscription.next().await.map(|n| n.hash()), Some(H256::from_low_u64_be(2)));
+
+            // The old server-side subscription is cancelled once the new one is live.
+            while !requests.lock().unwrap().iter().any(|r| r["method"] == "eth_unsubscribe") {
+                tokio::time::sleep(Duration::from_millis(5)).await;
+            }
+        };
+        tokio::time::timeout(Duration::from_secs(10), test).await.expect("set_filter timed out");
+
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests[0]["params"], json!(["graux_pendingTransactions", first]));
+        assert_eq!(requests[1]["params"], json!(["graux_pendingTransactions", second]));
+        assert_eq!(requests[2]["method"], "eth_unsubscribe");
+        assert!(requests[2]["params"].to_string().contains("\"0x1\""));
+        assert_eq!(server.connections(), 1);
+    }
+}
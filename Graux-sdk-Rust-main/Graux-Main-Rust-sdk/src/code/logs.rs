@@ -0,0 +1,287 @@
+use ethers_core::types::{BlockNumber, Filter, FilterBlockOption, Log};
+use ethers_providers::Middleware;
+use futures_util::future;
+use futures_util::stream::{self, BoxStream, StreamExt};
+
+use crate::error::{GrauxError, GrauxResult};
+
+/// Settings for `LogFetcher`.
+#[derive(Debug, Clone)]
+pub struct LogFetcherConfig {
+    /// Blocks requested per `eth_getLogs` before any splitting.
+    pub chunk_size: u64,
+    /// Chunks requested at the same time.
+    pub concurrency: usize,
+}
+
+impl Default for LogFetcherConfig {
+    fn default() -> Self {
+        LogFetcherConfig {
+            chunk_size: 2_000,
+            concurrency: 4,
+        }
+    }
+}
+
+/// Fetches the logs of arbitrarily wide block ranges.
+///
+/// The range is split into `chunk_size` chunks fetched `concurrency` at a time. A chunk the
+/// provider rejects as too wide or as returning too many results is bisected until it is
+/// accepted. Logs are yielded in block order regardless of which chunk finishes first.
+#[derive(Debug, Clone)]
+pub struct LogFetcher<M> {
+    inner: M,
+    config: LogFetcherConfig,
+}
+
+impl<M> LogFetcher<M>
+where
+    M: Middleware,
+    GrauxError: From<M::Error>,
+{
+    pub fn new(inner: M, config: LogFetcherConfig) -> Self {
+        LogFetcher { inner, config }
+    }
+
+    /// Streams every log matching `filter`. Block tags are resolved once up front: `latest` to
+    /// the current head, `safe` and `finalized` to the blocks the node reports for them.
+    /// `pending` is rejected, since pending logs do not belong to a block range. An `Err` item
+    /// ends the stream.
+    pub async fn stream(&self, filter: Filter) -> GrauxResult<BoxStream<'_, GrauxResult<Log>>> {
+        let (from_block, to_block) = match &filter.block_option {
+            FilterBlockOption::AtBlockHash(_) => {
+                let logs = self.inner.get_logs(&filter).await?;
+                return Ok(stream::iter(logs.into_iter().map(Ok)).boxed());
+            }
+            FilterBlockOption::Range { from_block, to_block } => (*from_block, *to_block),
+        };
+
+        let from = self.resolve(from_block).await?;
+        let to = self.resolve(to_block).await?;
+        if from > to {
+            return Err(GrauxError::InvalidArgument(format!(
+                "fromBlock {from} is after toBlock {to}"
+            )));
+        }
+
+        let chunk_size = self.config.chunk_size.max(1);
+        let chunks = (from..=to)
+            .step_by(chunk_size as usize)
+            .map(move |start| (start, start.saturating_add(chunk_size - 1).min(to)));
+
+        Ok(stream::iter(chunks)
+            .map(move |(start, end)| {
+                let filter = filter.clone();
+                async move { self.fetch_range(filter, start, end).await }
+            })
+            .buffered(self.config.concurrency.max(1))
+            .flat_map(|chunk| {
+                let items: Vec<GrauxResult<Log>> = match chunk {
+                    Ok(logs) => logs.into_iter().map(Ok).collect(),
+                    Err(err) => vec![Err(err)],
+                };
+                stream::iter(items)
+            })
+            .scan(false, |failed, item| {
+                if *failed {
+                    return future::ready(None);
+                }
+                *failed = item.is_err();
+                future::ready(Some(item))
+            })
+            .boxed())
+    }
+
+    async fn resolve(&self, block: Option<BlockNumber>) -> GrauxResult<u64> {
+        match block.unwrap_or_default() {
+            BlockNumber::Number(number) => Ok(number.as_u64()),
+            BlockNumber::Earliest => Ok(0),
+            BlockNumber::Latest => Ok(self.inner.get_block_number().await?.as_u64()),
+            tag @ (BlockNumber::Safe | BlockNumber::Finalized) => self
+                .inner
+                .get_block(tag)
+                .await?
+                .and_then(|block| block.number)
+                .map(|number| number.as_u64())
+                .ok_or_else(|| GrauxError::InvalidArgument(format!("the node has no {tag} block"))),
+            BlockNumber::Pending => Err(GrauxError::InvalidArgument(
+                "pending logs cannot be fetched by block range".to_owned(),
+            )),
+        }
+    }
+
+    /// Fetches `from..=to`, bisecting sub-ranges the provider refuses. Halves are fetched
+    /// left to right so the result stays in block order.
+    async fn fetch_range(&self, filter: Filter, from: u64, to: u64) -> GrauxResult<Vec<Log>> {
+        let mut logs = Vec::new();
+        let mut pending = vec![(from, to)];
+
+        while let Some((from, to)) = pending.pop() {
+            let chunk = filter.clone().from_block(from).to_block(to);
+            match self.inner.get_logs(&chunk).await {
+                Ok(chunk_logs) => logs.extend(chunk_logs),
+                Err(err) => {
+                    let err = GrauxError::from(err);
+                    if from == to || !is_log_range_error(&err) {
+                        return Err(err);
+                    }
+                    let mid = from + (to - from) / 2;
+                    pending.push((mid + 1, to));
+                    pending.push((from, mid));
+                }
+            }
+        }
+        Ok(logs)
+    }
+}
+
+/// Whether an `eth_getLogs` failure means the range was too wide or matched too many logs.
+/// Providers only report this through the error message (some with the rate-limit code),
+/// so the message is matched against the common wordings.
+pub fn is_log_range_error(error: &GrauxError) -> bool {
+    let message = match error {
+        GrauxError::Http { status: 413, .. } => return true,
+        GrauxError::JsonRpc { message, .. } | GrauxError::RateLimited { message, .. } => message,
+        _ => return false,
+    };
+    let message = message.to_ascii_lowercase();
+    [
+        "query returned more than",
+        "response size exceeded",
+        "response is too big",
+        "log response size",
+        "block range",
+        "range too large",
+        "too many blocks",
+        "exceed maximum block range",
+        "query timeout exceeded",
+    ]
+    .iter()
+    .any(|pattern| message.contains(pattern))
+}
+
+#[cfg(test)]
+mod tests {
+    use ethers_core::types::{Block, TxHash, U64};
+    use ethers_providers::{JsonRpcError, MockProvider, MockResponse, Provider};
+
+    use super::*;
+
+    fn fetcher(chunk_size: u64) -> (LogFetcher<Provider<MockProvider>>, MockProvider) {
+        let (provider, mock) = Provider::mocked();
+        let config = LogFetcherConfig {
+            chunk_size,
+            concurrency: 1,
+        };
+        (LogFetcher::new(provider, config), mock)
+    }
+
+    fn log(block: u64) -> Log {
+        Log {
+            block_number: Some(U64::from(block)),
+            ..Default::default()
+        }
+    }
+
+    fn too_many_results() -> MockResponse {
+        MockResponse::Error(JsonRpcError {
+            code: -32602,
+            message: "query returned more than 10000 results".to_owned(),
+            data: None,
+        })
+    }
+
+    async fn blocks(fetcher: &LogFetcher<Provider<MockProvider>>, filter: Filter) -> Vec<GrauxResult<u64>> {
+        let stream = fetcher.stream(filter).await.unwrap();
+        stream
+            .map(|log| log.map(|log| log.block_number.unwrap().as_u64()))
+            .collect()
+            .await
+    }
+
+    fn range(filter: &Filter, from: u64, to: u64) -> [Filter; 1] {
+        [filter.clone().from_block(from).to_block(to)]
+    }
+
+    #[tokio::test]
+    async fn bisects_rejected_ranges_in_block_order() {
+        let (fetcher, mock) = fetcher(4);
+        // Answers are popped last-in first-out.
+        mock.push::<Vec<Log>, _>(vec![log(4), log(5)]).unwrap();
+        mock.push::<Vec<Log>, _>(vec![log(3)]).unwrap();
+        mock.push::<Vec<Log>, _>(vec![log(2)]).unwrap();
+        mock.push_response(too_many_results());
+        mock.push::<Vec<Log>, _>(vec![log(0), log(1)]).unwrap();
+        mock.push_response(too_many_results());
+
+        let filter = Filter::new().from_block(0).to_block(5);
+        let blocks: Vec<u64> = blocks(&fetcher, filter.clone()).await.into_iter().map(Result::unwrap).collect();
+        assert_eq!(blocks, vec![0, 1, 2, 3, 4, 5]);
+
+        for (from, to) in [(0, 3), (0, 1), (2, 3), (2, 2), (3, 3), (4, 5)] {
+            mock.assert_request("eth_getLogs", range(&filter, from, to)).unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn a_rejected_single_block_ends_the_stream() {
+        let (fetcher, mock) = fetcher(1);
+        mock.push_response(too_many_results());
+        mock.push::<Vec<Log>, _>(vec![log(7)]).unwrap();
+
+        let blocks = blocks(&fetcher, Filter::new().from_block(7).to_block(9)).await;
+        assert_eq!(blocks.len(), 2);
+        assert_eq!(blocks[0].as_ref().unwrap(), &7);
+        assert!(matches!(blocks[1], Err(GrauxError::JsonRpc { .. })));
+    }
+
+    #[tokio::test]
+    async fn resolves_safe_and_finalized_tags() {
+        let (fetcher, mock) = fetcher(10);
+        let block = |number: u64| Block::<TxHash> {
+            number: Some(U64::from(number)),
+            ..Default::default()
+        };
+        mock.push::<Vec<Log>, _>(vec![log(12)]).unwrap();
+        mock.push::<Block<TxHash>, _>(block(14)).unwrap();
+        mock.push::<Block<TxHash>, _>(block(11)).unwrap();
+
+        let filter = Filter::new()
+            .from_block(BlockNumber::Finalized)
+            .to_block(BlockNumber::Safe);
+        let blocks: Vec<u64> = blocks(&fetcher, filter.clone()).await.into_iter().map(Result::unwrap).collect();
+        assert_eq!(blocks, vec![12]);
+
+        mock.assert_request("eth_getBlockByNumber", (BlockNumber::Finalized, false)).unwrap();
+        mock.assert_request("eth_getBlockByNumber", (BlockNumber::Safe, false)).unwrap();
+        mock.assert_request("eth_getLogs", range(&filter, 11, 14)).unwrap();
+    }
+
+    #[tokio::test]
+    async fn rejects_pending_and_inverted_ranges() {
+        let (fetcher, mock) = fetcher(10);
+        let pending = fetcher.stream(Filter::new().from_block(1).to_block(BlockNumber::Pending)).await;
+        assert!(matches!(pending, Err(GrauxError::InvalidArgument(_))));
+
+        mock.push::<U64, _>(U64::from(3)).unwrap();
+        let inverted = fetcher.stream(Filter::new().from_block(5)).await;
+        assert!(matches!(inverted, Err(GrauxError::InvalidArgument(_))));
+    }
+
+    #[test]
+    fn recognizes_range_errors() {
+        let json_rpc = |message: &str| GrauxError::JsonRpc {
+            code: -32000,
+            message: message.to_owned(),
+            data: None,
+        };
+        assert!(is_log_range_error(&json_rpc("Log response size exceeded.")));
+        assert!(is_log_range_error(&json_rpc("exceed maximum block range: 5000")));
+        assert!(is_log_range_error(&GrauxError::Http {
+            status: 413,
+            message: String::new()
+        }));
+        assert!(!is_log_range_error(&json_rpc("execution reverted")));
+        assert!(!is_log_range_error(&GrauxError::Timeout));
+    }
+}
@@ -1,13 +1,22 @@
-use ethers_core::types::{Address, Block, BlockTag, Log, TransactionReceipt, TransactionRequest, TransactionResponse};
+use ethers_core::types::transaction::eip2718::TypedTransaction;
+use ethers_core::types::{Address, Block, BlockNumber, BlockTag, Bytes, Filter, Log, Transaction, TransactionReceipt, TransactionRequest, TransactionResponse, TxHash};
 use ethers_core::utils::{BigEndianHash, to_32bytes, to_64bytes};
-use ethers_providers::{Middleware, MiddlewareError, Provider};
+use ethers_providers::{Middleware, Provider};
+use futures_util::stream::BoxStream;
+use std::collections::HashMap;
 use std::convert::TryFrom;
-
-custom error type for the GrauxConfig
-#[derive(Debug)]
-enum GrauxError {
-    ConfigError(String),
-}
+use std::sync::Arc;
+
+use crate::abi_registry::{AbiRegistry, CallOutput};
+use crate::deployer::{self, ContractDeployment};
+use crate::ens::EnsResolver;
+use crate::error::{GrauxError, GrauxResult};
+use crate::fees::{FeeOracle, FeeOracleConfig, FeeSpeed, FeeSuggestions};
+use crate::logs::{LogFetcher, LogFetcherConfig};
+use crate::nonce::{NonceManager, NonceSnapshot};
+use crate::replacement::{cancel_transaction, speed_up_transaction, ReplacementHandle};
+use crate::signatures::{LabeledLog, SignatureDb};
+use crate::signer::GrauxSigner;
 
 the GrauxConfig struct
 struct GrauxConfig {
@@ -15,9 +24,10 @@ struct GrauxConfig {
 }
 
 impl GrauxConfig {
-    fn new() -> Result<Self, GrauxError> {
+    fn new() -> GrauxResult<Self> {
         // Initialize the Ethereum provider
-        let provider = Provider::try_from("https://api.example.com")?;
+        let provider = Provider::try_from("https://api.example.com")
+            .map_err(|e| GrauxError::Config(e.to_string()))?;
 
         Ok(Self { provider })
     }
@@ -28,33 +38,102 @@ impl GrauxConfig {
 }
 struct GrauxCoreNamespace {
     config: GrauxConfig,
+    nonce_manager: Option<NonceManager<Provider>>,
+    signer: Option<Arc<GrauxSigner<Provider>>>,
+    fee_oracle: FeeOracle<Provider>,
+    fee_speed: FeeSpeed,
+    abi_registry: Arc<AbiRegistry>,
+    signatures: Arc<SignatureDb>,
+    log_fetcher: LogFetcher<Provider>,
+    ens: Arc<EnsResolver<Provider>>,
 }
 
 {
-    fn new(config: GrauxConfig) -> Self {
-        Self { config }
+    /// `ens` resolves `address_or_name` parameters; pass the resolver of `GrauxProvider::ens`
+    /// so every namespace shares one cache.
+    fn new(config: GrauxConfig, ens: Arc<EnsResolver<Provider>>) -> Self {
+        let fee_oracle = FeeOracle::new(config.get_provider().clone(), FeeOracleConfig::default());
+        let log_fetcher = LogFetcher::new(config.get_provider().clone(), LogFetcherConfig::default());
+        Self {
+            config,
+            nonce_manager: None,
+            signer: None,
+            fee_oracle,
+            fee_speed: FeeSpeed::default(),
+            abi_registry: Arc::new(AbiRegistry::new()),
+            signatures: Arc::new(SignatureDb::bundled()),
+            log_fetcher,
+            ens,
+        }
+    }
+
+    /// Decodes `call_decoded` results with `abi_registry`, which can be shared with the other
+    /// namespaces.
+    fn with_abi_registry(mut self, abi_registry: Arc<AbiRegistry>) -> Self {
+        self.abi_registry = abi_registry;
+        self
+    }
+
+    /// Replaces the fee oracle settings and the speed used to fill `send_transaction` fees.
+    fn with_fee_oracle(mut self, config: FeeOracleConfig, speed: FeeSpeed) -> Self {
+        self.fee_oracle = FeeOracle::new(self.config.get_provider().clone(), config);
+        self.fee_speed = speed;
+        self
+    }
+
+    /// Labels `get_labeled_logs` results with `signatures` instead of the bundled database.
+    fn with_signature_db(mut self, signatures: Arc<SignatureDb>) -> Self {
+        self.signatures = signatures;
+        self
+    }
+
+    /// Replaces the chunk size and concurrency used by `get_logs_paginated`.
+    fn with_log_fetcher(mut self, config: LogFetcherConfig) -> Self {
+        self.log_fetcher = LogFetcher::new(self.config.get_provider().clone(), config);
+        self
+    }
+
+    /// Allocates nonces locally in `send_transaction` instead of querying the node each time.
+    fn with_nonce_manager(mut self) -> Self {
+        self.nonce_manager = Some(NonceManager::new(self.config.get_provider().clone()));
+        self
+    }
+
+    /// Signs `speed_up` and `cancel` replacements locally and sends them raw, since Graux
+    /// nodes do not hold accounts.
+    fn with_signer(mut self, signer: Arc<GrauxSigner<Provider>>) -> Self {
+        self.signer = Some(signer);
+        self
+    }
+
+    /// Current nonce reservations per account; empty without a nonce manager.
+    async fn nonce_reservations(&self) -> HashMap<Address, NonceSnapshot> {
+        match &self.nonce_manager {
+            Some(nonce_manager) => nonce_manager.reservations().await,
+            None => HashMap::new(),
+        }
     }
 
     async fn get_balance(
         &self,
         address_or_name: &str,
         block_tag: Option<BlockTag>,
-    ) -> Result<BigEndianHash, MiddlewareError> {
+    ) -> GrauxResult<BigEndianHash> {
         let provider = self.config.get_provider();
-        let address = Address::from(address_or_name);
+        let address = self.ens.resolve(address_or_name).await?;
 
-        provider.get_balance(address, block_tag).await
+        Ok(provider.get_balance(address, block_tag).await?)
     }
 
     async fn get_code(
         &self,
         address_or_name: &str,
         block_tag: Option<BlockTag>,
-    ) -> Result<Vec<u8>, MiddlewareError> {
+    ) -> GrauxResult<Vec<u8>> {
         let provider = self.config.get_provider();
-        let address = Address::from(address_or_name);
+        let address = self.ens.resolve(address_or_name).await?;
 
-        provider.get_code(address, block_tag).await
+        Ok(provider.get_code(address, block_tag).await?)
     }
 
     async fn get_storage_at(
@@ -62,154 +141,275 @@ struct GrauxCoreNamespace {
         address_or_name: &str,
         position: BigEndianHash,
         block_tag: Option<BlockTag>,
-    ) -> Result<Vec<u8>, MiddlewareError> {
+    ) -> GrauxResult<Vec<u8>> {
         let provider = self.config.get_provider();
-        let address = Address::from(address_or_name);
+        let address = self.ens.resolve(address_or_name).await?;
 
-        provider.get_storage_at(address, position, block_tag).await
+        Ok(provider.get_storage_at(address, position, block_tag).await?)
     }
 
     async fn get_transaction_count(
         &self,
         address_or_name: &str,
         block_tag: Option<BlockTag>,
-    ) -> Result<u64, MiddlewareError> {
+    ) -> GrauxResult<u64> {
         let provider = self.config.get_provider();
-        let address = Address::from(address_or_name);
+        let address = self.ens.resolve(address_or_name).await?;
 
-        provider.get_transaction_count(address, block_tag).await
+        Ok(provider.get_transaction_count(address, block_tag).await?)
     }
 
     async fn get_block(
         &self,
         block_hash_or_block_tag: BlockTag,
-    ) -> Result<Block, MiddlewareError> {
+    ) -> GrauxResult<Block> {
         let provider = self.config.get_provider();
 
-        provider.get_block(block_hash_or_block_tag).await
+        Ok(provider.get_block(block_hash_or_block_tag).await?)
     }
 
     async fn get_block_with_transactions(
         &self,
         block_hash_or_block_tag: BlockTag,
-    ) -> Result<Block, MiddlewareError> {
+    ) -> GrauxResult<Block> {
         let provider = self.config.get_provider();
 
-        provider.get_block_with_txs(block_hash_or_block_tag).await
+        Ok(provider.get_block_with_txs(block_hash_or_block_tag).await?)
     }
 
-    async fn get_network(&self) -> Result<String, MiddlewareError> {
+    async fn get_network(&self) -> GrauxResult<String> {
         let provider = self.config.get_provider();
 
-        provider.get_network().await
+        Ok(provider.get_network().await?)
     }
 
-    async fn get_block_number(&self) -> Result<u64, MiddlewareError> {
+    async fn get_block_number(&self) -> GrauxResult<u64> {
         let provider = self.config.get_provider();
 
-        provider.get_block_number().await
+        Ok(provider.get_block_number().await?)
     }
 
-    async fn get_gas_price(&self) -> Result<BigEndianHash, MiddlewareError> {
+    async fn get_gas_price(&self) -> GrauxResult<BigEndianHash> {
         let provider = self.config.get_provider();
 
-        provider.get_gas_price().await
+        Ok(provider.get_gas_price().await?)
     }
 
-    async fn get_fee_data(&self) -> Result<BigEndianHash, MiddlewareError> {
-        let provider = self.config.get_provider();
-
-        provider.get_fee_data().await
+    async fn get_fee_data(&self) -> GrauxResult<FeeSuggestions> {
+        self.fee_oracle.suggest().await
     }
 
-    async fn ready(&self) -> Result<(), MiddlewareError> {
+    async fn ready(&self) -> GrauxResult<()> {
         let provider = self.config.get_provider();
 
-        provider.ready().await
+        Ok(provider.ready().await?)
     }
 
     async fn call(
         &self,
         tx: TransactionRequest,
         block_tag: Option<BlockTag>,
-    ) -> Result<Vec<u8>, MiddlewareError> {
+    ) -> GrauxResult<Vec<u8>> {
         let provider = self.config.get_provider();
 
-        provider.call(tx, block_tag).await
+        Ok(provider.call(tx, block_tag).await?)
+    }
+
+    /// Like `call`, with the input and return data decoded by the ABI registry. A revert that
+    /// came with revert data is returned as a `CallOutput` with `revert` set; one without
+    /// fails with `GrauxError::ExecutionReverted` as in `call`.
+    async fn call_decoded(
+        &self,
+        tx: TransactionRequest,
+        block_tag: Option<BlockTag>,
+    ) -> GrauxResult<CallOutput> {
+        let to = tx.to.as_ref().and_then(|to| to.as_address().copied());
+        let input = tx.data.clone().unwrap_or_default();
+
+        let result = match self.call(tx, block_tag).await {
+            Ok(output) => Ok(Bytes::from(output)),
+            Err(GrauxError::ExecutionReverted { message, data: Some(data) }) => match data.parse::<Bytes>() {
+                Ok(raw) => Err(raw),
+                Err(_) => return Err(GrauxError::ExecutionReverted { message, data: Some(data) }),
+            },
+            Err(err) => return Err(err),
+        };
+
+        Ok(self.abi_registry.decode_call_output(to, &input, result))
     }
 
     async fn estimate_gas(
         &self,
         tx: TransactionRequest,
-    ) -> Result<BigEndianHash, MiddlewareError> {
+    ) -> GrauxResult<BigEndianHash> {
         let provider = self.config.get_provider();
 
-        provider.estimate_gas(tx).await
+        Ok(provider.estimate_gas(tx).await?)
     }
 
     async fn get_transaction(
         &self,
         transaction_hash: BigEndianHash,
-    ) -> Result<TransactionResponse, MiddlewareError> {
+    ) -> GrauxResult<TransactionResponse> {
         let provider = self.config.get_provider();
 
-        provider.get_transaction(transaction_hash).await
+        Ok(provider.get_transaction(transaction_hash).await?)
     }
 
     async fn get_transaction_receipt(
         &self,
         transaction_hash: BigEndianHash,
-    ) -> Result<TransactionReceipt, MiddlewareError> {
+    ) -> GrauxResult<TransactionReceipt> {
         let provider = self.config.get_provider();
 
-        provider.get_transaction_receipt(transaction_hash).await
+        Ok(provider.get_transaction_receipt(transaction_hash).await?)
     }
 
+    /// Sends `tx`, filling in missing fees from the fee oracle first.
     async fn send_transaction(
         &self,
-        tx: TransactionRequest,
-    ) -> Result<BigEndianHash, MiddlewareError> {
+        tx: impl Into<TypedTransaction>,
+    ) -> GrauxResult<TxHash> {
+        let mut tx = tx.into();
+        self.fee_oracle.fill(&mut tx, self.fee_speed).await?;
+
+        if let (Some(nonce_manager), Some(_), None) = (&self.nonce_manager, tx.from(), tx.nonce()) {
+            return nonce_manager.send_transaction(tx).await;
+        }
+        let provider = self.config.get_provider();
+
+        Ok(provider.send_transaction(tx, None).await?.tx_hash())
+    }
+
+    /// Rebroadcasts the pending `tx_hash` with its fees raised by `bump_percent` (at least the
+    /// 10% nodes require for a replacement). Await the returned handle to see which of the two
+    /// transactions was mined.
+    async fn speed_up(
+        &self,
+        tx_hash: TxHash,
+        bump_percent: u64,
+    ) -> GrauxResult<ReplacementHandle<Provider>> {
+        let original = self.pending_transaction(tx_hash).await?;
+        let current = self.fee_oracle.suggest().await?.estimate(self.fee_speed);
+        let replacement = speed_up_transaction(&original, bump_percent, current)?;
+
+        self.send_replacement(original, replacement).await
+    }
+
+    /// Replaces the pending `tx_hash` with a zero-value transfer to its own sender.
+    async fn cancel(&self, tx_hash: TxHash) -> GrauxResult<ReplacementHandle<Provider>> {
+        let original = self.pending_transaction(tx_hash).await?;
+        let current = self.fee_oracle.suggest().await?.estimate(self.fee_speed);
+        let replacement = cancel_transaction(&original, current)?;
+
+        self.send_replacement(original, replacement).await
+    }
+
+    async fn pending_transaction(&self, tx_hash: TxHash) -> GrauxResult<Transaction> {
         let provider = self.config.get_provider();
 
-        provider.send_transaction(tx).await
+        provider
+            .get_transaction(tx_hash)
+            .await?
+            .ok_or_else(|| GrauxError::InvalidArgument(format!("transaction {:?} not found", tx_hash)))
+    }
+
+    async fn send_replacement(
+        &self,
+        original: Transaction,
+        replacement: TypedTransaction,
+    ) -> GrauxResult<ReplacementHandle<Provider>> {
+        let signer = self.signer.as_ref().ok_or_else(|| {
+            GrauxError::Config("replacing a transaction needs a local signer; set one with with_signer".to_owned())
+        })?;
+        // The replacement carries the original's nonce and fees, so the signer only signs it
+        // and sends it with `eth_sendRawTransaction`.
+        let tx_hash = signer.send_transaction(replacement).await?;
+        let provider = self.config.get_provider();
+        if let Some(nonce_manager) = &self.nonce_manager {
+            nonce_manager.mark_sent(original.from, original.nonce, tx_hash).await;
+        }
+
+        Ok(ReplacementHandle::new(
+            provider.clone(),
+            original.from,
+            original.nonce,
+            original.hash,
+            tx_hash,
+        ))
     }
 
     async fn wait_for_transaction(
         &self,
         transaction_hash: BigEndianHash,
-    ) -> Result<TransactionReceipt, MiddlewareError> {
+    ) -> GrauxResult<TransactionReceipt> {
         let provider = self.config.get_provider();
 
-        provider.wait_for_transaction(transaction_hash).await
+        Ok(provider.wait_for_transaction(transaction_hash).await?)
     }
 
      async fn get_logs(
         &self,
         filter: impl Into<LogFilter>,
-    ) -> Result<Vec<Log>, MiddlewareError> {
+    ) -> GrauxResult<Vec<Log>> {
         let provider = self.config.get_provider();
 
-        provider.get_logs(filter).await
+        Ok(provider.get_logs(filter).await?)
     }
 
-    async fn send(&self, method: &str, params: Vec<serde_json::Value>) -> Result<serde_json::Value, MiddlewareError> {
+    /// Streams the logs of an arbitrarily wide range in block order, splitting it into
+    /// chunks and bisecting any chunk the provider rejects as too large.
+    async fn get_logs_paginated(
+        &self,
+        filter: Filter,
+    ) -> GrauxResult<BoxStream<'_, GrauxResult<Log>>> {
+        self.log_fetcher.stream(filter).await
+    }
+
+    /// Like `get_logs`, with each log labeled with its best-guess event signature.
+    async fn get_labeled_logs(
+        &self,
+        filter: impl Into<LogFilter>,
+    ) -> GrauxResult<Vec<LabeledLog>> {
+        let logs = self.get_logs(filter).await?;
+
+        Ok(logs.into_iter().map(|log| self.signatures.label_log(log)).collect())
+    }
+
+    async fn send(&self, method: &str, params: Vec<serde_json::Value>) -> GrauxResult<serde_json::Value> {
         let provider = self.config.get_provider();
 
-        provider.send(method, params).await
+        Ok(provider.send(method, params).await?)
     }
 
+    /// Finds the block, transaction and deployer that created a contract, including
+    /// contracts created by factories. Requires an archive node with `debug_traceBlock`.
+    /// `from_block` and `to_block` narrow the search and default to the whole chain.
     async fn find_contract_deployer(
         &self,
         contract_address: &str,
-        from_block: Option<BlockTag>,
-        to_block: Option<BlockTag>,
-    ) -> Result<Option<(Address, u64)>, MiddlewareError> {
+        from_block: Option<BlockNumber>,
+        to_block: Option<BlockNumber>,
+    ) -> GrauxResult<Option<ContractDeployment>> {
         let provider = self.config.get_provider();
-        let address = Address::from(contract_address);
+        let address = self.ens.resolve(contract_address).await?;
 
-        provider
-            .find_contract_deployer(address, from_block, to_block)
-            .await
+        deployer::find_contract_deployer(provider, address, from_block, to_block).await
+    }
+
+    /// Primary ENS name of an address, verified by resolving it back.
+    async fn lookup_address(&self, address: Address) -> GrauxResult<Option<String>> {
+        self.ens.lookup_address(address).await
+    }
+
+    /// A text record of an ENS name, e.g. `"url"` or `"com.twitter"`.
+    async fn get_text(&self, name: &str, key: &str) -> GrauxResult<Option<String>> {
+        self.ens.text(name, key).await
+    }
+
+    /// The `avatar` record of an ENS name, as stored.
+    async fn get_avatar(&self, name: &str) -> GrauxResult<Option<String>> {
+        self.ens.avatar(name).await
     }
 }
 
@@ -223,8 +423,9 @@ fn main() {
         }
     };
 
-    Initialize GrauxCoreNamespace
-    let graux = GrauxCoreNamespace::new(config);
+    Initialize GrauxCoreNamespace with the ENS resolver shared by all namespaces
+    let ens = Arc::new(EnsResolver::new(config.get_provider().clone()));
+    let graux = GrauxCoreNamespace::new(config, ens);
 
     Use the GrauxCoreNamespace methods
     async {
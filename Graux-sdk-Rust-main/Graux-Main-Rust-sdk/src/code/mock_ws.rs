@@ -0,0 +1,82 @@
+//! Minimal WebSocket JSON-RPC server for tests of the subscription provider.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use futures_util::{SinkExt, StreamExt};
+use serde_json::Value;
+use tokio::net::TcpListener;
+use tokio::sync::broadcast;
+use tokio_tungstenite::tungstenite::Message;
+
+/// Answers every JSON-RPC message with `respond` until dropped with the test runtime.
+pub struct MockWsServer {
+    pub url: String,
+    connections: Arc<AtomicUsize>,
+    drop_all: broadcast::Sender<()>,
+}
+
+impl MockWsServer {
+    /// `respond` gets the zero-based number of the connection a request arrived on and the
+    /// request itself, and returns the messages to send back, in order.
+    pub async fn start<F>(respond: F) -> Self
+    where
+        F: Fn(usize, &Value) -> Vec<Value> + Send + Sync + 'static,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind mock server");
+        let url = format!("ws://{}", listener.local_addr().expect("mock server address"));
+        let connections = Arc::new(AtomicUsize::new(0));
+        let (drop_all, _) = broadcast::channel(1);
+        let respond = Arc::new(respond);
+
+        let counter = connections.clone();
+        let dropper = drop_all.clone();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let respond = respond.clone();
+                let mut dropped = dropper.subscribe();
+                let index = counter.fetch_add(1, Ordering::SeqCst);
+                tokio::spawn(async move {
+                    let Ok(mut ws) = tokio_tungstenite::accept_async(stream).await else {
+                        return;
+                    };
+                    loop {
+                        tokio::select! {
+                            message = ws.next() => {
+                                let Some(Ok(Message::Text(text))) = message else {
+                                    return;
+                                };
+                                let Ok(request) = serde_json::from_str::<Value>(&text) else {
+                                    continue;
+                                };
+                                for reply in respond(index, &request) {
+                                    if ws.send(Message::Text(reply.to_string())).await.is_err() {
+                                        return;
+                                    }
+                                }
+                            }
+                            // Dropping the socket without a close frame, like a lost link.
+                            _ = dropped.recv() => return,
+                        }
+                    }
+                });
+            }
+        });
+
+        MockWsServer {
+            url,
+            connections,
+            drop_all,
+        }
+    }
+
+    /// Number of connections accepted so far.
+    pub fn connections(&self) -> usize {
+        self.connections.load(Ordering::SeqCst)
+    }
+
+    /// Cuts every open connection.
+    pub fn drop_connections(&self) {
+        let _ = self.drop_all.send(());
+    }
+}
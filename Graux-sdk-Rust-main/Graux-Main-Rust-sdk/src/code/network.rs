@@ -0,0 +1,302 @@
+use std::collections::HashMap;
+use std::sync::{OnceLock, RwLock};
+use std::time::Duration;
+
+use crate::error::{GrauxError, GrauxResult};
+
+/// Network used when none is configured.
+pub const DEFAULT_NETWORK_NAME: &str = "eth-mainnet";
+
+const API_KEY_PLACEHOLDER: &str = "{api_key}";
+
+/// Native currency of a network.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NativeCurrency {
+    pub name: String,
+    pub symbol: String,
+    pub decimals: u8,
+}
+
+impl NativeCurrency {
+    pub fn new(name: &str, symbol: &str, decimals: u8) -> Self {
+        NativeCurrency {
+            name: name.to_owned(),
+            symbol: symbol.to_owned(),
+            decimals,
+        }
+    }
+}
+
+/// A network Graux can connect to.
+///
+/// URL templates contain an `{api_key}` placeholder that is filled in when a connection is made.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GrauxNetwork {
+    pub name: String,
+    pub chain_id: u64,
+    pub slug: String,
+    pub http_url_template: String,
+    pub ws_url_template: String,
+    pub nft_url_template: Option<String>,
+    pub native_currency: NativeCurrency,
+    pub block_time: Duration,
+    pub finality_depth: u64,
+    pub aliases: Vec<String>,
+}
+
+impl GrauxNetwork {
+    /// Creates a network using the standard Graux URL layout for `slug`.
+    pub fn new(
+        name: &str,
+        chain_id: u64,
+        slug: &str,
+        native_currency: NativeCurrency,
+        block_time: Duration,
+        finality_depth: u64,
+    ) -> Self {
+        GrauxNetwork {
+            name: name.to_owned(),
+            chain_id,
+            slug: slug.to_owned(),
+            http_url_template: format!("https://{slug}.g.graux.com/v2/{API_KEY_PLACEHOLDER}"),
+            ws_url_template: format!("wss://{slug}.g.graux.com/v2/{API_KEY_PLACEHOLDER}"),
+            nft_url_template: Some(format!("https://{slug}.g.graux.com/nft/v2/{API_KEY_PLACEHOLDER}")),
+            native_currency,
+            block_time,
+            finality_depth,
+            aliases: Vec::new(),
+        }
+    }
+
+    fn with_aliases(mut self, aliases: &[&str]) -> Self {
+        self.aliases = aliases.iter().map(|a| a.to_string()).collect();
+        self
+    }
+
+    fn without_nft(mut self) -> Self {
+        self.nft_url_template = None;
+        self
+    }
+
+    pub fn http_url(&self, api_key: &str) -> String {
+        self.http_url_template.replace(API_KEY_PLACEHOLDER, api_key)
+    }
+
+    pub fn ws_url(&self, api_key: &str) -> String {
+        self.ws_url_template.replace(API_KEY_PLACEHOLDER, api_key)
+    }
+
+    /// Returns `None` for networks without NFT API support.
+    pub fn nft_url(&self, api_key: &str) -> Option<String> {
+        self.nft_url_template
+            .as_ref()
+            .map(|template| template.replace(API_KEY_PLACEHOLDER, api_key))
+    }
+
+    fn matches_name(&self, name: &str) -> bool {
+        self.name.eq_ignore_ascii_case(name)
+            || self.slug.eq_ignore_ascii_case(name)
+            || self.aliases.iter().any(|a| a.eq_ignore_ascii_case(name))
+    }
+}
+
+impl Default for GrauxNetwork {
+    fn default() -> Self {
+        lookup_network(DEFAULT_NETWORK_NAME).expect("default network is always registered")
+    }
+}
+
+/// Set of networks known to the SDK, keyed by chain id.
+#[derive(Debug, Default)]
+pub struct NetworkRegistry {
+    networks: HashMap<u64, GrauxNetwork>,
+}
+
+impl NetworkRegistry {
+    /// Registry pre-populated with the networks Graux supports out of the box.
+    pub fn with_builtin_networks() -> Self {
+        let mut registry = NetworkRegistry::default();
+        for network in builtin_networks() {
+            registry.networks.insert(network.chain_id, network);
+        }
+        registry
+    }
+
+    /// Adds a network. Fails if the chain id or any of its names is already taken.
+    pub fn register(&mut self, network: GrauxNetwork) -> GrauxResult<()> {
+        if let Some(existing) = self.networks.get(&network.chain_id) {
+            return Err(GrauxError::InvalidNetwork(format!(
+                "chain id {} is already registered as {}",
+                network.chain_id, existing.name
+            )));
+        }
+        let names = std::iter::once(&network.name)
+            .chain(std::iter::once(&network.slug))
+            .chain(network.aliases.iter());
+        for name in names {
+            if let Some(existing) = self.by_name(name) {
+                return Err(GrauxError::InvalidNetwork(format!(
+                    "name {name} is already used by {}",
+                    existing.name
+                )));
+            }
+        }
+
+        self.networks.insert(network.chain_id, network);
+        Ok(())
+    }
+
+    pub fn by_name(&self, name: &str) -> Option<&GrauxNetwork> {
+        let name = name.trim();
+        self.networks.values().find(|n| n.matches_name(name))
+    }
+
+    pub fn by_chain_id(&self, chain_id: u64) -> Option<&GrauxNetwork> {
+        self.networks.get(&chain_id)
+    }
+
+    /// All registered networks, ordered by chain id.
+    pub fn networks(&self) -> Vec<&GrauxNetwork> {
+        let mut networks: Vec<_> = self.networks.values().collect();
+        networks.sort_by_key(|n| n.chain_id);
+        networks
+    }
+}
+
+fn registry() -> &'static RwLock<NetworkRegistry> {
+    static REGISTRY: OnceLock<RwLock<NetworkRegistry>> = OnceLock::new();
+    REGISTRY.get_or_init(|| RwLock::new(NetworkRegistry::with_builtin_networks()))
+}
+
+/// Registers a custom network in the process-wide registry.
+pub fn register_network(network: GrauxNetwork) -> GrauxResult<()> {
+    registry()
+        .write()
+        .expect("network registry lock poisoned")
+        .register(network)
+}
+
+/// Looks a network up by name, slug or alias (case-insensitive).
+pub fn lookup_network(name: &str) -> GrauxResult<GrauxNetwork> {
+    registry()
+        .read()
+        .expect("network registry lock poisoned")
+        .by_name(name)
+        .cloned()
+        .ok_or_else(|| GrauxError::InvalidNetwork(name.to_owned()))
+}
+
+pub fn lookup_network_by_chain_id(chain_id: u64) -> GrauxResult<GrauxNetwork> {
+    registry()
+        .read()
+        .expect("network registry lock poisoned")
+        .by_chain_id(chain_id)
+        .cloned()
+        .ok_or_else(|| GrauxError::InvalidNetwork(format!("chain id {chain_id}")))
+}
+
+pub fn registered_networks() -> Vec<GrauxNetwork> {
+    registry()
+        .read()
+        .expect("network registry lock poisoned")
+        .networks()
+        .into_iter()
+        .cloned()
+        .collect()
+}
+
+fn builtin_networks() -> Vec<GrauxNetwork> {
+    let eth = || NativeCurrency::new("Ether", "ETH", 18);
+
+    vec![
+        GrauxNetwork::new("eth-mainnet", 1, "eth-mainnet", eth(), Duration::from_secs(12), 64)
+            .with_aliases(&["mainnet", "homestead", "ethereum"]),
+        GrauxNetwork::new("eth-sepolia", 11_155_111, "eth-sepolia", eth(), Duration::from_secs(12), 64)
+            .with_aliases(&["sepolia"]),
+        GrauxNetwork::new("eth-holesky", 17_000, "eth-holesky", eth(), Duration::from_secs(12), 64)
+            .with_aliases(&["holesky"]),
+        GrauxNetwork::new(
+            "polygon-mainnet",
+            137,
+            "polygon-mainnet",
+            NativeCurrency::new("POL", "POL", 18),
+            Duration::from_secs(2),
+            128,
+        )
+        .with_aliases(&["polygon", "matic"]),
+        GrauxNetwork::new(
+            "polygon-amoy",
+            80_002,
+            "polygon-amoy",
+            NativeCurrency::new("POL", "POL", 18),
+            Duration::from_secs(2),
+            128,
+        )
+        .with_aliases(&["amoy"]),
+        GrauxNetwork::new("arb-mainnet", 42_161, "arb-mainnet", eth(), Duration::from_millis(250), 20)
+            .with_aliases(&["arbitrum"]),
+        GrauxNetwork::new("arb-sepolia", 421_614, "arb-sepolia", eth(), Duration::from_millis(250), 20)
+            .with_aliases(&["arbitrum-sepolia"]),
+        GrauxNetwork::new("opt-mainnet", 10, "opt-mainnet", eth(), Duration::from_secs(2), 10)
+            .with_aliases(&["optimism"]),
+        GrauxNetwork::new("opt-sepolia", 11_155_420, "opt-sepolia", eth(), Duration::from_secs(2), 10)
+            .with_aliases(&["optimism-sepolia"]),
+        GrauxNetwork::new("base-mainnet", 8_453, "base-mainnet", eth(), Duration::from_secs(2), 10)
+            .with_aliases(&["base"]),
+        GrauxNetwork::new("base-sepolia", 84_532, "base-sepolia", eth(), Duration::from_secs(2), 10),
+        GrauxNetwork::new(
+            "astar-mainnet",
+            592,
+            "astar-mainnet",
+            NativeCurrency::new("Astar", "ASTR", 18),
+            Duration::from_secs(12),
+            10,
+        )
+        .with_aliases(&["astar"])
+        .without_nft(),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn custom(name: &str, chain_id: u64) -> GrauxNetwork {
+        GrauxNetwork::new(name, chain_id, name, NativeCurrency::new("Ether", "ETH", 18), Duration::from_secs(2), 10)
+    }
+
+    #[test]
+    fn looks_networks_up_by_name_slug_alias_and_chain_id() {
+        let registry = NetworkRegistry::with_builtin_networks();
+        assert_eq!(registry.by_name("Homestead").unwrap().chain_id, 1);
+        assert_eq!(registry.by_name(" matic ").unwrap().chain_id, 137);
+        assert_eq!(registry.by_chain_id(8_453).unwrap().name, "base-mainnet");
+        assert!(registry.by_name("dogechain").is_none());
+    }
+
+    #[test]
+    fn fills_the_api_key_into_url_templates() {
+        let network = lookup_network("sepolia").unwrap();
+        assert_eq!(network.http_url("key"), "https://eth-sepolia.g.graux.com/v2/key");
+        assert_eq!(network.ws_url("key"), "wss://eth-sepolia.g.graux.com/v2/key");
+        assert_eq!(lookup_network("astar").unwrap().nft_url("key"), None);
+    }
+
+    #[test]
+    fn rejects_duplicate_chain_ids_and_names() {
+        let mut registry = NetworkRegistry::with_builtin_networks();
+        assert!(registry.register(custom("my-chain", 1)).is_err());
+        assert!(registry.register(custom("mainnet", 999_001)).is_err());
+        registry.register(custom("my-chain", 999_001)).unwrap();
+        assert_eq!(registry.by_name("MY-CHAIN").unwrap().chain_id, 999_001);
+    }
+
+    #[test]
+    fn networks_are_ordered_by_chain_id() {
+        let registry = NetworkRegistry::with_builtin_networks();
+        let chain_ids: Vec<u64> = registry.networks().iter().map(|n| n.chain_id).collect();
+        let mut sorted = chain_ids.clone();
+        sorted.sort_unstable();
+        assert_eq!(chain_ids, sorted);
+    }
+}
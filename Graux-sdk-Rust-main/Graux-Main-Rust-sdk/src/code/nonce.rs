@@ -0,0 +1,448 @@
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::future::Future;
+use std::time::{Duration, Instant};
+
+use ethers_core::types::transaction::eip2718::TypedTransaction;
+use ethers_core::types::{Address, BlockId, BlockNumber, TxHash, U256};
+use ethers_providers::Middleware;
+use tokio::sync::Mutex;
+
+use crate::error::{GrauxError, GrauxResult};
+
+/// Sent reservations are kept this long for `reservations()` before being pruned.
+const SENT_RETENTION: Duration = Duration::from_secs(600);
+
+/// A nonce handed out by `NonceManager::reserve`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NonceReservation {
+    pub nonce: U256,
+    /// Set once the transaction using this nonce was broadcast.
+    pub tx_hash: Option<TxHash>,
+    pub reserved_at: Instant,
+}
+
+/// Nonce state of one account, as returned by `NonceManager::reservations`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NonceSnapshot {
+    /// Next nonce that will be allocated once `free` is exhausted.
+    pub next: U256,
+    pub reserved: Vec<NonceReservation>,
+    /// Nonces below `next` that were released or found unused and will be handed out first.
+    pub free: Vec<U256>,
+}
+
+#[derive(Debug, Default)]
+struct AccountNonces {
+    next: U256,
+    reserved: BTreeMap<U256, NonceReservation>,
+    free: BTreeSet<U256>,
+}
+
+/// Allocates nonces locally so concurrent senders from one account do not each call
+/// `eth_getTransactionCount`.
+///
+/// The first reservation for an address reads its `pending` transaction count; after that,
+/// nonces are allocated from the cache. Nonces of transactions that fail to broadcast are
+/// reused, and a "nonce too low" / "nonce too high" rejection triggers a resync. When it is
+/// unclear whether a transaction reached the node, such as after a timeout or when the
+/// sending future is dropped, the account is resynced instead.
+#[derive(Debug)]
+pub struct NonceManager<M> {
+    inner: M,
+    accounts: Mutex<HashMap<Address, AccountNonces>>,
+    /// Synchronous so that `InFlight` can update it on drop.
+    sending: std::sync::Mutex<Sending>,
+}
+
+#[derive(Debug, Default)]
+struct Sending {
+    /// Reservations currently being broadcast by `send_with`; `resync` leaves them alone.
+    in_flight: HashSet<(Address, U256)>,
+    /// Accounts whose last send was abandoned; they are resynced before the next reservation.
+    stale: HashSet<Address>,
+}
+
+/// Marks a reservation as being broadcast. If dropped before `finish`, the account is
+/// flagged for a resync.
+struct InFlight<'a> {
+    sending: &'a std::sync::Mutex<Sending>,
+    address: Address,
+    nonce: U256,
+    finished: bool,
+}
+
+impl<'a> InFlight<'a> {
+    fn new(sending: &'a std::sync::Mutex<Sending>, address: Address, nonce: U256) -> Self {
+        lock_sending(sending).in_flight.insert((address, nonce));
+        InFlight {
+            sending,
+            address,
+            nonce,
+            finished: false,
+        }
+    }
+
+    fn finish(mut self) {
+        self.finished = true;
+    }
+}
+
+impl Drop for InFlight<'_> {
+    fn drop(&mut self) {
+        let mut sending = lock_sending(self.sending);
+        sending.in_flight.remove(&(self.address, self.nonce));
+        if !self.finished {
+            sending.stale.insert(self.address);
+        }
+    }
+}
+
+fn lock_sending(sending: &std::sync::Mutex<Sending>) -> std::sync::MutexGuard<'_, Sending> {
+    sending.lock().expect("nonce send tracking lock poisoned")
+}
+
+impl<M> NonceManager<M>
+where
+    M: Middleware,
+    GrauxError: From<M::Error>,
+{
+    pub fn new(inner: M) -> Self {
+        NonceManager {
+            inner,
+            accounts: Mutex::new(HashMap::new()),
+            sending: std::sync::Mutex::new(Sending::default()),
+        }
+    }
+
+    pub fn inner(&self) -> &M {
+        &self.inner
+    }
+
+    /// Allocates the next nonce for `address`. The caller must follow up with `mark_sent` or
+    /// `release`; a reservation that is neither is returned to the pool by `resync`.
+    pub async fn reserve(&self, address: Address) -> GrauxResult<U256> {
+        let mut accounts = self.accounts.lock().await;
+        if lock_sending(&self.sending).stale.remove(&address) {
+            if let Err(err) = self.resync_locked(&mut accounts, address).await {
+                lock_sending(&self.sending).stale.insert(address);
+                return Err(err);
+            }
+        }
+
+        let account = match accounts.entry(address) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                let next = self.pending_count(address).await?;
+                entry.insert(AccountNonces {
+                    next,
+                    ..AccountNonces::default()
+                })
+            }
+        };
+        account.reserved.retain(|_, r| r.tx_hash.is_none() || r.reserved_at.elapsed() < SENT_RETENTION);
+        let nonce = match account.free.pop_first() {
+            Some(nonce) => nonce,
+            None => {
+                let nonce = account.next;
+                account.next += U256::one();
+                nonce
+            }
+        };
+        account.reserved.insert(
+            nonce,
+            NonceReservation {
+                nonce,
+                tx_hash: None,
+                reserved_at: Instant::now(),
+            },
+        );
+        Ok(nonce)
+    }
+
+    /// Records that the transaction using `nonce` was broadcast as `tx_hash`.
+    pub async fn mark_sent(&self, address: Address, nonce: U256, tx_hash: TxHash) {
+        let mut accounts = self.accounts.lock().await;
+        if let Some(reservation) = accounts
+            .get_mut(&address)
+            .and_then(|account| account.reserved.get_mut(&nonce))
+        {
+            reservation.tx_hash = Some(tx_hash);
+        }
+    }
+
+    /// Returns an unused nonce so that it is handed out again instead of leaving a gap.
+    pub async fn release(&self, address: Address, nonce: U256) {
+        let mut accounts = self.accounts.lock().await;
+        let Some(account) = accounts.get_mut(&address) else {
+            return;
+        };
+        if account.reserved.remove(&nonce).is_none() {
+            return;
+        }
+        if nonce + U256::one() == account.next {
+            account.next = nonce;
+        } else {
+            account.free.insert(nonce);
+        }
+    }
+
+    /// Re-reads the `pending` transaction count of `address` and rebuilds its state.
+    ///
+    /// Reservations below the pending count are dropped, as are reservations that were never
+    /// marked sent and are not being broadcast. Unreserved nonces between the pending count
+    /// and the highest remaining reservation are marked free, so the gap is filled by the
+    /// next reservations.
+    pub async fn resync(&self, address: Address) -> GrauxResult<()> {
+        let mut accounts = self.accounts.lock().await;
+        self.resync_locked(&mut accounts, address).await
+    }
+
+    async fn resync_locked(&self, accounts: &mut HashMap<Address, AccountNonces>, address: Address) -> GrauxResult<()> {
+        let pending = self.pending_count(address).await?;
+        let account = accounts.entry(address).or_default();
+
+        account.reserved = account.reserved.split_off(&pending);
+        let sending = lock_sending(&self.sending);
+        account
+            .reserved
+            .retain(|nonce, r| r.tx_hash.is_some() || sending.in_flight.contains(&(address, *nonce)));
+        drop(sending);
+        let highest = account.reserved.keys().next_back().copied();
+        account.next = highest.map_or(pending, |n| n + U256::one()).max(pending);
+
+        account.free.clear();
+        let mut nonce = pending;
+        while nonce < account.next {
+            if !account.reserved.contains_key(&nonce) {
+                account.free.insert(nonce);
+            }
+            nonce += U256::one();
+        }
+        Ok(())
+    }
+
+    /// Snapshot of the reservation table, for debugging.
+    pub async fn reservations(&self) -> HashMap<Address, NonceSnapshot> {
+        let accounts = self.accounts.lock().await;
+        accounts
+            .iter()
+            .map(|(address, account)| {
+                (
+                    *address,
+                    NonceSnapshot {
+                        next: account.next,
+                        reserved: account.reserved.values().cloned().collect(),
+                        free: account.free.iter().copied().collect(),
+                    },
+                )
+            })
+            .collect()
+    }
+
+    /// Fills in a nonce for `tx` and broadcasts it. On a nonce rejection the account is
+    /// resynced and the transaction retried once with a fresh nonce.
+    pub async fn send_transaction(&self, tx: TypedTransaction) -> GrauxResult<TxHash> {
+        let from = *tx
+            .from()
+            .ok_or_else(|| GrauxError::InvalidArgument("transaction has no from address".to_owned()))?;
+
+        self.send_with(from, |nonce| {
+            let mut tx = tx.clone();
+            tx.set_nonce(nonce);
+            async move { Ok(self.inner.send_transaction(tx, None).await?.tx_hash()) }
+        })
+        .await
+    }
+
+    /// Reserves a nonce for `from` and passes it to `send`, with the same release and
+    /// resync-and-retry handling as `send_transaction`. Used by callers that broadcast
+    /// through something other than `eth_sendTransaction`, such as a local signer.
+    ///
+    /// The nonce is released right away only if the node definitely rejected the
+    /// transaction. After an ambiguous failure the account is resynced, so the nonce is reused
+    /// only if the node did not take the transaction.
+    pub async fn send_with<F, Fut>(&self, from: Address, mut send: F) -> GrauxResult<TxHash>
+    where
+        F: FnMut(U256) -> Fut,
+        Fut: Future<Output = GrauxResult<TxHash>>,
+    {
+        let mut resynced = false;
+        loop {
+            let nonce = self.reserve(from).await?;
+            let in_flight = InFlight::new(&self.sending, from, nonce);
+
+            let err = match send(nonce).await {
+                Ok(tx_hash) => {
+                    self.mark_sent(from, nonce, tx_hash).await;
+                    in_flight.finish();
+                    return Ok(tx_hash);
+                }
+                Err(err) => err,
+            };
+
+            if !is_definite_rejection(&err) {
+                // Dropping the guard unfinished flags the account in case this resync fails.
+                drop(in_flight);
+                if self.resync(from).await.is_ok() {
+                    lock_sending(&self.sending).stale.remove(&from);
+                }
+                return Err(err);
+            }
+
+            self.release(from, nonce).await;
+            in_flight.finish();
+            if resynced || !is_nonce_error(&err) {
+                return Err(err);
+            }
+            self.resync(from).await?;
+            resynced = true;
+        }
+    }
+
+    async fn pending_count(&self, address: Address) -> GrauxResult<U256> {
+        Ok(self
+            .inner
+            .get_transaction_count(address, Some(BlockId::Number(BlockNumber::Pending)))
+            .await?)
+    }
+}
+
+/// Whether `error` shows the transaction was not accepted, so its nonce can be reused at once.
+/// Transport failures, timeouts and server errors are ambiguous: the node may have taken it.
+fn is_definite_rejection(error: &GrauxError) -> bool {
+    match error {
+        GrauxError::JsonRpc { .. }
+        | GrauxError::ExecutionReverted { .. }
+        | GrauxError::RateLimited { .. }
+        | GrauxError::Unauthorized(_)
+        | GrauxError::InvalidArgument(_)
+        | GrauxError::Signer(_) => true,
+        GrauxError::Http { status, .. } => *status < 500,
+        _ => false,
+    }
+}
+
+/// Whether the node rejected a transaction because of its nonce. Nodes only report this
+/// through the error message, so the message is matched against the common wordings.
+pub fn is_nonce_error(error: &GrauxError) -> bool {
+    let GrauxError::JsonRpc { message, .. } = error else {
+        return false;
+    };
+    let message = message.to_ascii_lowercase();
+    ["nonce too low", "nonce too high", "nonce gap", "invalid nonce"]
+        .iter()
+        .any(|pattern| message.contains(pattern))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers_providers::{MockProvider, Provider};
+    use futures_util::FutureExt;
+
+    const ACCOUNT: Address = Address::repeat_byte(0x11);
+
+    /// A manager whose provider reports `counts` as the pending transaction count, in order.
+    fn manager(counts: &[u64]) -> (NonceManager<Provider<MockProvider>>, MockProvider) {
+        let (provider, mock) = Provider::mocked();
+        // The mock answers requests last-pushed first.
+        for count in counts.iter().rev() {
+            mock.push::<U256, _>(U256::from(*count)).unwrap();
+        }
+        (NonceManager::new(provider), mock)
+    }
+
+    async fn snapshot(manager: &NonceManager<Provider<MockProvider>>) -> (u64, Vec<u64>, Vec<u64>) {
+        let snapshot = manager.reservations().await.remove(&ACCOUNT).unwrap();
+        (
+            snapshot.next.as_u64(),
+            snapshot.reserved.iter().map(|r| r.nonce.as_u64()).collect(),
+            snapshot.free.iter().map(|n| n.as_u64()).collect(),
+        )
+    }
+
+    fn rpc_error(message: &str) -> GrauxError {
+        GrauxError::JsonRpc {
+            code: -32000,
+            message: message.to_owned(),
+            data: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn allocates_from_the_pending_count_and_reuses_released_nonces() {
+        let (manager, _mock) = manager(&[5]);
+        for expected in 5..8u64 {
+            assert_eq!(manager.reserve(ACCOUNT).await.unwrap(), U256::from(expected));
+        }
+
+        manager.release(ACCOUNT, U256::from(6)).await;
+        assert_eq!(snapshot(&manager).await, (8, vec![5, 7], vec![6]));
+        assert_eq!(manager.reserve(ACCOUNT).await.unwrap(), U256::from(6));
+
+        manager.release(ACCOUNT, U256::from(7)).await;
+        assert_eq!(snapshot(&manager).await.0, 7);
+    }
+
+    #[tokio::test]
+    async fn resync_frees_gaps_and_unsent_reservations() {
+        let (manager, _mock) = manager(&[5, 5]);
+        for _ in 0..4 {
+            manager.reserve(ACCOUNT).await.unwrap();
+        }
+        manager.mark_sent(ACCOUNT, U256::from(7), TxHash::repeat_byte(7)).await;
+
+        manager.resync(ACCOUNT).await.unwrap();
+        assert_eq!(snapshot(&manager).await, (8, vec![7], vec![5, 6]));
+    }
+
+    #[tokio::test]
+    async fn definite_rejections_release_the_nonce() {
+        let (manager, _mock) = manager(&[5]);
+        let result = manager
+            .send_with(ACCOUNT, |_| async { Err(rpc_error("insufficient funds")) })
+            .await;
+        assert!(result.is_err());
+        assert_eq!(snapshot(&manager).await, (5, vec![], vec![]));
+    }
+
+    #[tokio::test]
+    async fn nonce_errors_resync_and_retry_once() {
+        let (manager, _mock) = manager(&[5, 9]);
+        let mut tried = Vec::new();
+        let hash = manager
+            .send_with(ACCOUNT, |nonce| {
+                tried.push(nonce.as_u64());
+                let result = match nonce.as_u64() {
+                    5 => Err(rpc_error("nonce too low")),
+                    _ => Ok(TxHash::repeat_byte(1)),
+                };
+                async move { result }
+            })
+            .await
+            .unwrap();
+        assert_eq!(hash, TxHash::repeat_byte(1));
+        assert_eq!(tried, vec![5, 9]);
+    }
+
+    #[tokio::test]
+    async fn ambiguous_failures_resync_instead_of_releasing() {
+        // The node did take the transaction: the pending count moved past its nonce.
+        let (manager, _mock) = manager(&[5, 6]);
+        let result = manager.send_with(ACCOUNT, |_| async { Err(GrauxError::Timeout) }).await;
+        assert!(matches!(result, Err(GrauxError::Timeout)));
+        assert_eq!(snapshot(&manager).await, (6, vec![], vec![]));
+    }
+
+    #[tokio::test]
+    async fn abandoned_sends_resync_before_the_next_reservation() {
+        let (manager, _mock) = manager(&[5, 5]);
+        let abandoned = manager
+            .send_with(ACCOUNT, |_| futures_util::future::pending())
+            .now_or_never();
+        assert!(abandoned.is_none());
+
+        assert_eq!(manager.reserve(ACCOUNT).await.unwrap(), U256::from(5));
+    }
+}
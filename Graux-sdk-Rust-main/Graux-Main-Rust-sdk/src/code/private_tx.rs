@@ -0,0 +1,466 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use ethers_core::types::TransactionReceipt;
+use futures_util::future::BoxFuture;
+use futures_util::Stream;
+use tokio::sync::{oneshot, watch};
+
+use crate::error::{GrauxError, GrauxResult};
+use crate::transact::{Graux, SendPrivateTransactionOptions};
+
+/// Consecutive polling failures tolerated before the handle resolves with the error.
+const MAX_CONSECUTIVE_ERRORS: u32 = 5;
+
+/// Produces a freshly signed replacement transaction for resubmission attempt `n`
+/// (starting at 1), typically with higher fees.
+pub type ResignFn = Arc<dyn Fn(u32) -> BoxFuture<'static, GrauxResult<String>> + Send + Sync>;
+
+/// What to do when a private transaction expires without being included.
+#[derive(Clone, Default)]
+pub enum ExpiryAction {
+    /// Resolve with `PrivateTxOutcome::Expired`.
+    #[default]
+    Report,
+    /// Call `eth_cancelPrivateTransaction` and resolve with `PrivateTxOutcome::Cancelled`.
+    Cancel,
+    /// Submit the transaction returned by `resign`, valid for another `blocks` blocks, at
+    /// most `max_attempts` times.
+    Resubmit {
+        resign: ResignFn,
+        max_attempts: u32,
+        blocks: u64,
+    },
+}
+
+/// How a `PrivateTxHandle` follows its transaction.
+#[derive(Clone)]
+pub struct PrivateTxTracking {
+    pub poll_interval: Duration,
+    /// Block at which to give up even if `max_block_number` is later. Required for
+    /// `ExpiryAction::Cancel` to be useful, since Graux drops the transaction by itself once
+    /// `max_block_number` has passed.
+    pub deadline_block: Option<u64>,
+    pub on_expiry: ExpiryAction,
+}
+
+impl Default for PrivateTxTracking {
+    fn default() -> Self {
+        PrivateTxTracking {
+            poll_interval: Duration::from_secs(2),
+            deadline_block: None,
+            on_expiry: ExpiryAction::Report,
+        }
+    }
+}
+
+/// Progress of a tracked private transaction.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PrivateTxStatus {
+    Submitted { hash: String },
+    Pending { hash: String, block: u64 },
+    Resubmitted { hash: String, attempt: u32 },
+    Included(Box<TransactionReceipt>),
+    Expired { hash: String, block: u64 },
+    Cancelled { hash: String },
+}
+
+/// Final result of a tracked private transaction.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PrivateTxOutcome {
+    Included(Box<TransactionReceipt>),
+    Expired,
+    Cancelled,
+}
+
+/// Handle to a private transaction being tracked in the background.
+///
+/// Await it for the final `PrivateTxOutcome`, or use `statuses` to follow progress.
+pub struct PrivateTxHandle {
+    hash: String,
+    status: watch::Receiver<PrivateTxStatus>,
+    outcome: oneshot::Receiver<GrauxResult<PrivateTxOutcome>>,
+}
+
+impl PrivateTxHandle {
+    pub(crate) fn spawn(
+        graux: Graux,
+        hash: String,
+        max_block_number: Option<u64>,
+        options: Option<SendPrivateTransactionOptions>,
+        tracking: PrivateTxTracking,
+    ) -> Self {
+        let (status_tx, status) = watch::channel(PrivateTxStatus::Submitted { hash: hash.clone() });
+        let (outcome_tx, outcome) = oneshot::channel();
+        let tracker = Tracker {
+            graux,
+            hash: hash.clone(),
+            max_block_number,
+            earlier: Vec::new(),
+            options,
+            tracking,
+            status: status_tx,
+        };
+        tokio::spawn(async move {
+            let _ = outcome_tx.send(tracker.run().await);
+        });
+
+        PrivateTxHandle { hash, status, outcome }
+    }
+
+    /// Hash of the originally submitted transaction.
+    pub fn hash(&self) -> &str {
+        &self.hash
+    }
+
+    pub fn status(&self) -> PrivateTxStatus {
+        self.status.borrow().clone()
+    }
+
+    /// Stream of status updates, starting with the current one. Intermediate updates may be
+    /// skipped if the consumer is slower than the tracker; the final status is always seen.
+    pub fn statuses(&self) -> impl Stream<Item = PrivateTxStatus> {
+        futures_util::stream::unfold((self.status.clone(), true), |(mut status, first)| async move {
+            if !first {
+                status.changed().await.ok()?;
+            }
+            let current = status.borrow_and_update().clone();
+            Some((current, (status, false)))
+        })
+    }
+}
+
+impl Future for PrivateTxHandle {
+    type Output = GrauxResult<PrivateTxOutcome>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        match Pin::new(&mut self.outcome).poll(cx) {
+            Poll::Ready(Ok(outcome)) => Poll::Ready(outcome),
+            Poll::Ready(Err(_)) => Poll::Ready(Err(GrauxError::Transport(
+                "private transaction tracker stopped".to_owned(),
+            ))),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+/// A replaced submission, polled until its own `max_block_number` has passed since it may
+/// still be included.
+struct Submission {
+    hash: String,
+    max_block_number: Option<u64>,
+}
+
+enum Check {
+    Included(Box<TransactionReceipt>),
+    Pending { block: u64 },
+}
+
+struct Tracker {
+    graux: Graux,
+    /// The latest submission.
+    hash: String,
+    max_block_number: Option<u64>,
+    earlier: Vec<Submission>,
+    options: Option<SendPrivateTransactionOptions>,
+    tracking: PrivateTxTracking,
+    status: watch::Sender<PrivateTxStatus>,
+}
+
+impl Tracker {
+    async fn run(mut self) -> GrauxResult<PrivateTxOutcome> {
+        let mut attempt = 0;
+        let mut errors = 0;
+
+        loop {
+            tokio::time::sleep(self.tracking.poll_interval).await;
+
+            let block = match self.check().await {
+                Ok(Check::Included(receipt)) => return Ok(self.included(*receipt)),
+                Ok(Check::Pending { block }) => {
+                    errors = 0;
+                    block
+                }
+                Err(err) => {
+                    errors += 1;
+                    if errors >= MAX_CONSECUTIVE_ERRORS {
+                        return Err(err);
+                    }
+                    continue;
+                }
+            };
+            self.earlier
+                .retain(|submission| !past_max_block(submission.max_block_number, block));
+
+            if !self.is_expired(block) {
+                let _ = self.status.send(PrivateTxStatus::Pending {
+                    hash: self.hash.clone(),
+                    block,
+                });
+                continue;
+            }
+
+            match self.tracking.on_expiry.clone() {
+                ExpiryAction::Cancel => return self.cancel(block).await,
+                ExpiryAction::Resubmit {
+                    resign,
+                    max_attempts,
+                    blocks,
+                } if attempt < max_attempts => {
+                    attempt += 1;
+                    let signed = resign(attempt).await?;
+                    let max_block_number = Some(block + blocks);
+                    let hash = self
+                        .graux
+                        .submit_private_transaction(signed, max_block_number, self.options.clone())
+                        .await?;
+                    self.earlier.push(Submission {
+                        hash: std::mem::replace(&mut self.hash, hash),
+                        max_block_number: std::mem::replace(&mut self.max_block_number, max_block_number),
+                    });
+                    self.tracking.deadline_block = None;
+                    let _ = self.status.send(PrivateTxStatus::Resubmitted {
+                        hash: self.hash.clone(),
+                        attempt,
+                    });
+                }
+                _ => return Ok(self.expired(block)),
+            }
+        }
+    }
+
+    /// Polls for a receipt, then reads the current block number if there is none.
+    async fn check(&self) -> GrauxResult<Check> {
+        if let Some(receipt) = self.poll().await? {
+            return Ok(Check::Included(Box::new(receipt)));
+        }
+        let block = self.graux.get_block_number().await?;
+        Ok(Check::Pending { block })
+    }
+
+    /// Looks for a receipt of the latest submission, then of the earlier ones still live.
+    async fn poll(&self) -> GrauxResult<Option<TransactionReceipt>> {
+        let hashes = std::iter::once(&self.hash).chain(self.earlier.iter().map(|submission| &submission.hash));
+        for hash in hashes {
+            if let Some(receipt) = self.graux.get_private_transaction_receipt(hash.clone()).await? {
+                return Ok(Some(receipt));
+            }
+        }
+        Ok(None)
+    }
+
+    fn is_expired(&self, block: u64) -> bool {
+        past_max_block(self.max_block_number, block)
+            || self.tracking.deadline_block.is_some_and(|deadline| block >= deadline)
+    }
+
+    async fn cancel(&self, block: u64) -> GrauxResult<PrivateTxOutcome> {
+        if self.graux.cancel_private_transaction(self.hash.clone()).await? {
+            let _ = self.status.send(PrivateTxStatus::Cancelled {
+                hash: self.hash.clone(),
+            });
+            return Ok(PrivateTxOutcome::Cancelled);
+        }
+        // Cancellation is refused once the transaction is mined or already dropped.
+        match self.poll().await? {
+            Some(receipt) => Ok(self.included(receipt)),
+            None => Ok(self.expired(block)),
+        }
+    }
+
+    fn included(&self, receipt: TransactionReceipt) -> PrivateTxOutcome {
+        let receipt = Box::new(receipt);
+        let _ = self.status.send(PrivateTxStatus::Included(receipt.clone()));
+        PrivateTxOutcome::Included(receipt)
+    }
+
+    fn expired(&self, block: u64) -> PrivateTxOutcome {
+        let _ = self.status.send(PrivateTxStatus::Expired {
+            hash: self.hash.clone(),
+            block,
+        });
+        PrivateTxOutcome::Expired
+    }
+}
+
+/// Whether a transaction submitted with `max_block_number` can no longer be included at `block`.
+fn past_max_block(max_block_number: Option<u64>, block: u64) -> bool {
+    max_block_number.is_some_and(|max| block > max)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::sync::Mutex;
+
+    use ethers_core::types::H256;
+    use ethers_providers::{Http, Provider};
+    use serde_json::{json, Value};
+
+    use crate::ens::EnsResolver;
+    use crate::mock_http::{MockResponse, MockServer};
+
+    const ORIGINAL: H256 = H256::repeat_byte(0xaa);
+    const REPLACEMENT: H256 = H256::repeat_byte(0xbb);
+
+    type Requests = Arc<Mutex<Vec<(String, Value)>>>;
+
+    /// Starts a node that answers each call with `respond(method, first param, calls)`, where
+    /// `calls` counts the earlier calls of the same method. Every call is recorded.
+    async fn node<F>(respond: F) -> (MockServer, Requests)
+    where
+        F: Fn(&str, &Value, usize) -> Value + Send + Sync + 'static,
+    {
+        let requests: Requests = Arc::default();
+        let log = requests.clone();
+        let calls = Mutex::new(HashMap::<String, usize>::new());
+        let server = MockServer::start(move |request| {
+            let body: Value = serde_json::from_str(&request.body).unwrap();
+            let method = body["method"].as_str().unwrap().to_owned();
+            let param = body["params"][0].clone();
+            let count = {
+                let mut calls = calls.lock().unwrap();
+                let count = calls.entry(method.clone()).or_default();
+                *count += 1;
+                *count - 1
+            };
+            let result = respond(&method, &param, count);
+            log.lock().unwrap().push((method, param));
+            MockResponse::json(200, json!({ "jsonrpc": "2.0", "id": body["id"], "result": result }).to_string())
+        })
+        .await;
+        (server, requests)
+    }
+
+    fn receipt(hash: H256) -> Value {
+        json!(TransactionReceipt {
+            transaction_hash: hash,
+            ..Default::default()
+        })
+    }
+
+    fn track(server: &MockServer, max_block_number: u64, on_expiry: ExpiryAction) -> PrivateTxHandle {
+        let provider = Provider::<Http>::try_from(server.url.as_str()).unwrap();
+        let graux = Graux::new(provider.clone(), Arc::new(EnsResolver::new(provider)));
+        let tracking = PrivateTxTracking {
+            poll_interval: Duration::from_millis(1),
+            deadline_block: None,
+            on_expiry,
+        };
+        PrivateTxHandle::spawn(graux, format!("{ORIGINAL:?}"), Some(max_block_number), None, tracking)
+    }
+
+    fn included(outcome: &PrivateTxOutcome) -> Option<H256> {
+        match outcome {
+            PrivateTxOutcome::Included(receipt) => Some(receipt.transaction_hash),
+            _ => None,
+        }
+    }
+
+    #[tokio::test]
+    async fn resolves_with_the_receipt_once_included() {
+        let (server, _) = node(|method, _, calls| match method {
+            "eth_getPrivateTransactionReceipt" if calls == 0 => Value::Null,
+            "eth_getPrivateTransactionReceipt" => receipt(ORIGINAL),
+            "eth_blockNumber" => json!("0xa"),
+            method => panic!("unexpected {method}"),
+        })
+        .await;
+
+        let handle = track(&server, 20, ExpiryAction::Report);
+        let status = handle.status.clone();
+        let outcome = handle.await.unwrap();
+
+        assert_eq!(included(&outcome), Some(ORIGINAL));
+        assert!(matches!(&*status.borrow(), PrivateTxStatus::Included(_)));
+    }
+
+    #[tokio::test]
+    async fn reports_expiry_after_max_block_number() {
+        let (server, _) = node(|method, _, calls| match method {
+            "eth_getPrivateTransactionReceipt" => Value::Null,
+            "eth_blockNumber" if calls == 0 => json!("0xa"),
+            "eth_blockNumber" => json!("0xc"),
+            method => panic!("unexpected {method}"),
+        })
+        .await;
+
+        let handle = track(&server, 11, ExpiryAction::Report);
+        let status = handle.status.clone();
+        let outcome = handle.await.unwrap();
+
+        assert_eq!(outcome, PrivateTxOutcome::Expired);
+        assert_eq!(
+            *status.borrow(),
+            PrivateTxStatus::Expired {
+                hash: format!("{ORIGINAL:?}"),
+                block: 12,
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn refused_cancel_of_a_mined_transaction_resolves_as_included() {
+        // The transaction is mined between the expiry check and the cancel request.
+        let (server, requests) = node(|method, _, calls| match method {
+            "eth_getPrivateTransactionReceipt" if calls == 0 => Value::Null,
+            "eth_getPrivateTransactionReceipt" => receipt(ORIGINAL),
+            "eth_blockNumber" => json!("0xc"),
+            "eth_cancelPrivateTransaction" => json!(false),
+            method => panic!("unexpected {method}"),
+        })
+        .await;
+
+        let outcome = track(&server, 11, ExpiryAction::Cancel).await.unwrap();
+
+        assert_eq!(included(&outcome), Some(ORIGINAL));
+        let requests = requests.lock().unwrap();
+        assert!(requests.contains(&(
+            "eth_cancelPrivateTransaction".to_owned(),
+            json!({ "txHash": format!("{ORIGINAL:?}") })
+        )));
+    }
+
+    #[tokio::test]
+    async fn keeps_polling_the_original_after_resubmitting() {
+        // Receipt polls go original, replacement, original; the original is found on the third.
+        let (server, requests) = node(|method, param, calls| match method {
+            "eth_getPrivateTransactionReceipt" if calls >= 2 && param["txHash"] == format!("{ORIGINAL:?}") => {
+                receipt(ORIGINAL)
+            }
+            "eth_getPrivateTransactionReceipt" => Value::Null,
+            "eth_blockNumber" => json!("0xc"),
+            "eth_sendPrivateTransaction" => json!(format!("{REPLACEMENT:?}")),
+            method => panic!("unexpected {method}"),
+        })
+        .await;
+        let resign: ResignFn = Arc::new(|attempt| Box::pin(async move { Ok(format!("0xsigned{attempt}")) }));
+        let on_expiry = ExpiryAction::Resubmit {
+            resign,
+            max_attempts: 1,
+            blocks: 5,
+        };
+
+        let outcome = track(&server, 11, on_expiry).await.unwrap();
+
+        assert_eq!(included(&outcome), Some(ORIGINAL));
+        let requests = requests.lock().unwrap();
+        let (_, submitted) = requests
+            .iter()
+            .find(|(method, _)| method == "eth_sendPrivateTransaction")
+            .expect("resubmitted");
+        assert_eq!(submitted["tx"], "0xsigned1");
+    }
+
+    #[test]
+    fn submissions_stay_live_through_their_max_block() {
+        assert!(!past_max_block(Some(100), 99));
+        assert!(!past_max_block(Some(100), 100));
+        assert!(past_max_block(Some(100), 101));
+        assert!(!past_max_block(None, u64::MAX));
+    }
+}
@@ -1,5 +1,3 @@
-use std::convert::TryInto;
-
 use ethers::prelude::*;
 use ethers::providers::{
     Middleware, Provider, ProviderError, JsonRpcClient, Http, MiddlewareAction, MiddlewareContext,
@@ -7,33 +5,28 @@ use ethers::providers::{
 use ethers::types::{Address, BlockId, U256};
 
 use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::time::Duration;
 
 use graux::const::*;
 use graux::logger::*;
 use graux::util::*;
 
-*Define custom error types for the provider*
-#[derive(Debug, thiserror::Error)]
-enum GrauxProviderError {
-    #[error("No network detected")]
-    NoNetworkDetected,
-
-    #[error("Invalid network: {0}")]
-    InvalidNetwork(String),
-
-    #[error("Invalid API key: {0}")]
-    InvalidApiKey(String),
-
-    #[error(transparent)]
-    ProviderError(#[from] ProviderError),
-}
+use crate::batch::{self, BatchTransport};
+use crate::ens::EnsResolver;
+use crate::error::{GrauxError, GrauxResult};
+use crate::network::{lookup_network, GrauxNetwork};
+use crate::reorg::{self, ReorgAwareStream, ReorgConfig};
+use crate::retry::RetryPolicy;
+use crate::signer::GrauxSigner;
 
 Custom implementation of GrauxProvider
+#[derive(Clone)]
 pub struct GrauxProvider<C: JsonRpcClient + Clone> {
     provider: Provider<C>,
     api_key: String,
-    max_retries: u32,
-    batch_requests: bool,
+    retry_policy: RetryPolicy,
+    ens: Arc<EnsResolver<Provider<C>>>,
 }
 
 impl<C: JsonRpcClient + Clone> GrauxProvider<C> {
@@ -41,8 +34,8 @@ impl<C: JsonRpcClient + Clone> GrauxProvider<C> {
     pub async fn new(
         config: GrauxConfig,
         client: C,
-    ) -> Result<Self, GrauxProviderError> {
-        let api_key = GrauxProvider::get_api_key(config.api_key);
+    ) -> GrauxResult<Self> {
+        let api_key = GrauxProvider::get_api_key(config.api_key, config.url.as_deref())?;
 
         // Generate our own connection info with the correct endpoint URLs
         let graux_network = GrauxProvider::get_graux_network(config.network)?;
@@ -58,54 +51,60 @@ impl<C: JsonRpcClient + Clone> GrauxProvider<C> {
             connection.url = url;
         }
 
-        connection.throttle_limit = config.max_retries;
-
         Normalize the Graux named network input to the network names used by ethers.
         This allows the parent provider to correctly set the network.
-        let ethers_network: Network = graux_network.into();
+        let ethers_network = Network::from(graux_network.chain_id);
         let provider = Provider::new(client, ethers_network, connection).await?;
 
         Ok(Self {
+            ens: Arc::new(EnsResolver::new(provider.clone())),
             provider,
             api_key,
-            max_retries: config.max_retries,
-            batch_requests: config.batch_requests,
+            retry_policy: RetryPolicy::new(config.max_retries),
         })
     }
 
-    Normalize the API key to a string.
-    fn get_api_key(api_key: Option<&str>) -> String {
-        api_key
-            .map(|key| key.to_owned())
-            .unwrap_or_else(|| DEFAULT_GRAUX_API_KEY.to_owned())
+    ENS resolver with a TTL cache, for forward and reverse lookups and text records.
+    Pass it to the namespace constructors so an `address_or_name` resolves through one cache.
+    pub fn ens(&self) -> Arc<EnsResolver<Provider<C>>> {
+        self.ens.clone()
     }
 
-    Converts the `Network` input to the network enum used by Graux.
-    fn get_graux_network(network: Option<&str>) -> Result<Network, GrauxProviderError> {
-        if let Some(network) = network {
-            match network {
-                "mainnet" => Ok(Network::Mainnet),
-                "ropsten" => Ok(Network::Ropsten),
-                "rinkeby" => Ok(Network::Rinkeby),
-                "goerli" => Ok(Network::Goerli),
-                "kovan" => Ok(Network::Kovan),
-                _ => Err(GrauxProviderError::InvalidNetwork(network.to_owned())),
-            }
-        } else {
-            Ok(DEFAULT_NETWORK)
+    Replaces the retry policy derived from `max_retries`, e.g. to tune the backoff bounds
+    or to allow replaying non-idempotent methods with `retry_non_idempotent`.
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    Normalize the API key to a string. A config with only a custom URL has no key, and the
+    network endpoints built with the empty key are replaced by that URL.
+    fn get_api_key(api_key: Option<&str>, url: Option<&str>) -> GrauxResult<String> {
+        match (api_key, url) {
+            (Some(api_key), _) => Ok(api_key.to_owned()),
+            (None, Some(_)) => Ok(String::new()),
+            (None, None) => Err(GrauxError::InvalidApiKey("API key is missing".to_owned())),
+        }
+    }
+
+    Resolves the network name against the network registry.
+    fn get_graux_network(network: Option<&str>) -> GrauxResult<GrauxNetwork> {
+        match network {
+            Some(network) => lookup_network(network),
+            None => Ok(GrauxNetwork::default()),
         }
     }
 
     Returns a connection info object compatible with ethers that contains
     the correct URLs for Graux.
     fn get_graux_connection_info(
-        network: &Network,
+        network: &GrauxNetwork,
         api_key: &str,
         protocol: &str,
     ) -> ConnectionInfo {
         let url = match protocol {
-            "http" => get_graux_http_url(network, api_key),
-            "ws" => get_graux_ws_url(network, api_key),
+            "http" => network.http_url(api_key),
+            "ws" => network.ws_url(api_key),
             _ => unreachable!("Invalid protocol specified"),
         };
 
@@ -124,6 +123,45 @@ impl<C: JsonRpcClient + Clone> GrauxProvider<C> {
     }
 }
 
+impl<C: JsonRpcClient + Clone + 'static> GrauxProvider<C> {
+    Polls for new blocks and reports reorgs within `config.window` blocks as explicit
+    `Reorged` events, unlike `watch_blocks` which only forwards new hashes.
+    pub fn watch_blocks_with_reorgs(&self, config: ReorgConfig) -> ReorgAwareStream {
+        reorg::watch_blocks(self.provider.clone(), config)
+    }
+
+    Returns a client that signs with `wallet` locally and sends raw transactions. Use it
+    instead of `send_transaction` / `sign` below, which ask the node to sign.
+    The signer talks to the node through this provider, so signed sends get the same
+    retries and error mapping as every other request.
+    pub fn with_signer(&self, wallet: LocalWallet) -> GrauxSigner<GrauxProvider<C>> {
+        GrauxSigner::new(self.clone(), wallet)
+    }
+}
+
+impl GrauxProvider<BatchTransport> {
+    Creates a provider whose concurrent requests are coalesced into JSON-RPC batches.
+    This is the provider `GrauxConfig::get_provider` builds when `batch_requests` is enabled.
+    The batch client gets the same timeout, auth token and SDK header as the default one.
+    pub async fn new_batched(config: GrauxConfig) -> GrauxResult<Self> {
+        let url = match &config.url {
+            Some(url) => url.clone(),
+            None => {
+                let api_key = GrauxProvider::get_api_key(config.api_key, None)?;
+                GrauxProvider::get_graux_network(config.network)?.http_url(&api_key)
+            }
+        };
+        let http = batch::http_client(
+            Duration::from_millis(config.request_timeout.into()),
+            config.auth_token.as_deref(),
+            &[("Graux-Ethers-Sdk-Version", VERSION)],
+        )?;
+        let client = BatchTransport::with_client(url, config.batch_config.clone().unwrap_or_default(), http);
+
+        GrauxProvider::new(config, client).await
+    }
+}
+
 impl<C: JsonRpcClient + Clone> Middleware for GrauxProvider<C> {
     fn on_response<F>(
         &self,
@@ -134,7 +172,6 @@ impl<C: JsonRpcClient + Clone> Middleware for GrauxProvider<C> {
         F: Fn(&MiddlewareContext) -> MiddlewareAction,
     {
         let method = context.request.method.clone();
-        let params = context.request.params.clone();
         let method_name = context.method_name().to_owned();
         let mut headers = context.client.headers().clone();
         headers.insert(
@@ -142,21 +179,6 @@ impl<C: JsonRpcClient + Clone> Middleware for GrauxProvider<C> {
             method_name.to_owned(),
         );
 
-        if self.batch_requests {
-            let batch_client = context.client.clone();
-            let batch_fn = move |requests: Vec<JsonRpcRequest>| {
-                let client = batch_client.clone();
-                async move {
-                    let response = client.batch_send(&requests).await?;
-                    Ok(response)
-                }
-            };
-
-            return self
-                .provider
-                .send_batch(params.try_into().unwrap(), batch_fn);
-        }
-
         let result = next(context);
 
         match result {
@@ -169,6 +191,31 @@ impl<C: JsonRpcClient + Clone> Middleware for GrauxProvider<C> {
                 let response = match response {
                     Ok(res) => res,
                     Err(err) => {
+                        // Prefer the HTTP status when there is one so that 429/5xx and
+                        // `Retry-After` are classified without inspecting error text.
+                        let graux_err = match context.response_status() {
+                            Some(status) if !(200..300).contains(&status) => {
+                                GrauxError::from_http_status(
+                                    status,
+                                    context.response_header("Retry-After"),
+                                    err.to_string(),
+                                )
+                            }
+                            _ => GrauxError::from(err.clone()),
+                        };
+                        if let Some(delay) =
+                            self.retry_policy
+                                .retry_delay(&method, context.attempt(), &graux_err)
+                        {
+                            self.provider.emit_debug_event(
+                                "retry",
+                                context.request.clone(),
+                                Err(err.clone().into()),
+                            );
+
+                            return MiddlewareAction::Retry(context.retry_after(delay));
+                        }
+
                         self.provider.emit_debug_event(
                             "response",
                             context.request.clone(),
@@ -370,8 +417,12 @@ impl<C: JsonRpcClient + Clone> ethers::providers::SignerProvider
         self.provider.estimate_gas(tx, block)
     }
 
-    fn resolve_name(&self, ens_name: &str) -> ethers::providers::ProviderResult<Option<Address>> {
-        self.provider.resolve_name(ens_name)
+    Goes through the shared resolver, so lookups hit the same cache as the namespaces.
+    async fn resolve_name(&self, ens_name: &str) -> ethers::providers::ProviderResult<Option<Address>> {
+        self.ens
+            .resolve_name(ens_name)
+            .await
+            .map_err(|e| ProviderError::EnsError(e.to_string()))
     }
 
     fn sign<T: Into<Bytes>>(&self, data: T, address: Address) -> ethers::providers::ProviderResult<Signature> {
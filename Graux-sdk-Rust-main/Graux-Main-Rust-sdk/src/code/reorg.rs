@@ -0,0 +1,361 @@
+use std::collections::{HashMap, VecDeque};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use ethers_core::types::{Block, BlockNumber, Filter, Log, TxHash, H256};
+use ethers_providers::Middleware;
+use futures_util::{Stream, StreamExt};
+use tokio::sync::mpsc;
+
+use crate::error::{GrauxError, GrauxResult};
+
+/// Settings for a reorg-aware block stream.
+#[derive(Debug, Clone)]
+pub struct ReorgConfig {
+    /// Number of recent blocks whose hashes are kept to find the common ancestor of a reorg.
+    pub window: usize,
+    /// How often the latest block is polled when the stream is not fed by a subscription.
+    pub poll_interval: Duration,
+    /// Logs to deliver alongside blocks. Logs of dropped blocks are re-emitted with
+    /// `removed: true`.
+    pub filter: Option<Filter>,
+}
+
+impl Default for ReorgConfig {
+    fn default() -> Self {
+        ReorgConfig {
+            window: 64,
+            poll_interval: Duration::from_secs(2),
+            filter: None,
+        }
+    }
+}
+
+/// Events produced by a `ReorgAwareStream`.
+// Events are moved through a channel one at a time; boxing the payloads would only make
+// matching on them more awkward.
+#[allow(clippy::large_enum_variant)]
+#[derive(Debug, Clone)]
+pub enum ChainEvent {
+    /// A block extending the current canonical chain.
+    Block(Block<TxHash>),
+    /// The chain switched branches. `dropped` is ordered oldest first, as is `added`.
+    Reorged {
+        dropped: Vec<Block<TxHash>>,
+        added: Vec<Block<TxHash>>,
+    },
+    /// A log matching the configured filter. `removed` is set for logs of dropped blocks.
+    Log(Log),
+}
+
+/// Stream of `ChainEvent`s. An `Err` item reports a failed lookup; the stream continues.
+pub struct ReorgAwareStream {
+    receiver: mpsc::UnboundedReceiver<GrauxResult<ChainEvent>>,
+}
+
+impl Stream for ReorgAwareStream {
+    type Item = GrauxResult<ChainEvent>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.receiver.poll_recv(cx)
+    }
+}
+
+/// Polls `provider` for the latest block every `config.poll_interval`.
+pub fn watch_blocks<M>(provider: M, config: ReorgConfig) -> ReorgAwareStream
+where
+    M: Middleware + Clone + 'static,
+    GrauxError: From<M::Error>,
+{
+    let interval = config.poll_interval;
+    let poller = provider.clone();
+    let heads = futures_util::stream::unfold(None::<H256>, move |last| {
+        let poller = poller.clone();
+        async move {
+            loop {
+                tokio::time::sleep(interval).await;
+                match poller.get_block(BlockNumber::Latest).await {
+                    Ok(Some(block)) if block.hash != last => {
+                        let hash = block.hash;
+                        return Some((Ok(block), hash));
+                    }
+                    Ok(_) => continue,
+                    Err(err) => return Some((Err(GrauxError::from(err)), last)),
+                }
+            }
+        }
+    });
+
+    from_heads(provider, Box::pin(heads), config)
+}
+
+/// Tracks reorgs on an existing stream of new heads, such as a `newHeads` subscription.
+/// `provider` is used to fetch ancestors that the stream skipped.
+pub fn from_heads<M, S>(provider: M, heads: S, config: ReorgConfig) -> ReorgAwareStream
+where
+    M: Middleware + 'static,
+    GrauxError: From<M::Error>,
+    S: Stream<Item = GrauxResult<Block<TxHash>>> + Send + Unpin + 'static,
+{
+    let (sender, receiver) = mpsc::unbounded_channel();
+    let tracker = Tracker {
+        provider,
+        capacity: config.window.max(1),
+        filter: config.filter,
+        chain: VecDeque::new(),
+        logs: HashMap::new(),
+        sender,
+    };
+    tokio::spawn(tracker.run(heads));
+
+    ReorgAwareStream { receiver }
+}
+
+/// Set when the consumer dropped the stream.
+struct Closed;
+
+struct Tracker<M> {
+    provider: M,
+    capacity: usize,
+    filter: Option<Filter>,
+    /// Recent canonical blocks, oldest first.
+    chain: VecDeque<Block<TxHash>>,
+    /// Logs delivered for each block in `chain`.
+    logs: HashMap<H256, Vec<Log>>,
+    sender: mpsc::UnboundedSender<GrauxResult<ChainEvent>>,
+}
+
+impl<M> Tracker<M>
+where
+    M: Middleware,
+    GrauxError: From<M::Error>,
+{
+    async fn run<S>(mut self, mut heads: S)
+    where
+        S: Stream<Item = GrauxResult<Block<TxHash>>> + Unpin,
+    {
+        while let Some(head) = heads.next().await {
+            let step = match head {
+                Ok(head) => self.on_head(head).await,
+                Err(err) => self.emit(Err(err)),
+            };
+            if step.is_err() {
+                return;
+            }
+        }
+    }
+
+    fn emit(&self, event: GrauxResult<ChainEvent>) -> Result<(), Closed> {
+        self.sender.send(event).map_err(|_| Closed)
+    }
+
+    fn position(&self, hash: H256) -> Option<usize> {
+        self.chain.iter().rposition(|b| b.hash == Some(hash))
+    }
+
+    async fn on_head(&mut self, head: Block<TxHash>) -> Result<(), Closed> {
+        let Some(hash) = head.hash else {
+            return Ok(());
+        };
+        if self.position(hash).is_some() {
+            return Ok(());
+        }
+        if self.chain.is_empty() {
+            return self.extend(head).await;
+        }
+
+        // Walk back from the new head until we reach a block we already know. Blocks above the
+        // tip only fill a gap in the head stream, however many there are; the walk gives up
+        // once the parent would be older than the oldest tracked block. By then every branch
+        // block at the tracked heights has differed from ours, so the fork is confirmed.
+        let oldest = self.chain.front().map_or(0, block_number);
+        let mut branch = vec![head];
+        let ancestor = loop {
+            let last = branch.last().expect("branch is never empty");
+            let parent = last.parent_hash;
+            if let Some(pos) = self.position(parent) {
+                break Some(pos);
+            }
+            if block_number(last) <= oldest {
+                break None;
+            }
+            match self.provider.get_block(parent).await {
+                Ok(Some(block)) => branch.push(block),
+                Ok(None) => {
+                    return self.emit(Err(GrauxError::Decode(format!("parent block {parent:?} not found"))))
+                }
+                Err(err) => return self.emit(Err(err.into())),
+            }
+        };
+        branch.reverse();
+
+        let dropped: Vec<Block<TxHash>> = match ancestor {
+            Some(pos) => self.chain.drain(pos + 1..).collect(),
+            None => {
+                self.emit(Err(GrauxError::ReorgTooDeep(self.capacity)))?;
+                self.chain.drain(..).collect()
+            }
+        };
+
+        if dropped.is_empty() {
+            // Only a gap in the head stream; the skipped blocks are ordinary new blocks.
+            for block in branch {
+                self.extend(block).await?;
+            }
+            return Ok(());
+        }
+
+        self.emit(Ok(ChainEvent::Reorged {
+            dropped: dropped.clone(),
+            added: branch.clone(),
+        }))?;
+        for block in dropped.iter().rev() {
+            let logs = block.hash.and_then(|h| self.logs.remove(&h)).unwrap_or_default();
+            for mut log in logs.into_iter().rev() {
+                log.removed = Some(true);
+                self.emit(Ok(ChainEvent::Log(log)))?;
+            }
+        }
+        for block in branch {
+            self.push(block.clone());
+            self.deliver_logs(&block).await?;
+        }
+        Ok(())
+    }
+
+    async fn extend(&mut self, block: Block<TxHash>) -> Result<(), Closed> {
+        self.push(block.clone());
+        self.emit(Ok(ChainEvent::Block(block.clone())))?;
+        self.deliver_logs(&block).await
+    }
+
+    fn push(&mut self, block: Block<TxHash>) {
+        self.chain.push_back(block);
+        while self.chain.len() > self.capacity {
+            if let Some(hash) = self.chain.pop_front().and_then(|b| b.hash) {
+                self.logs.remove(&hash);
+            }
+        }
+    }
+
+    async fn deliver_logs(&mut self, block: &Block<TxHash>) -> Result<(), Closed> {
+        let (Some(filter), Some(hash)) = (&self.filter, block.hash) else {
+            return Ok(());
+        };
+
+        match self.provider.get_logs(&filter.clone().at_block_hash(hash)).await {
+            Ok(logs) => {
+                for log in &logs {
+                    self.emit(Ok(ChainEvent::Log(log.clone())))?;
+                }
+                self.logs.insert(hash, logs);
+                Ok(())
+            }
+            Err(err) => self.emit(Err(err.into())),
+        }
+    }
+}
+
+fn block_number(block: &Block<TxHash>) -> u64 {
+    block.number.map_or(0, |n| n.as_u64())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers_providers::{MockProvider, Provider};
+
+    /// Block `number` on fork `fork`, whose parent is `parent` on fork `parent_fork`.
+    fn block(number: u64, fork: u64, parent_fork: u64) -> Block<TxHash> {
+        Block {
+            number: Some(number.into()),
+            hash: Some(hash(number, fork)),
+            parent_hash: hash(number.saturating_sub(1), parent_fork),
+            ..Default::default()
+        }
+    }
+
+    fn hash(number: u64, fork: u64) -> H256 {
+        H256::from_low_u64_be(number * 1_000 + fork)
+    }
+
+    /// Runs `heads` through a tracker. `ancestors` are the blocks the provider will be asked
+    /// for, in request order.
+    async fn events(heads: Vec<Block<TxHash>>, ancestors: Vec<Block<TxHash>>, window: usize) -> Vec<GrauxResult<ChainEvent>> {
+        let (provider, mock) = Provider::mocked();
+        // The mock answers requests last-pushed first.
+        for ancestor in ancestors.iter().rev() {
+            mock.push::<Block<TxHash>, _>(ancestor).unwrap();
+        }
+        let config = ReorgConfig {
+            window,
+            ..Default::default()
+        };
+        let heads = futures_util::stream::iter(heads.into_iter().map(Ok));
+        from_heads::<Provider<MockProvider>, _>(provider, heads, config).collect().await
+    }
+
+    fn number(block: &Block<TxHash>) -> u64 {
+        block.number.unwrap().as_u64()
+    }
+
+    fn blocks(events: &[GrauxResult<ChainEvent>]) -> Vec<u64> {
+        events
+            .iter()
+            .filter_map(|event| match event {
+                Ok(ChainEvent::Block(block)) => Some(number(block)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn linear_heads_are_delivered_as_blocks() {
+        let events = events(vec![block(1, 0, 0), block(2, 0, 0), block(2, 0, 0), block(3, 0, 0)], vec![], 8).await;
+        assert_eq!(blocks(&events), vec![1, 2, 3]);
+    }
+
+    #[tokio::test]
+    async fn skipped_heads_are_fetched_and_delivered_in_order() {
+        let events = events(vec![block(1, 0, 0), block(4, 0, 0)], vec![block(3, 0, 0), block(2, 0, 0)], 8).await;
+        assert_eq!(blocks(&events), vec![1, 2, 3, 4]);
+    }
+
+    #[tokio::test]
+    async fn walks_back_to_the_common_ancestor_on_a_reorg() {
+        let heads = vec![block(1, 0, 0), block(2, 0, 0), block(3, 0, 0), block(4, 1, 1)];
+        let events = events(heads, vec![block(3, 1, 0)], 8).await;
+
+        assert_eq!(blocks(&events), vec![1, 2, 3]);
+        let Some(Ok(ChainEvent::Reorged { dropped, added })) = events.last() else {
+            panic!("expected a reorg, got {events:?}");
+        };
+        assert_eq!(dropped.iter().map(|b| b.hash.unwrap()).collect::<Vec<_>>(), vec![hash(3, 0)]);
+        assert_eq!(
+            added.iter().map(|b| b.hash.unwrap()).collect::<Vec<_>>(),
+            vec![hash(3, 1), hash(4, 1)]
+        );
+    }
+
+    #[tokio::test]
+    async fn reorgs_deeper_than_the_window_are_reported() {
+        let heads = vec![block(1, 0, 0), block(2, 0, 0), block(3, 0, 0), block(4, 1, 1)];
+        let ancestors = vec![block(3, 1, 1), block(2, 1, 1)];
+        let events = events(heads, ancestors, 2).await;
+
+        assert!(events
+            .iter()
+            .any(|event| matches!(event, Err(GrauxError::ReorgTooDeep(2)))));
+    }
+
+    #[tokio::test]
+    async fn gaps_wider_than_the_window_are_not_reorgs() {
+        // A window of 2 and a gap of 4 blocks between the first and the second head.
+        let ancestors = (2..=5).rev().map(|number| block(number, 0, 0)).collect();
+        let events = events(vec![block(1, 0, 0), block(6, 0, 0)], ancestors, 2).await;
+
+        assert!(events.iter().all(Result::is_ok), "{events:?}");
+        assert_eq!(blocks(&events), vec![1, 2, 3, 4, 5, 6]);
+    }
+}
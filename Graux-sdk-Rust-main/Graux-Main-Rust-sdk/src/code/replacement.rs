@@ -0,0 +1,316 @@
+use std::time::Duration;
+
+use ethers_core::types::transaction::eip2718::TypedTransaction;
+use ethers_core::types::transaction::eip2930::AccessList;
+use ethers_core::types::{
+    Address, BlockId, BlockNumber, Bytes, Eip1559TransactionRequest, Transaction, TransactionReceipt,
+    TransactionRequest, TxHash, U256,
+};
+use ethers_providers::Middleware;
+
+use crate::error::{GrauxError, GrauxResult};
+use crate::fees::FeeEstimate;
+
+/// Nodes only accept a same-nonce replacement whose fees are at least this much higher.
+pub const MIN_REPLACEMENT_BUMP_PERCENT: u64 = 10;
+
+const TRANSFER_GAS: u64 = 21_000;
+
+/// Builds a copy of the pending `original` with its fees raised by `bump_percent` (at least
+/// `MIN_REPLACEMENT_BUMP_PERCENT`), or to `current` if the network now asks for more.
+pub fn speed_up_transaction(
+    original: &Transaction,
+    bump_percent: u64,
+    current: Option<FeeEstimate>,
+) -> GrauxResult<TypedTransaction> {
+    let access_list = original.access_list.clone().unwrap_or_default();
+    let mut tx = replacement_of(
+        original,
+        original.to,
+        original.value,
+        original.input.clone(),
+        original.gas,
+        access_list,
+    )?;
+    bump_fees(&mut tx, original, bump_percent, current)?;
+    Ok(tx)
+}
+
+/// Builds a zero-value self-transfer that takes the nonce of the pending `original`, with fees
+/// bumped as in `speed_up_transaction`. The access list is left empty so that the plain
+/// transfer gas limit covers it.
+pub fn cancel_transaction(original: &Transaction, current: Option<FeeEstimate>) -> GrauxResult<TypedTransaction> {
+    let mut tx = replacement_of(
+        original,
+        Some(original.from),
+        U256::zero(),
+        Bytes::default(),
+        U256::from(TRANSFER_GAS),
+        AccessList::default(),
+    )?;
+    bump_fees(&mut tx, original, MIN_REPLACEMENT_BUMP_PERCENT, current)?;
+    Ok(tx)
+}
+
+/// Transaction of the same type, sender, nonce and chain as `original`, without fees.
+fn replacement_of(
+    original: &Transaction,
+    to: Option<Address>,
+    value: U256,
+    data: Bytes,
+    gas: U256,
+    access_list: AccessList,
+) -> GrauxResult<TypedTransaction> {
+    if original.block_number.is_some() {
+        return Err(GrauxError::InvalidArgument(format!(
+            "transaction {:?} is already mined",
+            original.hash
+        )));
+    }
+
+    let chain_id = original.chain_id.map(|id| id.as_u64().into());
+    let request = TransactionRequest {
+        from: Some(original.from),
+        to: to.map(Into::into),
+        gas: Some(gas),
+        gas_price: None,
+        value: Some(value),
+        data: Some(data),
+        nonce: Some(original.nonce),
+        chain_id,
+    };
+
+    Ok(match original.transaction_type.map(|t| t.as_u64()) {
+        Some(2) => TypedTransaction::Eip1559(Eip1559TransactionRequest {
+            from: request.from,
+            to: request.to,
+            gas: request.gas,
+            value: request.value,
+            data: request.data,
+            nonce: request.nonce,
+            access_list,
+            max_priority_fee_per_gas: None,
+            max_fee_per_gas: None,
+            chain_id,
+        }),
+        Some(1) => request.with_access_list(access_list).into(),
+        _ => request.into(),
+    })
+}
+
+fn bump_fees(
+    tx: &mut TypedTransaction,
+    original: &Transaction,
+    bump_percent: u64,
+    current: Option<FeeEstimate>,
+) -> GrauxResult<()> {
+    let percent = bump_percent.max(MIN_REPLACEMENT_BUMP_PERCENT);
+    match tx {
+        TypedTransaction::Eip1559(inner) => {
+            let (Some(max_fee), Some(priority_fee)) =
+                (original.max_fee_per_gas, original.max_priority_fee_per_gas)
+            else {
+                return Err(GrauxError::Decode("EIP-1559 transaction without fee caps".to_owned()));
+            };
+            let mut priority_fee = bump(priority_fee, percent);
+            let mut max_fee = bump(max_fee, percent);
+            if let Some(current) = current {
+                priority_fee = priority_fee.max(current.max_priority_fee_per_gas);
+                max_fee = max_fee.max(current.max_fee_per_gas);
+            }
+            inner.max_priority_fee_per_gas = Some(priority_fee);
+            inner.max_fee_per_gas = Some(max_fee.max(priority_fee));
+        }
+        _ => {
+            let gas_price = original
+                .gas_price
+                .ok_or_else(|| GrauxError::Decode("transaction without gas price".to_owned()))?;
+            let mut gas_price = bump(gas_price, percent);
+            if let Some(current) = current {
+                gas_price = gas_price.max(current.max_fee_per_gas);
+            }
+            tx.set_gas_price(gas_price);
+        }
+    }
+    Ok(())
+}
+
+/// `value` raised by `percent`, rounded up so the result never lands just under the threshold.
+fn bump(value: U256, percent: u64) -> U256 {
+    (value * (100 + percent) + 99) / 100
+}
+
+/// Which of a set of competing same-nonce transactions was mined.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ReplacementOutcome {
+    Original(Box<TransactionReceipt>),
+    Replacement(Box<TransactionReceipt>),
+    /// The nonce was used by a transaction this handle does not know about.
+    Superseded,
+}
+
+/// Follows an original transaction and its replacements until one of them is mined.
+#[derive(Debug, Clone)]
+pub struct ReplacementHandle<M> {
+    inner: M,
+    from: Address,
+    nonce: U256,
+    /// The original transaction first, then its replacements in the order they were sent.
+    hashes: Vec<TxHash>,
+    poll_interval: Duration,
+}
+
+impl<M> ReplacementHandle<M>
+where
+    M: Middleware,
+    GrauxError: From<M::Error>,
+{
+    pub fn new(inner: M, from: Address, nonce: U256, original: TxHash, replacement: TxHash) -> Self {
+        ReplacementHandle {
+            inner,
+            from,
+            nonce,
+            hashes: vec![original, replacement],
+            poll_interval: Duration::from_secs(2),
+        }
+    }
+
+    pub fn with_poll_interval(mut self, poll_interval: Duration) -> Self {
+        self.poll_interval = poll_interval;
+        self
+    }
+
+    pub fn original(&self) -> TxHash {
+        self.hashes[0]
+    }
+
+    /// The most recently sent replacement.
+    pub fn replacement(&self) -> TxHash {
+        *self.hashes.last().expect("a handle always holds a replacement")
+    }
+
+    pub fn hashes(&self) -> &[TxHash] {
+        &self.hashes
+    }
+
+    pub fn nonce(&self) -> U256 {
+        self.nonce
+    }
+
+    /// Waits until the nonce is used and reports which transaction used it.
+    pub async fn wait(&self) -> GrauxResult<ReplacementOutcome> {
+        loop {
+            if let Some(outcome) = self.mined().await? {
+                return Ok(outcome);
+            }
+
+            let latest = self
+                .inner
+                .get_transaction_count(self.from, Some(BlockId::Number(BlockNumber::Latest)))
+                .await?;
+            if latest > self.nonce {
+                // The winning receipt may have become available since the check above.
+                return Ok(self.mined().await?.unwrap_or(ReplacementOutcome::Superseded));
+            }
+
+            tokio::time::sleep(self.poll_interval).await;
+        }
+    }
+
+    async fn mined(&self) -> GrauxResult<Option<ReplacementOutcome>> {
+        for (index, hash) in self.hashes.iter().enumerate() {
+            if let Some(receipt) = self.inner.get_transaction_receipt(*hash).await? {
+                let receipt = Box::new(receipt);
+                return Ok(Some(if index == 0 {
+                    ReplacementOutcome::Original(receipt)
+                } else {
+                    ReplacementOutcome::Replacement(receipt)
+                }));
+            }
+        }
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers_core::types::transaction::eip2930::AccessListItem;
+    use ethers_core::types::H256;
+
+    const GWEI: u64 = 1_000_000_000;
+
+    fn pending(transaction_type: Option<u64>) -> Transaction {
+        Transaction {
+            hash: TxHash::repeat_byte(1),
+            nonce: U256::from(7),
+            from: Address::repeat_byte(0xaa),
+            to: Some(Address::repeat_byte(0xbb)),
+            value: U256::from(5),
+            gas: U256::from(90_000),
+            input: Bytes::from(vec![0xa9, 0x05, 0x9c, 0xbb]),
+            chain_id: Some(U256::one()),
+            transaction_type: transaction_type.map(Into::into),
+            access_list: Some(AccessList(vec![AccessListItem {
+                address: Address::repeat_byte(0xcc),
+                storage_keys: vec![H256::zero()],
+            }])),
+            gas_price: Some(U256::from(20 * GWEI)),
+            max_fee_per_gas: Some(U256::from(40 * GWEI)),
+            max_priority_fee_per_gas: Some(U256::from(2 * GWEI)),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn bumps_round_up() {
+        assert_eq!(bump(U256::from(100), 10), U256::from(110));
+        assert_eq!(bump(U256::from(101), 10), U256::from(112));
+    }
+
+    #[test]
+    fn speed_up_keeps_the_call_and_raises_fees() {
+        let tx = speed_up_transaction(&pending(Some(2)), 5, None).unwrap();
+        let TypedTransaction::Eip1559(inner) = &tx else {
+            panic!("expected an EIP-1559 transaction, got {tx:?}");
+        };
+        assert_eq!(inner.nonce, Some(U256::from(7)));
+        assert_eq!(inner.data.as_ref().unwrap().as_ref(), &[0xa9, 0x05, 0x9c, 0xbb]);
+        assert_eq!(inner.access_list.0.len(), 1);
+        assert_eq!(inner.max_priority_fee_per_gas, Some(U256::from(2_200_000_000u64)));
+        assert_eq!(inner.max_fee_per_gas, Some(U256::from(44 * GWEI)));
+    }
+
+    #[test]
+    fn current_fees_win_when_higher_than_the_bump() {
+        let current = FeeEstimate {
+            max_fee_per_gas: U256::from(30 * GWEI),
+            max_priority_fee_per_gas: U256::from(3 * GWEI),
+        };
+        let tx = speed_up_transaction(&pending(None), 10, Some(current)).unwrap();
+        assert_eq!(tx.gas_price(), Some(U256::from(30 * GWEI)));
+    }
+
+    #[test]
+    fn cancel_is_a_plain_self_transfer() {
+        for transaction_type in [None, Some(1), Some(2)] {
+            let original = pending(transaction_type);
+            let tx = cancel_transaction(&original, None).unwrap();
+            assert_eq!(tx.to_addr(), Some(&original.from));
+            assert_eq!(tx.value(), Some(&U256::zero()));
+            assert_eq!(tx.gas(), Some(&U256::from(TRANSFER_GAS)));
+            assert_eq!(tx.nonce(), Some(&original.nonce));
+            assert!(tx.access_list().map_or(true, |list| list.0.is_empty()));
+        }
+    }
+
+    #[test]
+    fn mined_transactions_cannot_be_replaced() {
+        let mut original = pending(Some(2));
+        original.block_number = Some(100u64.into());
+        assert!(matches!(
+            cancel_transaction(&original, None),
+            Err(GrauxError::InvalidArgument(_))
+        ));
+    }
+}
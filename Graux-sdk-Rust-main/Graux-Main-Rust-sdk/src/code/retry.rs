@@ -0,0 +1,187 @@
+use std::time::Duration;
+
+use rand::Rng;
+
+use crate::error::GrauxError;
+
+/// Methods that must not be replayed blindly, since a lost response does not mean the
+/// request was not applied.
+const NON_IDEMPOTENT_METHODS: &[&str] = &[
+    "eth_sendRawTransaction",
+    "eth_sendTransaction",
+    "eth_sendPrivateTransaction",
+    "eth_cancelPrivateTransaction",
+];
+
+const DEFAULT_INITIAL_BACKOFF: Duration = Duration::from_millis(250);
+const DEFAULT_MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// Controls when and how long `GrauxProvider` waits before replaying a failed request.
+/// A `Retry-After` sent by the server is honoured but capped at `max_backoff`.
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    pub max_retries: u32,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    /// Replay non-idempotent methods such as `eth_sendRawTransaction` as well.
+    pub retry_non_idempotent: bool,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_retries: 5,
+            initial_backoff: DEFAULT_INITIAL_BACKOFF,
+            max_backoff: DEFAULT_MAX_BACKOFF,
+            retry_non_idempotent: false,
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub fn new(max_retries: u32) -> Self {
+        RetryPolicy {
+            max_retries,
+            ..RetryPolicy::default()
+        }
+    }
+
+    /// Returns how long to wait before retry number `attempt` (starting at 0), or `None`
+    /// if the request should fail with `error`.
+    pub fn retry_delay(&self, method: &str, attempt: u32, error: &GrauxError) -> Option<Duration> {
+        if attempt >= self.max_retries {
+            return None;
+        }
+        if !self.retry_non_idempotent && NON_IDEMPOTENT_METHODS.contains(&method) {
+            return None;
+        }
+
+        match error {
+            GrauxError::RateLimited {
+                retry_after: Some(retry_after),
+                ..
+            } => Some((*retry_after).min(self.max_backoff)),
+            _ if is_retryable(error) => Some(self.backoff(attempt)),
+            _ => None,
+        }
+    }
+
+    /// Exponential backoff with "equal jitter": half of the delay is fixed, the other
+    /// half is random so that concurrent clients do not retry in lockstep.
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let exponential = self
+            .initial_backoff
+            .saturating_mul(2u32.saturating_pow(attempt))
+            .min(self.max_backoff);
+        let half = exponential / 2;
+        let jitter = rand::thread_rng().gen_range(0..=half.as_millis() as u64);
+
+        half + Duration::from_millis(jitter)
+    }
+}
+
+/// Whether `error` is a transient failure worth retrying.
+pub fn is_retryable(error: &GrauxError) -> bool {
+    match error {
+        GrauxError::RateLimited { .. } => true,
+        GrauxError::Http { status, .. } => *status >= 500,
+        GrauxError::Transport(_) | GrauxError::Timeout => true,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fast_policy(max_retries: u32) -> RetryPolicy {
+        RetryPolicy {
+            max_retries,
+            initial_backoff: Duration::from_millis(1),
+            max_backoff: Duration::from_millis(5),
+            retry_non_idempotent: false,
+        }
+    }
+
+    /// Replays `statuses` the way `GrauxProvider::on_response` does: each failed HTTP
+    /// response is classified with `from_http_status` and retried while `retry_delay` allows.
+    /// Returns the number of requests sent and the final error, if the request failed.
+    fn replay(policy: &RetryPolicy, method: &str, statuses: &[(u16, Option<&str>)]) -> (usize, Option<GrauxError>) {
+        let mut attempt = 0;
+        for (sent, &(status, retry_after)) in statuses.iter().enumerate() {
+            if (200..300).contains(&status) {
+                return (sent + 1, None);
+            }
+            let err = GrauxError::from_http_status(status, retry_after, String::new());
+            match policy.retry_delay(method, attempt, &err) {
+                Some(_) => attempt += 1,
+                None => return (sent + 1, Some(err)),
+            }
+        }
+        panic!("ran out of responses after {} requests", statuses.len());
+    }
+
+    #[test]
+    fn retries_rate_limits_and_server_errors_until_success() {
+        let statuses = [(429, Some("0")), (503, None), (200, None)];
+        let (sent, err) = replay(&fast_policy(5), "eth_blockNumber", &statuses);
+        assert_eq!(sent, 3);
+        assert!(err.is_none());
+    }
+
+    #[test]
+    fn gives_up_after_max_retries() {
+        let (sent, err) = replay(&fast_policy(2), "eth_blockNumber", &[(502, None); 4]);
+        assert_eq!(sent, 3);
+        assert!(matches!(err, Some(GrauxError::Http { status: 502, .. })));
+    }
+
+    #[test]
+    fn does_not_retry_client_errors() {
+        for status in [400, 401, 403, 404] {
+            let (sent, err) = replay(&fast_policy(5), "eth_blockNumber", &[(status, None), (200, None)]);
+            assert_eq!(sent, 1, "status {status}");
+            assert!(err.is_some());
+        }
+    }
+
+    #[test]
+    fn replays_non_idempotent_methods_only_when_allowed() {
+        let method = "eth_sendRawTransaction";
+        let (sent, err) = replay(&fast_policy(2), method, &[(503, None); 3]);
+        assert_eq!(sent, 1);
+        assert!(err.is_some());
+
+        let policy = RetryPolicy {
+            retry_non_idempotent: true,
+            ..fast_policy(2)
+        };
+        let (sent, err) = replay(&policy, method, &[(503, None); 3]);
+        assert_eq!(sent, 3);
+        assert!(err.is_some());
+    }
+
+    #[test]
+    fn caps_retry_after_at_max_backoff() {
+        let error = GrauxError::RateLimited {
+            message: String::new(),
+            retry_after: Some(Duration::from_secs(3_600)),
+        };
+        let delay = fast_policy(3).retry_delay("eth_call", 0, &error);
+        assert_eq!(delay, Some(Duration::from_millis(5)));
+    }
+
+    #[test]
+    fn backoff_grows_and_stays_within_bounds() {
+        let policy = RetryPolicy {
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(1),
+            ..RetryPolicy::default()
+        };
+        for attempt in 0..10 {
+            let full = (Duration::from_millis(100) * 2u32.pow(attempt)).min(Duration::from_secs(1));
+            let delay = policy.backoff(attempt);
+            assert!(delay >= full / 2 && delay <= full, "attempt {attempt}: {delay:?}");
+        }
+    }
+}
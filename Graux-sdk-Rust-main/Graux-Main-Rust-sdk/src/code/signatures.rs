@@ -0,0 +1,261 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::RwLock;
+
+use ethers_core::abi::{HumanReadableParser, ParamType};
+use ethers_core::types::{Log, H256};
+use ethers_core::utils::keccak256;
+
+use crate::error::{GrauxError, GrauxResult};
+use crate::trace::{CallFrame, TraceResult};
+
+/// Signatures shipped with the SDK, in the text format read by `SignatureDb::merge_str`.
+const BUNDLED: &str = include_str!("data/signatures.txt");
+
+/// A log with the event signature `SignatureDb` guessed for its first topic.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LabeledLog {
+    pub log: Log,
+    pub signature: Option<String>,
+}
+
+#[derive(Debug, Default)]
+struct Signatures {
+    functions: HashMap<[u8; 4], Vec<String>>,
+    events: HashMap<H256, Vec<String>>,
+}
+
+/// Offline lookup of function selectors and event topics, used to label trace frames and
+/// logs when no ABI is registered for them.
+///
+/// Signatures are stored as text, one per line, with event signatures prefixed by `event `
+/// and `#` starting a comment. Signatures are added in Solidity's human-readable form and
+/// stored canonically, so `function transfer(address to, uint amount)` becomes
+/// `transfer(address,uint256)`. Selectors and topics are computed on load, so a signature file
+/// is just the list of signatures. A selector may map to several signatures (collisions are
+/// common for 4-byte selectors); they are kept in the order they were added.
+#[derive(Debug, Default)]
+pub struct SignatureDb {
+    signatures: RwLock<Signatures>,
+}
+
+impl SignatureDb {
+    pub fn empty() -> Self {
+        Self::default()
+    }
+
+    /// The signatures bundled with the SDK: common token, NFT, DEX, proxy and ENS methods.
+    pub fn bundled() -> Self {
+        let db = Self::empty();
+        db.merge_str(BUNDLED).expect("bundled signatures are valid");
+        db
+    }
+
+    pub fn add_function(&self, signature: &str) -> GrauxResult<()> {
+        let function = HumanReadableParser::parse_function(signature.trim()).map_err(|e| invalid(signature, e))?;
+        let signature = canonical(signature, &function.name, function.inputs.iter().map(|input| &input.kind))?;
+        let selector = selector(&signature);
+        let mut signatures = self.signatures.write().expect("signature db lock poisoned");
+        push_unique(signatures.functions.entry(selector).or_default(), signature);
+        Ok(())
+    }
+
+    pub fn add_event(&self, signature: &str) -> GrauxResult<()> {
+        let event = HumanReadableParser::parse_event(signature.trim()).map_err(|e| invalid(signature, e))?;
+        let signature = canonical(signature, &event.name, event.inputs.iter().map(|input| &input.kind))?;
+        let topic = H256(keccak256(signature.as_bytes()));
+        let mut signatures = self.signatures.write().expect("signature db lock poisoned");
+        push_unique(signatures.events.entry(topic).or_default(), signature);
+        Ok(())
+    }
+
+    /// Adds every signature of a signature file's contents.
+    pub fn merge_str(&self, contents: &str) -> GrauxResult<()> {
+        for line in contents.lines() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+            match line.strip_prefix("event ") {
+                Some(event) => self.add_event(event)?,
+                None => self.add_function(line)?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Merges a signature file from disk into the database.
+    pub fn merge_file(&self, path: impl AsRef<Path>) -> GrauxResult<()> {
+        let path = path.as_ref();
+        let contents = std::fs::read_to_string(path)
+            .map_err(|e| GrauxError::Config(format!("cannot read {}: {e}", path.display())))?;
+        self.merge_str(&contents)
+    }
+
+    /// Writes the database in the format read by `merge_file`.
+    pub fn to_file_contents(&self) -> String {
+        let signatures = self.signatures.read().expect("signature db lock poisoned");
+        let mut functions: Vec<&String> = signatures.functions.values().flatten().collect();
+        let mut events: Vec<&String> = signatures.events.values().flatten().collect();
+        functions.sort();
+        events.sort();
+
+        let mut contents = String::new();
+        for function in functions {
+            contents.push_str(function);
+            contents.push('\n');
+        }
+        for event in events {
+            contents.push_str("event ");
+            contents.push_str(event);
+            contents.push('\n');
+        }
+        contents
+    }
+
+    /// Known signatures for a function selector, best guess first.
+    pub fn functions(&self, selector: [u8; 4]) -> Vec<String> {
+        let signatures = self.signatures.read().expect("signature db lock poisoned");
+        signatures.functions.get(&selector).cloned().unwrap_or_default()
+    }
+
+    /// Known signatures for an event topic, best guess first.
+    pub fn events(&self, topic: H256) -> Vec<String> {
+        let signatures = self.signatures.read().expect("signature db lock poisoned");
+        signatures.events.get(&topic).cloned().unwrap_or_default()
+    }
+
+    pub fn label_log(&self, log: Log) -> LabeledLog {
+        let signature = log.topics.first().and_then(|topic| self.events(*topic).into_iter().next());
+        LabeledLog { log, signature }
+    }
+
+    /// Sets `label` on every frame and log of a call tree whose selector or topic is known.
+    pub fn label_call_frame(&self, frame: &mut CallFrame) {
+        frame.label = frame
+            .selector()
+            .and_then(|selector| self.functions(selector).into_iter().next());
+        for log in &mut frame.logs {
+            log.label = log.topics.first().and_then(|topic| self.events(*topic).into_iter().next());
+        }
+        for call in &mut frame.calls {
+            self.label_call_frame(call);
+        }
+    }
+
+    /// Labels the call tree of a `callTracer` result; other results are left as they are.
+    pub fn label_trace(&self, result: &mut TraceResult) {
+        if let TraceResult::Call(frame) = result {
+            self.label_call_frame(frame);
+        }
+    }
+}
+
+/// `name(type,...)` with canonical type names, as hashed for selectors and topics.
+fn canonical<'a>(
+    signature: &str,
+    name: &str,
+    kinds: impl Iterator<Item = &'a ParamType>,
+) -> GrauxResult<String> {
+    if name.is_empty() {
+        return Err(invalid(signature, "missing name"));
+    }
+    let kinds: Vec<String> = kinds.map(ToString::to_string).collect();
+    Ok(format!("{name}({})", kinds.join(",")))
+}
+
+fn invalid(signature: &str, reason: impl std::fmt::Display) -> GrauxError {
+    GrauxError::InvalidArgument(format!("invalid signature `{signature}`: {reason}"))
+}
+
+fn selector(signature: &str) -> [u8; 4] {
+    let hash = keccak256(signature.as_bytes());
+    [hash[0], hash[1], hash[2], hash[3]]
+}
+
+fn push_unique(signatures: &mut Vec<String>, signature: String) {
+    if !signatures.contains(&signature) {
+        signatures.push(signature);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn canonicalizes_human_readable_signatures() {
+        let db = SignatureDb::empty();
+        db.add_function("function transfer(address to, uint amount) external returns (bool)")
+            .unwrap();
+        db.add_function("swap((address,uint256)[] calldata orders, bytes data)").unwrap();
+        db.add_event("event Transfer(address indexed from, address indexed to, uint256 value)")
+            .unwrap();
+
+        assert_eq!(db.functions([0xa9, 0x05, 0x9c, 0xbb]), vec!["transfer(address,uint256)"]);
+        assert_eq!(
+            db.to_file_contents(),
+            "swap((address,uint256)[],bytes)\ntransfer(address,uint256)\nevent Transfer(address,address,uint256)\n"
+        );
+        let topic: H256 = "0xddf252ad1be2c89b69c2b068fc378daa952ba7f163c4a11628f55a4df523b3ef"
+            .parse()
+            .unwrap();
+        assert_eq!(db.events(topic), vec!["Transfer(address,address,uint256)"]);
+    }
+
+    #[test]
+    fn rejects_malformed_signatures() {
+        let db = SignatureDb::empty();
+        for signature in ["transfer", "transfer(address", "(address)", "transfer(adress)", ""] {
+            assert!(
+                matches!(db.add_function(signature), Err(GrauxError::InvalidArgument(_))),
+                "{signature}"
+            );
+        }
+        assert!(db.add_event("event (uint256)").is_err());
+        assert!(db.merge_str("ok()\nbroken(\n").is_err());
+    }
+
+    #[test]
+    fn keeps_colliding_signatures_in_insertion_order() {
+        let db = SignatureDb::empty();
+        db.merge_str("# comment\ntransfer(address,uint256)\n\ntransfer( address , uint256 ) # again\n")
+            .unwrap();
+        // A known collision with `transfer(address,uint256)`.
+        db.add_function("many_msg_babbage(bytes1)").unwrap();
+        assert_eq!(
+            db.functions([0xa9, 0x05, 0x9c, 0xbb]),
+            vec!["transfer(address,uint256)", "many_msg_babbage(bytes1)"]
+        );
+    }
+
+    #[test]
+    fn bundled_signatures_round_trip_through_a_file() {
+        let bundled = SignatureDb::bundled();
+        let path = std::env::temp_dir().join(format!("graux-signatures-{}.txt", std::process::id()));
+        std::fs::write(&path, bundled.to_file_contents()).unwrap();
+
+        let merged = SignatureDb::empty();
+        merged.merge_file(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(merged.to_file_contents(), bundled.to_file_contents());
+        assert!(matches!(merged.merge_file(&path), Err(GrauxError::Config(_))));
+    }
+
+    #[test]
+    fn labels_frames_and_logs() {
+        let db = SignatureDb::bundled();
+        let transfer = "0xddf252ad1be2c89b69c2b068fc378daa952ba7f163c4a11628f55a4df523b3ef";
+        let mut frame: CallFrame = serde_json::from_value(serde_json::json!({
+            "type": "CALL", "from": "0x0000000000000000000000000000000000000001", "input": "0xa9059cbb",
+            "logs": [{"address": "0x0000000000000000000000000000000000000002", "topics": [transfer]}],
+            "calls": [{"type": "CALL", "from": "0x0000000000000000000000000000000000000002", "input": "0xffffffff"}]
+        }))
+        .unwrap();
+        db.label_call_frame(&mut frame);
+
+        assert_eq!(frame.label.as_deref(), Some("transfer(address,uint256)"));
+        assert_eq!(frame.logs[0].label.as_deref(), Some("Transfer(address,address,uint256)"));
+        assert_eq!(frame.calls[0].label, None);
+    }
+}
@@ -0,0 +1,251 @@
+use std::path::Path;
+
+use ethers_core::types::transaction::eip2718::TypedTransaction;
+use ethers_core::types::{Address, Bytes, Signature, TxHash};
+use ethers_providers::Middleware;
+use ethers_signers::coins_bip39::English;
+use ethers_signers::{LocalWallet, MnemonicBuilder, Signer};
+use tokio::sync::OnceCell;
+
+use crate::error::{GrauxError, GrauxResult};
+use crate::fees::{FeeOracle, FeeOracleConfig, FeeSpeed};
+use crate::nonce::NonceManager;
+
+/// BIP-44 path of the first Ethereum account; `from_mnemonic` replaces the last index.
+const ETH_DERIVATION_PATH: &str = "m/44'/60'/0'/0/";
+
+/// Client that signs transactions with a local key and broadcasts them through
+/// `eth_sendRawTransaction`, so the node never needs to hold the account.
+///
+/// Missing chain id, nonce, gas limit and fees are filled in before signing; nonces come from
+/// a `NonceManager` so concurrent sends do not collide.
+#[derive(Debug)]
+pub struct GrauxSigner<M> {
+    inner: M,
+    wallet: LocalWallet,
+    chain_id: OnceCell<u64>,
+    nonce_manager: NonceManager<M>,
+    fee_oracle: FeeOracle<M>,
+    fee_speed: FeeSpeed,
+}
+
+impl<M> GrauxSigner<M>
+where
+    M: Middleware + Clone,
+    GrauxError: From<M::Error>,
+{
+    pub fn new(inner: M, wallet: LocalWallet) -> Self {
+        GrauxSigner {
+            nonce_manager: NonceManager::new(inner.clone()),
+            fee_oracle: FeeOracle::new(inner.clone(), FeeOracleConfig::default()),
+            inner,
+            wallet,
+            chain_id: OnceCell::new(),
+            fee_speed: FeeSpeed::default(),
+        }
+    }
+
+    /// Signs with a hex-encoded secp256k1 private key, with or without `0x` prefix.
+    pub fn from_private_key(inner: M, private_key: &str) -> GrauxResult<Self> {
+        let private_key = private_key.strip_prefix("0x").unwrap_or(private_key);
+        let wallet = private_key
+            .parse::<LocalWallet>()
+            .map_err(|e| GrauxError::Signer(e.to_string()))?;
+        Ok(Self::new(inner, wallet))
+    }
+
+    /// Signs with account `index` derived from a BIP-39 phrase along `m/44'/60'/0'/0/{index}`.
+    pub fn from_mnemonic(inner: M, phrase: &str, index: u32) -> GrauxResult<Self> {
+        let wallet = MnemonicBuilder::<English>::default()
+            .phrase(phrase)
+            .derivation_path(&format!("{ETH_DERIVATION_PATH}{index}"))?
+            .build()?;
+        Ok(Self::new(inner, wallet))
+    }
+
+    /// Signs with the key stored in an encrypted JSON (Web3 Secret Storage) keystore file.
+    pub fn from_keystore(inner: M, path: impl AsRef<Path>, password: impl AsRef<[u8]>) -> GrauxResult<Self> {
+        let wallet = LocalWallet::decrypt_keystore(path, password)?;
+        Ok(Self::new(inner, wallet))
+    }
+
+    /// Speed used to fill in missing fees. Defaults to `FeeSpeed::Standard`.
+    pub fn with_fee_speed(mut self, fee_speed: FeeSpeed) -> Self {
+        self.fee_speed = fee_speed;
+        self
+    }
+
+    pub fn address(&self) -> Address {
+        self.wallet.address()
+    }
+
+    pub fn wallet(&self) -> &LocalWallet {
+        &self.wallet
+    }
+
+    pub fn inner(&self) -> &M {
+        &self.inner
+    }
+
+    pub fn nonce_manager(&self) -> &NonceManager<M> {
+        &self.nonce_manager
+    }
+
+    /// Chain id of the connected network, fetched once.
+    pub async fn chain_id(&self) -> GrauxResult<u64> {
+        self.chain_id
+            .get_or_try_init(|| async { Ok(self.inner.get_chainid().await?.as_u64()) })
+            .await
+            .copied()
+    }
+
+    /// Fills in `from`, chain id, fees and gas limit. The nonce is left alone: it is only
+    /// reserved when the transaction is sent.
+    pub async fn fill_transaction(&self, tx: &mut TypedTransaction) -> GrauxResult<()> {
+        match tx.from() {
+            Some(from) if *from != self.address() => {
+                return Err(GrauxError::InvalidArgument(format!(
+                    "transaction is from {:?} but the signer is {:?}",
+                    from,
+                    self.address()
+                )));
+            }
+            Some(_) => {}
+            None => {
+                tx.set_from(self.address());
+            }
+        }
+        if tx.chain_id().is_none() {
+            tx.set_chain_id(self.chain_id().await?);
+        }
+        self.fee_oracle.fill(tx, self.fee_speed).await?;
+        if tx.gas().is_none() {
+            let gas = self.inner.estimate_gas(tx, None).await?;
+            tx.set_gas(gas);
+        }
+        Ok(())
+    }
+
+    /// RLP-encodes and signs a fully filled transaction.
+    pub async fn sign_transaction(&self, tx: &TypedTransaction) -> GrauxResult<Bytes> {
+        let signature = self.wallet.sign_transaction(tx).await?;
+        Ok(tx.rlp_signed(&signature))
+    }
+
+    /// Signs `message` with the EIP-191 personal message prefix.
+    pub async fn sign_message(&self, message: impl AsRef<[u8]> + Send + Sync) -> GrauxResult<Signature> {
+        Ok(self.wallet.sign_message(message).await?)
+    }
+
+    /// Fills, signs and broadcasts `tx`. A nonce set by the caller is used as is; otherwise
+    /// one is reserved from the nonce manager and released if the broadcast fails.
+    pub async fn send_transaction(&self, tx: impl Into<TypedTransaction>) -> GrauxResult<TxHash> {
+        let mut tx = tx.into();
+        self.fill_transaction(&mut tx).await?;
+
+        if tx.nonce().is_some() {
+            return self.send_signed(&tx).await;
+        }
+        self.nonce_manager
+            .send_with(self.address(), |nonce| {
+                let mut tx = tx.clone();
+                tx.set_nonce(nonce);
+                async move { self.send_signed(&tx).await }
+            })
+            .await
+    }
+
+    async fn send_signed(&self, tx: &TypedTransaction) -> GrauxResult<TxHash> {
+        let raw = self.sign_transaction(tx).await?;
+        Ok(self.inner.send_raw_transaction(raw).await?.tx_hash())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers_core::types::TransactionRequest;
+    use ethers_core::utils::parse_ether;
+    use ethers_providers::{MockProvider, Provider};
+
+    /// Account 0 of the `test … junk` development mnemonic used by Hardhat and Anvil.
+    const DEV_KEY: &str = "0xac0974bec39a17e36ba4a6b4d238ff944bacb478cbed5efcae784d7bf4f2ff80";
+    const DEV_MNEMONIC: &str = "test test test test test test test test test test test junk";
+
+    fn provider() -> Provider<MockProvider> {
+        Provider::mocked().0
+    }
+
+    fn address(hex: &str) -> Address {
+        hex.parse().unwrap()
+    }
+
+    #[test]
+    fn derives_the_address_of_a_private_key() {
+        let expected = address("0xf39fd6e51aad88f6f4ce6ab8827279cfffb92266");
+        let signer = GrauxSigner::from_private_key(provider(), DEV_KEY).unwrap();
+        assert_eq!(signer.address(), expected);
+
+        let unprefixed = GrauxSigner::from_private_key(provider(), &DEV_KEY[2..]).unwrap();
+        assert_eq!(unprefixed.address(), expected);
+        assert!(GrauxSigner::from_private_key(provider(), "0x1234").is_err());
+    }
+
+    #[test]
+    fn derives_mnemonic_accounts_along_the_bip44_path() {
+        let accounts = [
+            "0xf39fd6e51aad88f6f4ce6ab8827279cfffb92266",
+            "0x70997970c51812dc3a010c7d01b50e0d17dc79c8",
+            "0x3c44cdddb6a900fa2b585dd299e03d12fa4293bc",
+        ];
+        for (index, expected) in accounts.iter().enumerate() {
+            let signer = GrauxSigner::from_mnemonic(provider(), DEV_MNEMONIC, index as u32).unwrap();
+            assert_eq!(signer.address(), address(expected));
+        }
+    }
+
+    #[test]
+    fn decrypts_keystores() {
+        let dir = std::env::temp_dir().join(format!("graux-signer-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let key = ethers_core::utils::hex::decode(DEV_KEY).unwrap();
+        LocalWallet::encrypt_keystore(&dir, &mut ethers_core::rand::thread_rng(), key, "hunter2", Some("dev.json"))
+            .unwrap();
+
+        let signer = GrauxSigner::from_keystore(provider(), dir.join("dev.json"), "hunter2");
+        let wrong = GrauxSigner::from_keystore(provider(), dir.join("dev.json"), "hunter3");
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(signer.unwrap().address(), address("0xf39fd6e51aad88f6f4ce6ab8827279cfffb92266"));
+        assert!(matches!(wrong, Err(GrauxError::Signer(_))));
+    }
+
+    #[tokio::test]
+    async fn signs_the_eip155_example_transaction() {
+        // The example from EIP-155 itself.
+        let key = "0x4646464646464646464646464646464646464646464646464646464646464646";
+        let signer = GrauxSigner::from_private_key(provider(), key).unwrap();
+        let tx: TypedTransaction = TransactionRequest::new()
+            .nonce(9)
+            .gas_price(20_000_000_000u64)
+            .gas(21_000)
+            .to(address("0x3535353535353535353535353535353535353535"))
+            .value(parse_ether(1).unwrap())
+            .chain_id(1)
+            .into();
+
+        let raw = signer.sign_transaction(&tx).await.unwrap();
+        let expected = "0xf86c098504a817c800825208943535353535353535353535353535353535353535880de0b6b3a76400008025a028ef61340bd939bc2195fe537567866003e1a15d3c71ff63e1590620aa636276a067cbe9d8997f761aecb703304b3800ccf555c9f3dc64214b297fb1966a3b6d83";
+        assert_eq!(raw.to_string(), expected);
+    }
+
+    #[tokio::test]
+    async fn refuses_transactions_from_another_account() {
+        let signer = GrauxSigner::from_private_key(provider(), DEV_KEY).unwrap();
+        let mut tx: TypedTransaction = TransactionRequest::new().from(Address::repeat_byte(1)).into();
+        assert!(matches!(
+            signer.fill_transaction(&mut tx).await,
+            Err(GrauxError::InvalidArgument(_))
+        ));
+    }
+}
@@ -0,0 +1,230 @@
+use ethers_core::types::{Address, Bytes, H256, U256};
+use serde::{Deserialize, Serialize};
+
+use crate::abi_registry::DecodedEvent;
+use crate::trace::CallFrame;
+
+/// Response of `graux_simulateAssetChanges`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SimulateAssetChangesResponse {
+    pub changes: Vec<AssetChange>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gas_used: Option<U256>,
+    /// Set when the simulated transaction failed; `changes` is then empty.
+    #[serde(default)]
+    pub error: Option<SimulationError>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SimulationError {
+    pub message: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum AssetType {
+    Native,
+    Erc20,
+    Erc721,
+    Erc1155,
+    /// NFTs that predate ERC-721, such as CryptoPunks.
+    SpecialNft,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum ChangeType {
+    Approve,
+    Transfer,
+}
+
+/// A single balance or approval change caused by the simulated transaction.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AssetChange {
+    pub asset_type: AssetType,
+    pub change_type: ChangeType,
+    pub from: Address,
+    pub to: Address,
+    /// Amount in the token's base unit. Always `1` for ERC-721 transfers.
+    #[serde(with = "decimal_u256")]
+    pub raw_amount: U256,
+    /// `raw_amount` scaled by `decimals`, as a decimal string (e.g. `"1.5"`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub amount: Option<String>,
+    /// Token contract; `None` for native transfers.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub contract_address: Option<Address>,
+    /// Token id for ERC-721 and ERC-1155 changes.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token_id: Option<U256>,
+    #[serde(flatten)]
+    pub metadata: TokenMetadata,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TokenMetadata {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub symbol: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub decimals: Option<u8>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub logo: Option<String>,
+}
+
+/// Response of `graux_simulateExecution`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SimulateExecutionResponse {
+    /// Top-level calls; nested calls hang off `CallFrame::calls`.
+    pub calls: Vec<CallFrame>,
+    pub logs: Vec<SimulatedLog>,
+}
+
+impl SimulateExecutionResponse {
+    /// The deepest call that reverted, which is where the revert reason originates.
+    pub fn first_revert(&self) -> Option<&CallFrame> {
+        self.calls
+            .iter()
+            .find_map(|call| call.first_revert())
+            .map(|found| found.frame)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum CallType {
+    Call,
+    Delegatecall,
+    Staticcall,
+    Callcode,
+    Create,
+    Create2,
+    Selfdestruct,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DecodedCall {
+    pub method_name: String,
+    #[serde(default)]
+    pub inputs: Vec<DecodedParam>,
+    #[serde(default)]
+    pub outputs: Vec<DecodedParam>,
+    /// Where the ABI used for decoding came from (e.g. `"ETHERSCAN"`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub authority: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SimulatedLog {
+    pub address: Address,
+    pub topics: Vec<H256>,
+    pub data: Bytes,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub decoded: Option<DecodedLog>,
+    #[serde(skip)]
+    pub decoded_event: Option<DecodedEvent>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DecodedLog {
+    pub event_name: String,
+    #[serde(default)]
+    pub inputs: Vec<DecodedParam>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub authority: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DecodedParam {
+    pub name: String,
+    /// Solidity type, e.g. `uint256` or `address[]`.
+    #[serde(rename = "type")]
+    pub param_type: String,
+    /// Value rendered as a string, as returned by the API.
+    pub value: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub indexed: Option<bool>,
+}
+
+/// (De)serializes a `U256` as a base-10 string, the format used for `rawAmount`.
+mod decimal_u256 {
+    use ethers_core::types::U256;
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(value: &U256, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&value.to_string())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<U256, D::Error> {
+        let raw = String::deserialize(deserializer)?;
+        match raw.strip_prefix("0x") {
+            Some(hex) => U256::from_str_radix(hex, 16).map_err(D::Error::custom),
+            None => U256::from_dec_str(&raw).map_err(D::Error::custom),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::Value;
+
+    const ASSET_CHANGES: &str = include_str!("../../tests/fixtures/simulate_asset_changes.json");
+    const EXECUTION: &str = include_str!("../../tests/fixtures/simulate_execution.json");
+
+    fn round_trip<T: Serialize + serde::de::DeserializeOwned>(fixture: &str) -> T {
+        let parsed: T = serde_json::from_str(fixture).unwrap();
+        let expected: Value = serde_json::from_str(fixture).unwrap();
+        assert_eq!(serde_json::to_value(&parsed).unwrap(), expected);
+        parsed
+    }
+
+    #[test]
+    fn asset_changes_round_trip() {
+        let response: SimulateAssetChangesResponse = round_trip(ASSET_CHANGES);
+
+        let types: Vec<AssetType> = response.changes.iter().map(|change| change.asset_type).collect();
+        assert_eq!(types, [AssetType::Native, AssetType::Erc20, AssetType::Erc721, AssetType::Erc1155]);
+        let usdc = &response.changes[1];
+        assert_eq!(usdc.raw_amount, U256::from(2_500_000_000u64));
+        assert_eq!(usdc.metadata.decimals, Some(6));
+        assert_eq!(response.changes[2].change_type, ChangeType::Approve);
+        assert_eq!(response.changes[2].token_id, Some(U256::from(0x1f3a)));
+        assert_eq!(response.error, None);
+    }
+
+    #[test]
+    fn execution_round_trips_into_call_frames() {
+        let response: SimulateExecutionResponse = round_trip(EXECUTION);
+
+        let root = &response.calls[0];
+        assert_eq!(root.decoded.as_ref().unwrap().method_name, "execute");
+        assert_eq!(root.calls[0].call_type, CallType::Staticcall);
+        assert_eq!(response.logs[0].decoded.as_ref().unwrap().event_name, "Approval");
+
+        let revert = response.first_revert().unwrap();
+        assert_eq!(revert, &root.calls[1]);
+        assert_eq!(revert.revert_reason.as_deref(), Some("TransferAmountExceeds"));
+    }
+
+    #[test]
+    fn raw_amounts_also_accept_hex() {
+        let change: AssetChange = serde_json::from_value(serde_json::json!({
+            "assetType": "ERC20",
+            "changeType": "TRANSFER",
+            "from": "0xd8da6bf26964af9d7eed9e03e53415d37aa96045",
+            "to": "0x3fc91a3afd70395cd496c647d5a6cc9d4b2b7fad",
+            "rawAmount": "0x9502f900",
+        }))
+        .unwrap();
+        assert_eq!(change.raw_amount, U256::from(2_500_000_000u64));
+    }
+}
@@ -0,0 +1,386 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+use ethers_core::types::{Address, Bytes, Log, TransactionReceipt, H256, U256};
+use ethers_core::utils::keccak256;
+
+use crate::trace::{AccountState, PrestateDiff};
+
+/// A value before and after the transaction. `None` means the account or slot did not exist.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Change<T> {
+    pub before: Option<T>,
+    pub after: Option<T>,
+}
+
+/// Everything the transaction changed in one account. Fields are `None` when unchanged.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AccountDiff {
+    pub balance: Option<Change<U256>>,
+    pub nonce: Option<Change<u64>>,
+    pub code: Option<Change<Bytes>>,
+    /// Changed storage slots; a cleared slot has `after` set to zero.
+    pub storage: BTreeMap<H256, Change<H256>>,
+    pub created: bool,
+    pub destroyed: bool,
+}
+
+/// Per-account state changes of a transaction, built from a `prestateTracer` diff-mode trace.
+///
+/// The `Display` implementation renders `report` without extra ERC-20 holders.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct StateDiff {
+    pub accounts: BTreeMap<Address, AccountDiff>,
+}
+
+/// Storage layout of an ERC-20 `balances` mapping.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Erc20BalanceLayout {
+    pub name: &'static str,
+    /// Storage slot of the mapping itself.
+    pub slot: H256,
+    /// Vyper hashes `slot ++ key` where Solidity hashes `key ++ slot`.
+    pub slot_first: bool,
+}
+
+/// An ERC-20 balance change recognized from a changed storage slot.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Erc20BalanceChange {
+    pub token: Address,
+    pub holder: Address,
+    /// Name of the `Erc20BalanceLayout` that matched.
+    pub layout: &'static str,
+    pub before: U256,
+    pub after: U256,
+}
+
+/// Balance mapping layouts of common token implementations, tried in order.
+pub fn well_known_erc20_layouts() -> Vec<Erc20BalanceLayout> {
+    let solidity = |name, slot: u64| Erc20BalanceLayout {
+        name,
+        slot: H256::from_low_u64_be(slot),
+        slot_first: false,
+    };
+    vec![
+        solidity("OpenZeppelin ERC20", 0),
+        solidity("mapping at slot 1", 1),
+        solidity("DAI / USDT", 2),
+        solidity("WETH9", 3),
+        solidity("USDC FiatToken", 9),
+        solidity("OpenZeppelin ERC20Upgradeable", 51),
+        Erc20BalanceLayout {
+            name: "OpenZeppelin ERC20 v5 (ERC-7201)",
+            slot: "0x52c63247e1f47db19d5ce0460030c497f067ca4cebf71ba98eeadabe20bace00"
+                .parse()
+                .expect("valid slot"),
+            slot_first: false,
+        },
+        Erc20BalanceLayout {
+            name: "Vyper ERC20",
+            slot: H256::zero(),
+            slot_first: true,
+        },
+    ]
+}
+
+impl Erc20BalanceLayout {
+    /// Storage slot that holds `holder`'s balance under this layout.
+    pub fn balance_slot(&self, holder: Address) -> H256 {
+        let key = H256::from(holder);
+        let mut preimage = [0u8; 64];
+        let (first, second) = if self.slot_first { (self.slot, key) } else { (key, self.slot) };
+        preimage[..32].copy_from_slice(first.as_bytes());
+        preimage[32..].copy_from_slice(second.as_bytes());
+        H256(keccak256(preimage))
+    }
+}
+
+impl From<&PrestateDiff> for StateDiff {
+    fn from(diff: &PrestateDiff) -> Self {
+        // In diff mode `post` only lists fields that changed, and omits storage slots that
+        // were cleared and accounts that were destroyed.
+        let empty = AccountState::default();
+        let addresses: BTreeSet<&Address> = diff.pre.keys().chain(diff.post.keys()).collect();
+
+        let accounts = addresses
+            .into_iter()
+            .map(|address| {
+                let pre = diff.pre.get(address);
+                let post = diff.post.get(address);
+                let destroyed = pre.is_some() && post.is_none();
+                let before = pre.unwrap_or(&empty);
+                let after = post.unwrap_or(&empty);
+
+                let storage = before
+                    .storage
+                    .keys()
+                    .chain(after.storage.keys())
+                    .collect::<BTreeSet<_>>()
+                    .into_iter()
+                    .filter_map(|slot| {
+                        let old = before.storage.get(slot).copied();
+                        let new = after.storage.get(slot).copied().unwrap_or_default();
+                        (old.unwrap_or_default() != new).then_some((*slot, Change { before: old, after: Some(new) }))
+                    })
+                    .collect();
+
+                let account = AccountDiff {
+                    balance: field_change(before.balance, after.balance, destroyed),
+                    nonce: field_change(before.nonce, after.nonce, destroyed),
+                    code: field_change(before.code.clone(), after.code.clone(), destroyed),
+                    storage,
+                    created: pre.is_none(),
+                    destroyed,
+                };
+                (*address, account)
+            })
+            .collect();
+
+        StateDiff { accounts }
+    }
+}
+
+/// Change of one account field; an absent `after` means unchanged unless the account was
+/// destroyed.
+fn field_change<T: PartialEq>(before: Option<T>, after: Option<T>, destroyed: bool) -> Option<Change<T>> {
+    match (before, after) {
+        (before, Some(after)) if before.as_ref() != Some(&after) => Some(Change {
+            before,
+            after: Some(after),
+        }),
+        (Some(before), None) if destroyed => Some(Change {
+            before: Some(before),
+            after: None,
+        }),
+        _ => None,
+    }
+}
+
+impl StateDiff {
+    /// Recognizes changed storage slots that hold ERC-20 balances.
+    ///
+    /// Every account in the diff is tried as a holder, plus `extra_holders` for holders whose
+    /// own account was not touched (e.g. the recipient of a plain `transfer`).
+    pub fn erc20_balance_changes(&self, extra_holders: &[Address]) -> Vec<Erc20BalanceChange> {
+        let holders: BTreeSet<Address> = self.accounts.keys().chain(extra_holders).copied().collect();
+        let layouts = well_known_erc20_layouts();
+        let mut changes = Vec::new();
+
+        for (token, account) in &self.accounts {
+            if account.storage.is_empty() {
+                continue;
+            }
+            for layout in &layouts {
+                for holder in &holders {
+                    let Some(change) = account.storage.get(&layout.balance_slot(*holder)) else {
+                        continue;
+                    };
+                    changes.push(Erc20BalanceChange {
+                        token: *token,
+                        holder: *holder,
+                        layout: layout.name,
+                        before: slot_value(change.before),
+                        after: slot_value(change.after),
+                    });
+                }
+            }
+        }
+        changes
+    }
+
+    /// Human-readable summary of every changed account, followed by the recognized ERC-20
+    /// balance changes. `extra_holders` is passed to `erc20_balance_changes`; `receipt_holders`
+    /// collects them from a mined transaction.
+    pub fn report(&self, extra_holders: &[Address]) -> String {
+        let mut report = String::new();
+        self.write_report(&mut report, extra_holders)
+            .expect("writing to a String cannot fail");
+        report
+    }
+
+    fn write_report(&self, f: &mut impl fmt::Write, extra_holders: &[Address]) -> fmt::Result {
+        for (address, account) in &self.accounts {
+            let status = match (account.created, account.destroyed) {
+                (true, _) => " (created)",
+                (_, true) => " (destroyed)",
+                _ => "",
+            };
+            writeln!(f, "{address:?}{status}")?;
+            if let Some(balance) = &account.balance {
+                let direction = match (balance.before.unwrap_or_default(), balance.after.unwrap_or_default()) {
+                    (before, after) if after > before => format!("+{}", after - before),
+                    (before, after) => format!("-{}", before - after),
+                };
+                writeln!(
+                    f,
+                    "  balance: {} -> {} ({direction} wei)",
+                    or_none(&balance.before),
+                    or_none(&balance.after)
+                )?;
+            }
+            if let Some(nonce) = &account.nonce {
+                writeln!(f, "  nonce: {} -> {}", or_none(&nonce.before), or_none(&nonce.after))?;
+            }
+            if let Some(code) = &account.code {
+                let size = |code: &Option<Bytes>| code.as_ref().map_or(0, |code| code.len());
+                writeln!(f, "  code: {} bytes -> {} bytes", size(&code.before), size(&code.after))?;
+            }
+            for (slot, change) in &account.storage {
+                writeln!(
+                    f,
+                    "  storage {slot:?}: {:?} -> {:?}",
+                    change.before.unwrap_or_default(),
+                    change.after.unwrap_or_default()
+                )?;
+            }
+        }
+
+        let erc20 = self.erc20_balance_changes(extra_holders);
+        if !erc20.is_empty() {
+            writeln!(f, "ERC-20 balance changes:")?;
+            for change in erc20 {
+                writeln!(
+                    f,
+                    "  token {:?} holder {:?}: {} -> {} ({})",
+                    change.token, change.holder, change.before, change.after, change.layout
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Possible ERC-20 holders of a mined transaction: its sender and recipient, and both sides of
+/// every `Transfer` it logged.
+pub fn receipt_holders(receipt: &TransactionReceipt) -> Vec<Address> {
+    let mut holders: BTreeSet<Address> = std::iter::once(receipt.from).chain(receipt.to).collect();
+    holders.extend(transfer_participants(&receipt.logs));
+    holders.into_iter().collect()
+}
+
+/// Senders and recipients of the `Transfer(address,address,uint256)` events in `logs`.
+pub fn transfer_participants(logs: &[Log]) -> Vec<Address> {
+    let transfer = H256(keccak256("Transfer(address,address,uint256)"));
+    let participants: BTreeSet<Address> = logs
+        .iter()
+        .filter(|log| log.topics.len() >= 3 && log.topics[0] == transfer)
+        .flat_map(|log| [Address::from(log.topics[1]), Address::from(log.topics[2])])
+        .collect();
+    participants.into_iter().collect()
+}
+
+fn slot_value(slot: Option<H256>) -> U256 {
+    slot.map(|value| U256::from_big_endian(value.as_bytes())).unwrap_or_default()
+}
+
+fn or_none<T: fmt::Display>(value: &Option<T>) -> String {
+    value.as_ref().map_or_else(|| "-".to_owned(), ToString::to_string)
+}
+
+impl fmt::Display for StateDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.write_report(f, &[])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TOKEN: Address = Address::repeat_byte(0x70);
+    const SENDER: Address = Address::repeat_byte(0x01);
+    const RECIPIENT: Address = Address::repeat_byte(0x02);
+
+    fn word(value: u64) -> H256 {
+        H256::from_low_u64_be(value)
+    }
+
+    /// A plain `transfer` of 30 tokens: only the token contract's storage changes.
+    fn transfer_diff() -> StateDiff {
+        let layout = well_known_erc20_layouts()[0];
+        let storage = BTreeMap::from([
+            (layout.balance_slot(SENDER), Change { before: Some(word(100)), after: Some(word(70)) }),
+            (layout.balance_slot(RECIPIENT), Change { before: None, after: Some(word(30)) }),
+        ]);
+        StateDiff {
+            accounts: BTreeMap::from([(TOKEN, AccountDiff { storage, ..Default::default() })]),
+        }
+    }
+
+    fn transfer_log(from: Address, to: Address) -> Log {
+        Log {
+            address: TOKEN,
+            topics: vec![H256(keccak256("Transfer(address,address,uint256)")), from.into(), to.into()],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn builds_account_diffs_from_a_prestate_diff() {
+        let diff: PrestateDiff = serde_json::from_value(serde_json::json!({
+            "pre": {
+                "0x0101010101010101010101010101010101010101": {
+                    "balance": "0x10", "nonce": 1, "storage": {(format!("{:?}", word(1))): word(5)}
+                },
+                "0x0303030303030303030303030303030303030303": {"balance": "0x1"}
+            },
+            "post": {
+                "0x0101010101010101010101010101010101010101": {"balance": "0x8", "nonce": 2},
+                "0x0202020202020202020202020202020202020202": {"balance": "0x8"}
+            }
+        }))
+        .unwrap();
+        let state = StateDiff::from(&diff);
+
+        let sender = &state.accounts[&SENDER];
+        assert_eq!(sender.balance, Some(Change { before: Some(16.into()), after: Some(8.into()) }));
+        assert_eq!(sender.nonce, Some(Change { before: Some(1), after: Some(2) }));
+        assert_eq!(sender.storage[&word(1)], Change { before: Some(word(5)), after: Some(H256::zero()) });
+        assert!(state.accounts[&RECIPIENT].created);
+        assert!(state.accounts[&Address::repeat_byte(0x03)].destroyed);
+    }
+
+    #[test]
+    fn untouched_holders_are_only_found_when_passed_in() {
+        let state = transfer_diff();
+        assert!(state.erc20_balance_changes(&[]).is_empty());
+        assert!(!state.to_string().contains("ERC-20"));
+
+        let changes = state.erc20_balance_changes(&[SENDER, RECIPIENT]);
+        assert_eq!(changes.len(), 2);
+        assert_eq!((changes[1].holder, changes[1].before, changes[1].after), (RECIPIENT, 0.into(), 30.into()));
+
+        let report = state.report(&[SENDER, RECIPIENT]);
+        assert!(report.contains(&format!("holder {RECIPIENT:?}: 0 -> 30 (OpenZeppelin ERC20)")));
+    }
+
+    #[test]
+    fn collects_holders_from_a_receipt() {
+        let receipt = TransactionReceipt {
+            from: SENDER,
+            to: Some(TOKEN),
+            logs: vec![
+                transfer_log(SENDER, RECIPIENT),
+                Log { topics: vec![H256::zero(), word(4), word(5)], ..Default::default() },
+            ],
+            ..Default::default()
+        };
+        assert_eq!(receipt_holders(&receipt), vec![SENDER, RECIPIENT, TOKEN]);
+
+        let report = transfer_diff().report(&receipt_holders(&receipt));
+        assert!(report.contains(&format!("holder {SENDER:?}: 100 -> 70")));
+    }
+
+    #[test]
+    fn vyper_layout_hashes_the_slot_first() {
+        let vyper = well_known_erc20_layouts().into_iter().find(|l| l.slot_first).unwrap();
+        let solidity = well_known_erc20_layouts()[0];
+        let mut preimage = [0u8; 64];
+        preimage[44..].copy_from_slice(SENDER.as_bytes());
+        assert_eq!(vyper.balance_slot(SENDER), H256(keccak256(preimage)));
+
+        preimage = [0u8; 64];
+        preimage[12..32].copy_from_slice(SENDER.as_bytes());
+        assert_eq!(solidity.balance_slot(SENDER), H256(keccak256(preimage)));
+    }
+}
@@ -0,0 +1,157 @@
+use ethers_core::types::{Address, Transaction, TxHash};
+use serde::{Deserialize, Serialize};
+
+/// Filter for the `graux_pendingTransactions` subscription.
+///
+/// A transaction matches if its sender is in `from_address` or its recipient is in
+/// `to_address`. Empty lists match nothing on that side; both empty match everything.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PendingTransactionsFilter {
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub from_address: Vec<Address>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub to_address: Vec<Address>,
+    /// Deliver only transaction hashes instead of full transactions.
+    pub hashes_only: bool,
+}
+
+/// One `from`/`to` pair of a `graux_minedTransactions` filter. A missing side matches any
+/// address.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct AddressPair {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub from: Option<Address>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub to: Option<Address>,
+}
+
+/// Filter for the `graux_minedTransactions` subscription.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MinedTransactionsFilter {
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub addresses: Vec<AddressPair>,
+    /// Also deliver transactions that were removed by a reorg.
+    pub include_removed: bool,
+    pub hashes_only: bool,
+}
+
+/// A transaction delivered by a Graux transaction subscription, either in full or, with
+/// `hashes_only`, as its hash.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(untagged)]
+pub enum TransactionNotification {
+    Hash(TxHash),
+    Full(Box<Transaction>),
+    HashOnly { hash: TxHash },
+}
+
+impl TransactionNotification {
+    pub fn hash(&self) -> TxHash {
+        match self {
+            TransactionNotification::Hash(hash) | TransactionNotification::HashOnly { hash } => *hash,
+            TransactionNotification::Full(tx) => tx.hash,
+        }
+    }
+
+    pub fn transaction(&self) -> Option<&Transaction> {
+        match self {
+            TransactionNotification::Full(tx) => Some(tx),
+            _ => None,
+        }
+    }
+}
+
+/// Notification of the `graux_minedTransactions` subscription.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct MinedTransaction {
+    /// Set when the transaction's block was dropped by a reorg.
+    #[serde(default)]
+    pub removed: bool,
+    pub transaction: TransactionNotification,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn pending_filter_serializes_with_graux_field_names() {
+        let filter = PendingTransactionsFilter {
+            from_address: vec![Address::repeat_byte(1)],
+            to_address: vec![Address::repeat_byte(2), Address::repeat_byte(3)],
+            hashes_only: true,
+        };
+        assert_eq!(
+            serde_json::to_value(&filter).unwrap(),
+            json!({
+                "fromAddress": [Address::repeat_byte(1)],
+                "toAddress": [Address::repeat_byte(2), Address::repeat_byte(3)],
+                "hashesOnly": true,
+            })
+        );
+        assert_eq!(
+            serde_json::to_value(PendingTransactionsFilter::default()).unwrap(),
+            json!({ "hashesOnly": false })
+        );
+    }
+
+    #[test]
+    fn mined_filter_serializes_address_pairs() {
+        let filter = MinedTransactionsFilter {
+            addresses: vec![
+                AddressPair {
+                    from: Some(Address::repeat_byte(1)),
+                    to: None,
+                },
+                AddressPair {
+                    from: None,
+                    to: Some(Address::repeat_byte(2)),
+                },
+            ],
+            include_removed: true,
+            hashes_only: false,
+        };
+        assert_eq!(
+            serde_json::to_value(&filter).unwrap(),
+            json!({
+                "addresses": [{ "from": Address::repeat_byte(1) }, { "to": Address::repeat_byte(2) }],
+                "includeRemoved": true,
+                "hashesOnly": false,
+            })
+        );
+    }
+
+    #[test]
+    fn decodes_hash_only_notifications() {
+        let hash = TxHash::repeat_byte(7);
+        let bare: TransactionNotification = serde_json::from_value(json!(hash)).unwrap();
+        assert_eq!(bare, TransactionNotification::Hash(hash));
+
+        let object: TransactionNotification = serde_json::from_value(json!({ "hash": hash })).unwrap();
+        assert_eq!(object, TransactionNotification::HashOnly { hash });
+        assert_eq!(object.hash(), hash);
+        assert!(object.transaction().is_none());
+    }
+
+    #[test]
+    fn decodes_full_notifications() {
+        let tx = Transaction {
+            hash: TxHash::repeat_byte(7),
+            from: Address::repeat_byte(1),
+            to: Some(Address::repeat_byte(2)),
+            ..Default::default()
+        };
+        let mined: MinedTransaction = serde_json::from_value(json!({
+            "removed": true,
+            "transaction": tx,
+        }))
+        .unwrap();
+
+        assert!(mined.removed);
+        assert_eq!(mined.transaction.hash(), tx.hash);
+        assert_eq!(mined.transaction.transaction(), Some(&tx));
+    }
+}
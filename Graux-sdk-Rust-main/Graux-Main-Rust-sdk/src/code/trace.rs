@@ -0,0 +1,466 @@
+use std::collections::BTreeMap;
+
+use ethers_core::types::{Address, Bytes, TxHash, H256, U256};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::abi_registry::{DecodedEvent, DecodedFunction, DecodedRevert};
+use crate::error::{GrauxError, GrauxResult};
+use crate::simulation::{CallType, DecodedCall};
+
+/// Tracer run by the `debug_trace*` methods. Each variant deserializes into the matching
+/// `TraceResult` variant.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Tracer {
+    /// `callTracer`: the tree of calls made by the transaction.
+    Call(CallTracerConfig),
+    /// `prestateTracer`: the accounts touched by the transaction, before execution or, with
+    /// `diff_mode`, before and after.
+    Prestate(PrestateTracerConfig),
+    /// `4byteTracer`: how often each function selector and calldata size was called.
+    FourByte,
+    /// `noopTracer`: returns nothing; useful to measure tracing overhead.
+    Noop,
+    /// Geth's default opcode-level struct logger.
+    StructLogger(StructLoggerConfig),
+    /// A custom JavaScript tracer, given as its source.
+    Js(String),
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CallTracerConfig {
+    /// Trace only the top-level call, skipping subcalls.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub only_top_call: bool,
+    /// Include the logs emitted by each call.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub with_log: bool,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PrestateTracerConfig {
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub diff_mode: bool,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StructLoggerConfig {
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub disable_stack: bool,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub disable_storage: bool,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub enable_memory: bool,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub enable_return_data: bool,
+}
+
+/// Tracing options object sent as the last parameter of `debug_trace*`.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct RawTracer {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tracer: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tracer_config: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    timeout: Option<String>,
+    /// Struct logger options, which geth expects at the top level.
+    #[serde(flatten)]
+    struct_logger: Option<StructLoggerConfig>,
+}
+
+impl Tracer {
+    pub fn call() -> Self {
+        Tracer::Call(CallTracerConfig::default())
+    }
+
+    pub fn prestate_diff() -> Self {
+        Tracer::Prestate(PrestateTracerConfig { diff_mode: true })
+    }
+
+    /// Builds the tracing options object. `timeout` uses Go duration syntax, e.g. `"10s"`.
+    pub(crate) fn options(&self, timeout: Option<String>) -> RawTracer {
+        let (tracer, tracer_config, struct_logger) = match self {
+            Tracer::Call(config) => (Some("callTracer".to_owned()), serde_json::to_value(config).ok(), None),
+            Tracer::Prestate(config) => {
+                (Some("prestateTracer".to_owned()), serde_json::to_value(config).ok(), None)
+            }
+            Tracer::FourByte => (Some("4byteTracer".to_owned()), None, None),
+            Tracer::Noop => (Some("noopTracer".to_owned()), None, None),
+            Tracer::StructLogger(config) => (None, None, Some(config.clone())),
+            Tracer::Js(source) => (Some(source.clone()), None, None),
+        };
+
+        RawTracer {
+            tracer,
+            tracer_config,
+            timeout,
+            struct_logger,
+        }
+    }
+
+    /// Deserializes the raw output of this tracer.
+    pub fn parse(&self, value: Value) -> GrauxResult<TraceResult> {
+        Ok(match self {
+            Tracer::Call(_) => TraceResult::Call(Box::new(serde_json::from_value(value)?)),
+            Tracer::Prestate(config) if config.diff_mode => {
+                TraceResult::Prestate(PrestateTrace::Diff(serde_json::from_value(value)?))
+            }
+            Tracer::Prestate(_) => TraceResult::Prestate(PrestateTrace::Prestate(serde_json::from_value(value)?)),
+            Tracer::FourByte => TraceResult::FourByte(serde_json::from_value(value)?),
+            Tracer::Noop => TraceResult::Noop,
+            Tracer::StructLogger(_) => TraceResult::StructLogs(serde_json::from_value(value)?),
+            Tracer::Js(_) => TraceResult::Js(value),
+        })
+    }
+}
+
+/// Output of a `debug_trace*` call, one variant per `Tracer`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TraceResult {
+    Call(Box<CallFrame>),
+    Prestate(PrestateTrace),
+    /// Call counts keyed by `"<selector>-<calldata size>"`, e.g. `"0xa9059cbb-64"`.
+    FourByte(BTreeMap<String, u64>),
+    Noop,
+    StructLogs(StructLogTrace),
+    /// Whatever the custom tracer's `result` function returned.
+    Js(Value),
+}
+
+impl TraceResult {
+    pub fn into_call(self) -> GrauxResult<CallFrame> {
+        match self {
+            TraceResult::Call(frame) => Ok(*frame),
+            other => Err(unexpected("callTracer", &other)),
+        }
+    }
+
+    pub fn into_prestate(self) -> GrauxResult<PrestateTrace> {
+        match self {
+            TraceResult::Prestate(trace) => Ok(trace),
+            other => Err(unexpected("prestateTracer", &other)),
+        }
+    }
+}
+
+/// Trace of one transaction of a traced block. A transaction the node failed to trace has
+/// `result` set to `GrauxError::TraceFailed` without affecting the rest of the block.
+#[derive(Debug)]
+pub struct TxTraceResult {
+    pub tx_hash: TxHash,
+    pub result: GrauxResult<TraceResult>,
+}
+
+/// One element of a `debug_traceBlockBy*` response. Nodes report a transaction that could not
+/// be traced through `error` instead of failing the whole call.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct BlockTraceEntry {
+    #[serde(default)]
+    tx_hash: Option<TxHash>,
+    #[serde(default)]
+    result: Option<Value>,
+    #[serde(default)]
+    error: Option<String>,
+}
+
+/// Transaction hashes of a block trace, or `None` if the node left any of them out.
+pub(crate) fn block_trace_hashes(entries: &[BlockTraceEntry]) -> Option<Vec<TxHash>> {
+    entries.iter().map(|entry| entry.tx_hash).collect()
+}
+
+/// Pairs the entries of a block trace with the block's transaction hashes, which must be in
+/// the same order, and parses each successful trace with `parse`.
+pub(crate) fn pair_block_traces(
+    hashes: Vec<TxHash>,
+    entries: Vec<BlockTraceEntry>,
+    mut parse: impl FnMut(Value) -> GrauxResult<TraceResult>,
+) -> GrauxResult<Vec<TxTraceResult>> {
+    if hashes.len() != entries.len() {
+        return Err(GrauxError::Decode(format!(
+            "{} traces for a block with {} transactions",
+            entries.len(),
+            hashes.len()
+        )));
+    }
+
+    Ok(hashes
+        .into_iter()
+        .zip(entries)
+        .map(|(tx_hash, entry)| TxTraceResult {
+            tx_hash,
+            result: match (entry.result, entry.error) {
+                (Some(result), _) => parse(result),
+                (None, error) => Err(GrauxError::TraceFailed(error.unwrap_or_default())),
+            },
+        })
+        .collect())
+}
+
+/// Traces of one block of a `trace_block_range` stream.
+#[derive(Debug)]
+pub struct BlockTraces {
+    pub block_number: u64,
+    /// `Err` if the whole block could not be traced.
+    pub traces: GrauxResult<Vec<TxTraceResult>>,
+}
+
+fn unexpected(expected: &str, got: &TraceResult) -> GrauxError {
+    GrauxError::Decode(format!("expected {expected} output, got {got:?}"))
+}
+
+/// One call of a `callTracer` trace, with its subcalls.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CallFrame {
+    #[serde(rename = "type")]
+    pub call_type: CallType,
+    pub from: Address,
+    #[serde(default)]
+    pub to: Option<Address>,
+    #[serde(default)]
+    pub value: Option<U256>,
+    #[serde(default)]
+    pub gas: U256,
+    #[serde(default)]
+    pub gas_used: U256,
+    #[serde(default)]
+    pub input: Bytes,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output: Option<Bytes>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// Decoded `Error(string)` reason, when the node provides one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub revert_reason: Option<String>,
+    /// Decoding done by the Graux API; only set in `graux_simulateExecution` results.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub decoded: Option<DecodedCall>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub calls: Vec<CallFrame>,
+    /// Present with `CallTracerConfig::with_log`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub logs: Vec<CallLog>,
+    /// Input and output decoded with the `AbiRegistry`, if it knows the function.
+    #[serde(skip)]
+    pub decoded_call: Option<DecodedFunction>,
+    /// Set by the `AbiRegistry` for reverted calls.
+    #[serde(skip)]
+    pub decoded_revert: Option<DecodedRevert>,
+    /// Best-effort function signature from the `SignatureDb`, for frames without an ABI.
+    #[serde(skip)]
+    pub label: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CallLog {
+    pub address: Address,
+    #[serde(default)]
+    pub topics: Vec<H256>,
+    #[serde(default)]
+    pub data: Bytes,
+    #[serde(skip)]
+    pub decoded_event: Option<DecodedEvent>,
+    /// Best-effort event signature from the `SignatureDb`.
+    #[serde(skip)]
+    pub label: Option<String>,
+}
+
+/// Output of the `prestateTracer`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PrestateTrace {
+    /// State of every touched account before the transaction.
+    Prestate(BTreeMap<Address, AccountState>),
+    /// With `diff_mode`: only the changed fields, before and after.
+    Diff(PrestateDiff),
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PrestateDiff {
+    #[serde(default)]
+    pub pre: BTreeMap<Address, AccountState>,
+    #[serde(default)]
+    pub post: BTreeMap<Address, AccountState>,
+}
+
+/// Account fields reported by the `prestateTracer`; absent fields were not touched (or, in
+/// diff mode, did not change).
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AccountState {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub balance: Option<U256>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nonce: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub code: Option<Bytes>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub storage: BTreeMap<H256, H256>,
+}
+
+/// Output of the default struct logger.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StructLogTrace {
+    pub gas: u64,
+    pub failed: bool,
+    pub return_value: String,
+    pub struct_logs: Vec<StructLog>,
+}
+
+/// State of the EVM before one opcode.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StructLog {
+    pub pc: u64,
+    pub op: String,
+    pub gas: u64,
+    pub gas_cost: u64,
+    pub depth: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stack: Option<Vec<U256>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub memory: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub storage: Option<BTreeMap<H256, H256>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn options(tracer: Tracer, timeout: Option<&str>) -> Value {
+        serde_json::to_value(tracer.options(timeout.map(str::to_owned))).unwrap()
+    }
+
+    #[test]
+    fn builds_tracer_options() {
+        assert_eq!(options(Tracer::call(), None), json!({"tracer": "callTracer", "tracerConfig": {}}));
+        let with_log = Tracer::Call(CallTracerConfig {
+            only_top_call: false,
+            with_log: true,
+        });
+        assert_eq!(
+            options(with_log, Some("10s")),
+            json!({"tracer": "callTracer", "tracerConfig": {"withLog": true}, "timeout": "10s"})
+        );
+        assert_eq!(
+            options(Tracer::prestate_diff(), None),
+            json!({"tracer": "prestateTracer", "tracerConfig": {"diffMode": true}})
+        );
+        assert_eq!(options(Tracer::FourByte, None), json!({"tracer": "4byteTracer"}));
+        assert_eq!(options(Tracer::Js("{}".to_owned()), None), json!({"tracer": "{}"}));
+    }
+
+    #[test]
+    fn struct_logger_options_sit_at_the_top_level() {
+        let tracer = Tracer::StructLogger(StructLoggerConfig {
+            disable_storage: true,
+            enable_memory: true,
+            ..Default::default()
+        });
+        assert_eq!(options(tracer, None), json!({"disableStorage": true, "enableMemory": true}));
+    }
+
+    #[test]
+    fn parses_each_tracer_into_its_own_result() {
+        let frame = json!({
+            "type": "CALL",
+            "from": "0x00000000000000000000000000000000000000aa",
+            "to": "0x00000000000000000000000000000000000000bb",
+            "gas": "0x5208",
+            "gasUsed": "0x5208",
+            "input": "0x",
+            "calls": [{
+                "type": "STATICCALL",
+                "from": "0x00000000000000000000000000000000000000bb",
+                "to": "0x00000000000000000000000000000000000000cc",
+                "input": "0x70a08231",
+                "error": "execution reverted"
+            }]
+        });
+        let call = Tracer::call().parse(frame).unwrap().into_call().unwrap();
+        assert_eq!(call.gas_used, U256::from(21_000));
+        assert_eq!(call.calls[0].call_type, CallType::Staticcall);
+        assert!(call.calls[0].is_reverted());
+
+        let account = "0x00000000000000000000000000000000000000aa";
+        let prestate = Tracer::Prestate(PrestateTracerConfig::default())
+            .parse(json!({account: {"balance": "0x10", "nonce": 3}}))
+            .unwrap()
+            .into_prestate()
+            .unwrap();
+        let PrestateTrace::Prestate(accounts) = prestate else {
+            panic!("expected a plain prestate, got {prestate:?}");
+        };
+        assert_eq!(accounts.values().next().unwrap().nonce, Some(3));
+
+        let diff = Tracer::prestate_diff()
+            .parse(json!({"pre": {account: {"nonce": 3}}, "post": {account: {"nonce": 4}}}))
+            .unwrap()
+            .into_prestate()
+            .unwrap();
+        assert!(matches!(diff, PrestateTrace::Diff(ref diff) if diff.post.values().next().unwrap().nonce == Some(4)));
+
+        let four_byte = Tracer::FourByte.parse(json!({"0xa9059cbb-64": 2})).unwrap();
+        assert_eq!(four_byte, TraceResult::FourByte(BTreeMap::from([("0xa9059cbb-64".to_owned(), 2)])));
+        assert_eq!(Tracer::Noop.parse(json!({})).unwrap(), TraceResult::Noop);
+    }
+
+    #[test]
+    fn parses_struct_logs() {
+        let trace = Tracer::StructLogger(StructLoggerConfig::default())
+            .parse(json!({
+                "gas": 21000,
+                "failed": false,
+                "returnValue": "",
+                "structLogs": [{"pc": 0, "op": "PUSH1", "gas": 79000, "gasCost": 3, "depth": 1, "stack": []}]
+            }))
+            .unwrap();
+        let TraceResult::StructLogs(trace) = trace else {
+            panic!("expected struct logs, got {trace:?}");
+        };
+        assert_eq!(trace.struct_logs[0].op, "PUSH1");
+        assert_eq!(trace.struct_logs[0].stack, Some(Vec::new()));
+    }
+
+    fn entries(value: Value) -> Vec<BlockTraceEntry> {
+        serde_json::from_value(value).unwrap()
+    }
+
+    #[test]
+    fn block_traces_keep_per_transaction_errors() {
+        let entries = entries(json!([
+            {"txHash": format!("{:?}", TxHash::repeat_byte(1)), "result": {}},
+            {"txHash": format!("{:?}", TxHash::repeat_byte(2)), "error": "execution timeout"},
+        ]));
+        let hashes = block_trace_hashes(&entries).unwrap();
+        let traces = pair_block_traces(hashes, entries, |result| Tracer::Noop.parse(result)).unwrap();
+
+        assert_eq!(traces[0].tx_hash, TxHash::repeat_byte(1));
+        assert!(matches!(traces[0].result, Ok(TraceResult::Noop)));
+        assert_eq!(traces[1].tx_hash, TxHash::repeat_byte(2));
+        assert!(matches!(traces[1].result, Err(GrauxError::TraceFailed(ref e)) if e == "execution timeout"));
+    }
+
+    #[test]
+    fn block_traces_without_hashes_need_the_transaction_list() {
+        let entries = entries(json!([{"result": {}}, {"result": {}}]));
+        assert_eq!(block_trace_hashes(&entries), None);
+
+        let mismatched = pair_block_traces(vec![TxHash::zero()], entries, |result| Tracer::Noop.parse(result));
+        assert!(matches!(mismatched, Err(GrauxError::Decode(_))));
+    }
+
+    #[test]
+    fn asking_for_the_wrong_result_type_fails() {
+        assert!(matches!(TraceResult::Noop.into_call(), Err(GrauxError::Decode(_))));
+    }
+}
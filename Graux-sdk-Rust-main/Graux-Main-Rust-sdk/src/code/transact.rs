@@ -1,26 +1,98 @@
 use ethers::{
     providers::{Middleware, Provider},
-    types::{BlockNumber, DebugTransaction, SimulateAssetChangesResponse, SimulateExecutionResponse, TransactionReceipt, TransactionRequest, TransactionResponse},
+    types::{BlockNumber, DebugTransaction, TransactionReceipt, TransactionRequest, TransactionResponse},
     utils::hexlify,
     Middleware as _,
 };
-use std::convert::TryFrom;
+use std::sync::Arc;
 
+use crate::abi_registry::AbiRegistry;
+use crate::ens::EnsResolver;
+use crate::error::{GrauxError, GrauxResult};
+use crate::private_tx::{PrivateTxHandle, PrivateTxTracking};
+use crate::simulation::{SimulateAssetChangesResponse, SimulateExecutionResponse};
+
+#[derive(Clone)]
 pub struct Graux {
     provider: Provider,
+    abi_registry: Arc<AbiRegistry>,
+    ens: Arc<EnsResolver<Provider>>,
 }
 
 impl Graux {
-    pub fn new(provider: Provider) -> Self {
-        Self { provider }
+    /// `ens` resolves ENS names in the `from` and `to` of simulated transactions; pass the
+    /// resolver of `GrauxProvider::ens`.
+    pub fn new(provider: Provider, ens: Arc<EnsResolver<Provider>>) -> Self {
+        Self {
+            provider,
+            abi_registry: Arc::new(AbiRegistry::new()),
+            ens,
+        }
+    }
+
+    /// Decodes simulation results with `abi_registry`, which can be shared with the other
+    /// namespaces.
+    pub fn with_abi_registry(mut self, abi_registry: Arc<AbiRegistry>) -> Self {
+        self.abi_registry = abi_registry;
+        self
+    }
+
+    pub fn abi_registry(&self) -> &Arc<AbiRegistry> {
+        &self.abi_registry
     }
 
+    /// Sends a private transaction and tracks it until it is included or expires.
     pub async fn send_private_transaction(
         &self,
         signed_transaction: String,
         max_block_number: Option<u64>,
         options: Option<SendPrivateTransactionOptions>,
-    ) -> Result<String, Box<dyn std::error::Error>> {
+    ) -> GrauxResult<PrivateTxHandle> {
+        self.send_private_transaction_with_tracking(
+            signed_transaction,
+            max_block_number,
+            options,
+            PrivateTxTracking::default(),
+        )
+        .await
+    }
+
+    /// Like `send_private_transaction`, with control over polling and what happens when the
+    /// transaction expires (report, cancel or resubmit).
+    pub async fn send_private_transaction_with_tracking(
+        &self,
+        signed_transaction: String,
+        max_block_number: Option<u64>,
+        options: Option<SendPrivateTransactionOptions>,
+        tracking: PrivateTxTracking,
+    ) -> GrauxResult<PrivateTxHandle> {
+        let hash = self
+            .submit_private_transaction(signed_transaction, max_block_number, options.clone())
+            .await?;
+        Ok(PrivateTxHandle::spawn(
+            self.clone(),
+            hash,
+            max_block_number,
+            options,
+            tracking,
+        ))
+    }
+
+    pub(crate) async fn submit_private_transaction(
+        &self,
+        signed_transaction: String,
+        max_block_number: Option<u64>,
+        options: Option<SendPrivateTransactionOptions>,
+    ) -> GrauxResult<String> {
+        if let Some(max_block_number) = max_block_number {
+            let current = self.get_block_number().await?;
+            if max_block_number <= current {
+                return Err(GrauxError::InvalidArgument(format!(
+                    "max_block_number {max_block_number} is not after the current block {current}"
+                )));
+            }
+        }
+
         let hex_block_number = max_block_number.map(hexlify);
         let tx = json!({
             "tx": signed_transaction,
@@ -31,13 +103,17 @@ impl Graux {
             .provider
             .send("eth_sendPrivateTransaction", vec![tx])
             .await?;
-        Ok(response[0].as_str().unwrap().to_owned())
+        Ok(serde_json::from_value(response)?)
+    }
+
+    pub(crate) async fn get_block_number(&self) -> GrauxResult<u64> {
+        Ok(self.provider.get_block_number().await?.as_u64())
     }
 
     pub async fn cancel_private_transaction(
         &self,
         transaction_hash: String,
-    ) -> Result<bool, Box<dyn std::error::Error>> {
+    ) -> GrauxResult<bool> {
         let tx = json!({
             "txHash": transaction_hash,
         });
@@ -45,77 +121,96 @@ impl Graux {
             .provider
             .send("eth_cancelPrivateTransaction", vec![tx])
             .await?;
-        Ok(response[0].as_bool().unwrap())
+        Ok(serde_json::from_value(response)?)
     }
 
     pub async fn simulate_asset_changes_bundle(
         &self,
         transactions: Vec<DebugTransaction>,
         block_identifier: Option<BlockIdentifier>,
-    ) -> Result<Vec<SimulateAssetChangesResponse>, Box<dyn std::error::Error>> {
+    ) -> GrauxResult<Vec<SimulateAssetChangesResponse>> {
+        let transactions = self.resolve_transactions(transactions).await?;
         let params = match block_identifier {
-            Some(block) => vec![transactions, block],
-            None => vec![transactions],
+            Some(block) => vec![json!(transactions), json!(block)],
+            None => vec![json!(transactions)],
         };
         let response = self
             .provider
             .send("graux_simulateAssetChangesBundle", params)
             .await?;
-        Ok(response.into_iter().map(TryFrom::try_from).collect::<Result<_, _>>()?)
+        Ok(serde_json::from_value(response)?)
     }
 
     pub async fn simulate_asset_changes(
         &self,
         transaction: DebugTransaction,
         block_identifier: Option<BlockIdentifier>,
-    ) -> Result<SimulateAssetChangesResponse, Box<dyn std::error::Error>> {
+    ) -> GrauxResult<SimulateAssetChangesResponse> {
+        let transaction = self.ens.resolve_transaction(serde_json::to_value(transaction)?).await?;
         let params = match block_identifier {
-            Some(block) => vec![transaction, block],
+            Some(block) => vec![transaction, json!(block)],
             None => vec![transaction],
         };
         let response = self
             .provider
             .send("graux_simulateAssetChanges", params)
             .await?;
-        Ok(TryFrom::try_from(response)?)
+        Ok(serde_json::from_value(response)?)
     }
 
     pub async fn simulate_execution_bundle(
         &self,
         transactions: Vec<DebugTransaction>,
         block_identifier: Option<BlockIdentifier>,
-    ) -> Result<Vec<SimulateExecutionResponse>, Box<dyn std::error::Error>> {
+    ) -> GrauxResult<Vec<SimulateExecutionResponse>> {
+        let transactions = self.resolve_transactions(transactions).await?;
         let params = match block_identifier {
-            Some(block) => vec![transactions, block],
-            None => vec![transactions],
+            Some(block) => vec![json!(transactions), json!(block)],
+            None => vec![json!(transactions)],
         };
         let response = self
             .provider
             .send("graux_simulateExecutionBundle", params)
             .await?;
-        Ok(response.into_iter().map(TryFrom::try_from).collect::<Result<_, _>>()?)
+        let mut responses: Vec<SimulateExecutionResponse> = serde_json::from_value(response)?;
+        for response in &mut responses {
+            self.abi_registry.annotate_simulation(response);
+        }
+        Ok(responses)
     }
 
     pub async fn simulate_execution(
         &self,
         transaction: DebugTransaction,
         block_identifier: Option<BlockIdentifier>,
-    ) -> Result<SimulateExecutionResponse, Box<dyn std::error::Error>> {
+    ) -> GrauxResult<SimulateExecutionResponse> {
+        let transaction = self.ens.resolve_transaction(serde_json::to_value(transaction)?).await?;
         let params = match block_identifier {
-            Some(block) => vec![transaction, block],
+            Some(block) => vec![transaction, json!(block)],
             None => vec![transaction],
         };
         let response = self
             .provider
             .send("graux_simulateExecution", params)
             .await?;
-        Ok(TryFrom::try_from(response)?)
+        let mut response: SimulateExecutionResponse = serde_json::from_value(response)?;
+        self.abi_registry.annotate_simulation(&mut response);
+        Ok(response)
+    }
+
+    /// Resolves ENS names in the `from` and `to` of each transaction of a bundle.
+    async fn resolve_transactions(&self, transactions: Vec<DebugTransaction>) -> GrauxResult<Vec<serde_json::Value>> {
+        let mut resolved = Vec::with_capacity(transactions.len());
+        for transaction in transactions {
+            resolved.push(self.ens.resolve_transaction(serde_json::to_value(transaction)?).await?);
+        }
+        Ok(resolved)
     }
 
     pub async fn get_private_transaction_receipt(
         &self,
         transaction_hash: String,
-    ) -> Result<Option<TransactionReceipt>, Box<dyn std::error::Error>> {
+    ) -> GrauxResult<Option<TransactionReceipt>> {
         let tx = json!({
             "txHash": transaction_hash,
         });
@@ -123,21 +218,77 @@ impl Graux {
             .provider
             .send("eth_getPrivateTransactionReceipt", vec![tx])
             .await?;
-        match response[0].as_object() {
-            Some(obj) if obj.is_empty() => Ok(None),
-            Some(obj) => Ok(Some(TryFrom::try_from(obj.clone())?)),
-            None => Ok(None),
+        // Until the transaction is included Graux answers with `null` or an empty object.
+        match response {
+            serde_json::Value::Object(obj) if obj.is_empty() => Ok(None),
+            response => Ok(serde_json::from_value(response)?),
         }
     }
 }
 
-#[derive(Debug, Serialize)]
-struct SendPrivateTransactionOptions {
-    gas: Option<u64>,
-    gas_price: Option<u64>,
-    value: Option<u64>,
-    max_priority_fee_per_gas: Option<u64>,
-    max_fee_per_gas: Option<u64>,
+/// The `preferences` object of `eth_sendPrivateTransaction`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SendPrivateTransactionOptions {
+    /// Send to all builders at once instead of only the default ones, trading privacy
+    /// for faster inclusion.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fast: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub privacy: Option<PrivacyPreferences>,
+}
+
+impl SendPrivateTransactionOptions {
+    pub fn fast() -> Self {
+        SendPrivateTransactionOptions {
+            fast: Some(true),
+            privacy: None,
+        }
+    }
+
+    /// Restricts the transaction to the named builders.
+    pub fn with_builders<I, S>(mut self, builders: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.privacy
+            .get_or_insert_with(PrivacyPreferences::default)
+            .builders
+            .extend(builders.into_iter().map(Into::into));
+        self
+    }
+
+    /// Shares `hint` with searchers.
+    pub fn with_hint(mut self, hint: PrivacyHint) -> Self {
+        let privacy = self.privacy.get_or_insert_with(PrivacyPreferences::default);
+        if !privacy.hints.contains(&hint) {
+            privacy.hints.push(hint);
+        }
+        self
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PrivacyPreferences {
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub hints: Vec<PrivacyHint>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub builders: Vec<String>,
+}
+
+/// Transaction data that may be shared with searchers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PrivacyHint {
+    Calldata,
+    ContractAddress,
+    FunctionSelector,
+    Logs,
+    DefaultLogs,
+    Hash,
+    TxHash,
 }
 
 #[derive(Debug, Serialize)]
@@ -145,3 +296,35 @@ struct BlockIdentifier {
     block_hash: Option<String>,
     block_number: Option<String>,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn preferences_serialize_as_camel_case() {
+        let options = SendPrivateTransactionOptions::fast()
+            .with_builders(["flashbots", "titan"])
+            .with_hint(PrivacyHint::ContractAddress)
+            .with_hint(PrivacyHint::TxHash)
+            .with_hint(PrivacyHint::TxHash);
+
+        assert_eq!(
+            serde_json::to_value(&options).unwrap(),
+            json!({
+                "fast": true,
+                "privacy": {
+                    "hints": ["contract_address", "tx_hash"],
+                    "builders": ["flashbots", "titan"],
+                },
+            })
+        );
+    }
+
+    #[test]
+    fn unset_preferences_are_omitted() {
+        let value = serde_json::to_value(SendPrivateTransactionOptions::default()).unwrap();
+        assert_eq!(value, json!({}));
+    }
+}